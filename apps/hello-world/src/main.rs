@@ -0,0 +1,16 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+
+use liblateral::{env, println};
+
+liblateral::entry!(main);
+fn main() -> i32 {
+    let name = env::args().get(1).copied().unwrap_or("world");
+    let greeting = format!("Hello, {}!", name);
+    println!("{}", greeting);
+    0
+}
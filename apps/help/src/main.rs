@@ -0,0 +1,18 @@
+#![no_std]
+#![no_main]
+
+use liblateral::println;
+
+const HELP: &[&str] = &[
+    "Normal Mode: move windows with WASD.",
+    "TAB opens the command bar; type a program's path (e.g. system/help) to run it.",
+    "SPACE focuses a window, capturing all input. ESC leaves Focus Mode.",
+];
+
+liblateral::entry!(main);
+fn main() -> i32 {
+    for line in HELP {
+        println!("{}", line);
+    }
+    0
+}
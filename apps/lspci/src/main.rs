@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+use liblateral::pci::{self, Device};
+use liblateral::println;
+
+/// Names for the common (class, subclass) pairs, or for a whole class with subclass `None`.
+const NAMES: &[(u8, Option<u8>, &str)] = &[
+    (0x01, Some(0x01), "IDE interface"),
+    (0x01, Some(0x06), "SATA controller"),
+    (0x01, Some(0x08), "Non-Volatile memory controller"),
+    (0x01, Some(0x00), "SCSI storage controller"),
+    (0x01, None, "Mass storage controller"),
+    (0x02, Some(0x00), "Ethernet controller"),
+    (0x02, None, "Network controller"),
+    (0x03, Some(0x00), "VGA compatible controller"),
+    (0x03, None, "Display controller"),
+    (0x04, Some(0x03), "Audio device"),
+    (0x04, None, "Multimedia controller"),
+    (0x05, None, "Memory controller"),
+    (0x06, Some(0x00), "Host bridge"),
+    (0x06, Some(0x01), "ISA bridge"),
+    (0x06, Some(0x04), "PCI bridge"),
+    (0x06, Some(0x80), "Bridge"),
+    (0x06, None, "Bridge"),
+    (0x07, None, "Communication controller"),
+    (0x08, None, "System peripheral"),
+    (0x0C, Some(0x03), "USB controller"),
+    (0x0C, Some(0x05), "SMBus"),
+    (0x0C, None, "Serial bus controller"),
+];
+
+fn name(device: &Device) -> &'static str {
+    NAMES
+        .iter()
+        .find(|&&(class, subclass, _)| {
+            class == device.class
+                && !matches!(subclass, Some(subclass) if subclass != device.subclass)
+        })
+        .map_or("Unclassified device", |&(_, _, name)| name)
+}
+
+liblateral::entry!(main);
+fn main() -> i32 {
+    for device in pci::devices() {
+        println!(
+            "{:02x}:{:02x}.{} {} [{:02x}{:02x}]: {:04x}:{:04x} (rev {:02x})",
+            device.bus,
+            device.device,
+            device.function,
+            name(&device),
+            device.class,
+            device.subclass,
+            device.vendor_id,
+            device.device_id,
+            device.revision
+        );
+    }
+    0
+}
@@ -0,0 +1,56 @@
+//! Builds the programs under `apps/` for ring 3, so the kernel and its tests can embed them.
+//!
+//! Each app's path is exported as `LATERAL_APP_<NAME>`, e.g. `LATERAL_APP_HELLO_WORLD` for
+//! `apps/hello-world`, for use with `include_bytes!(env!(...))`.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+const APPS: &[&str] = &["help", "hello-world", "lspci"];
+const SPEC: &str = "x86_64-lateral-user";
+
+fn main() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("apps");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let spec = root.join("spec").join(format!("{}.json", SPEC));
+
+    let mut command = Command::new(cargo);
+    command
+        .current_dir(&root)
+        .args(["build", "--release", "--target"])
+        .arg(&spec)
+        .args([
+            "-Zbuild-std=core,compiler_builtins,alloc",
+            "-Zbuild-std-features=compiler-builtins-mem",
+        ])
+        .arg("--target-dir")
+        .arg(&out);
+    for app in APPS {
+        command.args(["-p", app]);
+    }
+    // Flags meant for the kernel (or clippy, when it's the one building it) aren't meant for the
+    // apps.
+    for var in [
+        "RUSTFLAGS",
+        "CARGO_ENCODED_RUSTFLAGS",
+        "RUSTC_WRAPPER",
+        "RUSTC_WORKSPACE_WRAPPER",
+    ] {
+        command.env_remove(var);
+    }
+
+    let status = command.status().expect("failed to run cargo for the apps");
+    assert!(status.success(), "failed to build the apps");
+
+    for app in APPS {
+        let path = out.join(SPEC).join("release").join(app);
+        let var = app.to_uppercase().replace('-', "_");
+        println!("cargo:rustc-env=LATERAL_APP_{}={}", var, path.display());
+    }
+    println!("cargo:rerun-if-changed=apps");
+    println!("cargo:rerun-if-changed=lateral-abi");
+    println!("cargo:rerun-if-changed=liblateral");
+    println!("cargo:rerun-if-changed={}", spec.display());
+}
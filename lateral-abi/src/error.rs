@@ -0,0 +1,68 @@
+/// Why a syscall failed. Returned to the caller as the negated value in `rax`; the numbers match
+/// Linux's `errno` values.
+#[allow(clippy::upper_case_acronyms)]
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallError {
+    /// The caller isn't allowed to do that.
+    EPERM = 1,
+    /// No such file or program.
+    ENOENT = 2,
+    /// No such thread or process.
+    ESRCH = 3,
+    /// The arguments are too long.
+    E2BIG = 7,
+    /// Not a valid executable.
+    ENOEXEC = 8,
+    /// The process isn't a child of the caller.
+    ECHILD = 10,
+    /// Out of memory, or out of room in the address space.
+    ENOMEM = 12,
+    /// A pointer argument isn't mapped, or not the way the syscall needs it.
+    EFAULT = 14,
+    /// An argument is out of range or malformed.
+    EINVAL = 22,
+    /// There is no syscall with that number.
+    ENOSYS = 38,
+}
+
+impl SyscallError {
+    const ALL: [SyscallError; 10] = [
+        SyscallError::EPERM,
+        SyscallError::ENOENT,
+        SyscallError::ESRCH,
+        SyscallError::E2BIG,
+        SyscallError::ENOEXEC,
+        SyscallError::ECHILD,
+        SyscallError::ENOMEM,
+        SyscallError::EFAULT,
+        SyscallError::EINVAL,
+        SyscallError::ENOSYS,
+    ];
+
+    pub fn from_isize(code: isize) -> Option<Self> {
+        Self::ALL.into_iter().find(|&error| error as isize == code)
+    }
+}
+
+pub type SyscallResult = Result<usize, SyscallError>;
+
+/// Values this close to `usize::MAX` are errors rather than results.
+const MAX_ERROR: usize = 4095;
+
+/// Packs a result into `rax`.
+pub fn encode(result: SyscallResult) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(error) => (-(error as isize)) as usize,
+    }
+}
+
+/// Unpacks what a syscall left in `rax`. Unknown error codes come back as `ENOSYS`.
+pub fn decode(raw: usize) -> SyscallResult {
+    if raw > usize::MAX - MAX_ERROR {
+        Err(SyscallError::from_isize(-(raw as isize)).unwrap_or(SyscallError::ENOSYS))
+    } else {
+        Ok(raw)
+    }
+}
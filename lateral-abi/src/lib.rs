@@ -0,0 +1,152 @@
+//! The interface between Lateral and its programs, shared by the kernel and `liblateral`.
+//!
+//! A syscall's number goes in `rax` and its arguments in `rdi`, `rsi` and `rdx`, and it is made
+//! with `syscall` or, more slowly, `int 0x80`. The result comes back in `rax`: a value on success,
+//! or a negated [`SyscallError`] on failure, which [`decode`] tells apart. Times are whole
+//! nanoseconds and pointers are checked against the caller's address space.
+
+#![no_std]
+
+mod error;
+pub mod raw;
+
+pub use self::error::{decode, encode, SyscallError, SyscallResult};
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallNumber {
+    /// `sleep(nanoseconds)`
+    Sleep = 0,
+    /// `uptime() -> nanoseconds` since boot.
+    Uptime = 1,
+    /// `realtime() -> nanoseconds` since the Unix epoch.
+    Realtime = 2,
+    /// `set_priority(thread, priority)`. `ESRCH` if there is no such thread. From a process,
+    /// `EPERM` unless the thread is in the same process and `priority` is no higher than its
+    /// current one, and never `Realtime`.
+    SetPriority = 3,
+    /// `log(message, len)`: writes a UTF-8 message to the kernel log.
+    Log = 4,
+    /// `exit(code) -> !`: ends the calling process, or thread if it's a kernel thread.
+    Exit = 5,
+    /// `brk(addr) -> addr`: moves the end of the caller's heap to `addr` and returns where it
+    /// ended up. `brk(0)` only asks where it is.
+    Brk = 6,
+    /// `spawn(command, len) -> pid`: starts a built-in program as a child of the caller.
+    /// `command` is the program's path followed by its arguments, each ended by a NUL.
+    Spawn = 7,
+    /// `wait(pid) -> code`: blocks until a child exits, reaps it and returns its exit code.
+    /// `ECHILD` if `pid` isn't a child of the caller.
+    Wait = 8,
+    /// `kill(pid)`: ends a process. `ESRCH` if there is no such process. From a process, `EPERM`
+    /// unless it's the caller or one of its children.
+    Kill = 9,
+    /// `getpid() -> pid`. `ESRCH` for a kernel thread.
+    GetPid = 10,
+    /// `mmap(addr, len, prot) -> addr`: adds `len` bytes of zeroed memory, backed as they're
+    /// touched, at `addr` or wherever there's room if it's 0. `ENOMEM` if `addr..addr + len`
+    /// isn't free.
+    Mmap = 11,
+    /// `munmap(addr, len)`: removes whatever is mapped in `addr..addr + len`.
+    Munmap = 12,
+    /// `mprotect(addr, len, prot)`: changes the protection of mapped memory. `ENOMEM` if part of
+    /// the range isn't mapped.
+    Mprotect = 13,
+    /// `memstats(stats)`: writes how many frames of physical memory there are, how many are used
+    /// and how many are free, as three `u64`s, to `stats`.
+    MemStats = 14,
+    /// `pci_devices(records, count) -> total`: writes a [`PCI_RECORD_SIZE`] byte record for each
+    /// of the first `count` PCI functions to `records` and returns how many there are in all.
+    PciDevices = 15,
+}
+
+impl SyscallNumber {
+    const ALL: [SyscallNumber; 16] = [
+        SyscallNumber::Sleep,
+        SyscallNumber::Uptime,
+        SyscallNumber::Realtime,
+        SyscallNumber::SetPriority,
+        SyscallNumber::Log,
+        SyscallNumber::Exit,
+        SyscallNumber::Brk,
+        SyscallNumber::Spawn,
+        SyscallNumber::Wait,
+        SyscallNumber::Kill,
+        SyscallNumber::GetPid,
+        SyscallNumber::Mmap,
+        SyscallNumber::Munmap,
+        SyscallNumber::Mprotect,
+        SyscallNumber::MemStats,
+        SyscallNumber::PciDevices,
+    ];
+
+    pub fn from_usize(n: usize) -> Option<Self> {
+        Self::ALL.get(n).copied()
+    }
+}
+
+/// Memory may be read. Every mapping must allow this.
+pub const PROT_READ: usize = 1;
+/// Memory may be written. Not together with `PROT_EXEC`.
+pub const PROT_WRITE: usize = 2;
+/// Memory may be executed. Not together with `PROT_WRITE`.
+pub const PROT_EXEC: usize = 4;
+
+/// Bytes `pci_devices` writes per function: its bus, device and function number, header type,
+/// class, subclass, interface and revision as a byte each, then its vendor and device id as
+/// little-endian `u16`s, its interrupt line (`0xFF` for none), and three zero bytes.
+pub const PCI_RECORD_SIZE: usize = 16;
+
+/// The exit code of a thread killed for faulting, and of its process.
+pub const FAULT_EXIT_CODE: i32 = -1;
+/// The exit code of a process ended by `kill`.
+pub const KILLED_EXIT_CODE: i32 = -2;
+
+/// Scheduling classes, highest first, as `set_priority` takes them. A thread only runs while no
+/// thread of a higher class is ready, except for `Normal` threads the scheduler boosts once
+/// they've been passed over for long enough.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+#[repr(usize)]
+pub enum Priority {
+    /// Never aged or preempted by anything else; only round-robins with other realtime threads.
+    Realtime = 0,
+    /// Input handling, the compositor and anything else the user is waiting on.
+    Interactive = 1,
+    Normal = 2,
+    /// Only runs when nothing else is ready.
+    Idle = 3,
+}
+
+impl Priority {
+    pub const LEVELS: usize = 4;
+
+    pub fn from_usize(level: usize) -> Option<Self> {
+        match level {
+            0 => Some(Priority::Realtime),
+            1 => Some(Priority::Interactive),
+            2 => Some(Priority::Normal),
+            3 => Some(Priority::Idle),
+            _ => None,
+        }
+    }
+}
+
+/// Makes a syscall and decodes the result into a [`SyscallResult`]. Programs make it with
+/// `syscall`; kernel threads, for which only the interrupt works, start with `int 0x80;`.
+///
+/// ```ignore
+/// let uptime = unsafe { syscall!(SyscallNumber::Uptime) };
+/// let uptime = unsafe { syscall!(int 0x80; SyscallNumber::Uptime) };
+/// ```
+#[macro_export]
+macro_rules! syscall {
+    (int 0x80; $($args:tt)*) => {
+        $crate::syscall!(@$crate::raw::interrupt; $($args)*)
+    };
+    (@$via:path; $n:expr $(, $arg:expr)* $(,)?) => {
+        $crate::decode($via($n as usize, $crate::raw::args(&[$($arg as usize),*])))
+    };
+    ($($args:tt)*) => {
+        $crate::syscall!(@$crate::raw::syscall; $($args)*)
+    };
+}
@@ -0,0 +1,50 @@
+//! The two ways into the kernel, which [`syscall!`](crate::syscall) picks between.
+//!
+//! Unused arguments are passed as zeros. Either way, only `rax` comes back changed: the kernel
+//! preserves every other register but `rcx` and `r11`, which `syscall` itself overwrites.
+
+use core::arch::asm;
+
+/// Makes syscall `n` with `syscall`. Only for ring 3: the kernel returns with `sysret`.
+///
+/// # Safety
+/// The syscall must be one the caller can make safely with these arguments.
+pub unsafe fn syscall(n: usize, args: [usize; 3]) -> usize {
+    let res: usize;
+    asm!(
+        "syscall",
+        inlateout("rax") n => res,
+        in("rdi") args[0], in("rsi") args[1], in("rdx") args[2],
+        out("rcx") _, out("r11") _
+    );
+    res
+}
+
+/// Makes syscall `n` with `int 0x80`, from ring 3 or from a kernel thread.
+///
+/// # Safety
+/// As for [`syscall`].
+pub unsafe fn interrupt(n: usize, args: [usize; 3]) -> usize {
+    let res: usize;
+    asm!(
+        "int 0x80",
+        inlateout("rax") n => res,
+        in("rdi") args[0], in("rsi") args[1], in("rdx") args[2]
+    );
+    res
+}
+
+/// `given` padded out to three arguments.
+///
+/// # Panics
+/// If there are more than three.
+pub const fn args(given: &[usize]) -> [usize; 3] {
+    assert!(given.len() <= 3, "a syscall takes at most three arguments");
+    let mut args = [0; 3];
+    let mut i = 0;
+    while i < given.len() {
+        args[i] = given[i];
+        i += 1;
+    }
+    args
+}
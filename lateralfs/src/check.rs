@@ -0,0 +1,187 @@
+//! Checking a filesystem against the format, as `fsck` does.
+//!
+//! Every entry is reached from the section roots, and the blocks their extents cover are
+//! compared with the allocation bitmap and the superblock's count of free blocks.
+
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{Disk, Extent, Filesystem, FsError, Kind, Path, Section};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A section's root or an entry can't be read.
+    Unreadable { path: String, error: FsError },
+    /// A directory has more than one entry with this path.
+    Duplicate { path: String },
+    /// The block is used by more than one extent, or by one and the superblock or bitmap.
+    CrossLinked { block: u64 },
+    /// These blocks are in use but the bitmap says they're free.
+    Unmarked { start: u64, blocks: u64 },
+    /// These blocks are marked in use but nothing uses them.
+    Leaked { start: u64, blocks: u64 },
+    /// The bitmap says there are blocks past the end of the disk.
+    PastEnd,
+    /// The superblock's count of free blocks is wrong.
+    FreeCount { recorded: u64, actual: u64 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Unreadable { path, error } => write!(f, "{}: {}", path, error),
+            Problem::Duplicate { path } => write!(f, "{}: more than one entry has this name", path),
+            Problem::CrossLinked { block } => write!(f, "block {} is used more than once", block),
+            Problem::Unmarked { start, blocks } => write!(
+                f,
+                "blocks {}..{} are used but marked free",
+                start,
+                start + blocks
+            ),
+            Problem::Leaked { start, blocks } => write!(
+                f,
+                "blocks {}..{} are marked used but unused",
+                start,
+                start + blocks
+            ),
+            Problem::PastEnd => write!(f, "the bitmap has free blocks past the end"),
+            Problem::FreeCount { recorded, actual } => write!(
+                f,
+                "the superblock counts {} free blocks, but there are {}",
+                recorded, actual
+            ),
+        }
+    }
+}
+
+struct Checker<'a, D: Disk> {
+    fs: &'a Filesystem<D>,
+    /// A bit for every block something was found to use.
+    reached: Vec<u8>,
+    problems: Vec<Problem>,
+}
+
+impl<D: Disk> Checker<'_, D> {
+    fn reached(&self, block: u64) -> bool {
+        self.reached[(block / 8) as usize] & 1 << (block % 8) != 0
+    }
+
+    /// Marks `extent`'s blocks as reached. Returns whether it's usable and none of them already
+    /// were.
+    fn reach(&mut self, path: &str, extent: &Extent) -> bool {
+        if let Err(error) = self.fs.check_extent(extent) {
+            self.problems.push(Problem::Unreadable {
+                path: path.into(),
+                error,
+            });
+            return false;
+        }
+        let mut fresh = true;
+        for block in extent.start..extent.start + extent.blocks {
+            if self.reached(block) {
+                self.problems.push(Problem::CrossLinked { block });
+                fresh = false;
+            }
+            self.reached[(block / 8) as usize] |= 1 << (block % 8);
+        }
+        fresh
+    }
+
+    /// Checks the directory in `extent`, at `path`, and everything in it.
+    fn directory(&mut self, path: &Path, extent: &Extent) {
+        // A directory reached twice is only looked into once, so a loop ends.
+        if !self.reach(&path.to_string(), extent) {
+            return;
+        }
+        let entries = match self.fs.read_entries(extent) {
+            Ok(entries) => entries,
+            Err(error) => {
+                self.problems.push(Problem::Unreadable {
+                    path: path.to_string(),
+                    error,
+                });
+                return;
+            }
+        };
+
+        let mut names = BTreeSet::new();
+        for entry in entries {
+            // `decode` only accepts valid names.
+            let Ok(child) = path.join(&entry.name) else {
+                continue;
+            };
+            if !names.insert(entry.name.clone()) {
+                self.problems.push(Problem::Duplicate {
+                    path: child.to_string(),
+                });
+            }
+            match entry.kind {
+                Kind::File => {
+                    self.reach(&child.to_string(), &entry.extent);
+                }
+                Kind::Directory => self.directory(&child, &entry.extent),
+            }
+        }
+    }
+
+    /// Reports each run of blocks for which `wrong` holds as one problem.
+    fn runs(&mut self, wrong: impl Fn(&Self, u64) -> bool, problem: fn(u64, u64) -> Problem) {
+        let mut start = None;
+        for block in 0..=self.fs.superblock().block_count {
+            let is_wrong = block < self.fs.superblock().block_count && wrong(self, block);
+            match (start, is_wrong) {
+                (None, true) => start = Some(block),
+                (Some(first), false) => {
+                    self.problems.push(problem(first, block - first));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Every problem with `fs`. None means it's consistent.
+pub fn check<D: Disk>(fs: &Filesystem<D>) -> Vec<Problem> {
+    let superblock = *fs.superblock();
+    let mut checker = Checker {
+        fs,
+        reached: vec![0; superblock.block_count.div_ceil(8) as usize],
+        problems: Vec::new(),
+    };
+
+    // The superblock and the bitmap, which `mount` checked are in range.
+    for block in 0..superblock.bitmap_start + superblock.bitmap_blocks {
+        checker.reached[(block / 8) as usize] |= 1 << (block % 8);
+    }
+    for section in Section::ALL {
+        checker.directory(&Path::root(section), &superblock.root(section));
+    }
+
+    checker.runs(
+        |checker, block| checker.reached(block) && !checker.fs.is_used(block),
+        |start, blocks| Problem::Unmarked { start, blocks },
+    );
+    checker.runs(
+        |checker, block| !checker.reached(block) && checker.fs.is_used(block),
+        |start, blocks| Problem::Leaked { start, blocks },
+    );
+
+    let bitmap_bits = superblock.bitmap_blocks * crate::BLOCK_SIZE as u64 * 8;
+    if (superblock.block_count..bitmap_bits).any(|block| !fs.is_used(block)) {
+        checker.problems.push(Problem::PastEnd);
+    }
+    let actual = (0..superblock.block_count)
+        .filter(|&block| !fs.is_used(block))
+        .count() as u64;
+    if actual != superblock.free_blocks {
+        checker.problems.push(Problem::FreeCount {
+            recorded: superblock.free_blocks,
+            actual,
+        });
+    }
+    checker.problems
+}
@@ -0,0 +1,329 @@
+//! Finding entries by path, listing directories, and changing what's in them.
+//!
+//! A directory that outgrows its blocks moves to new ones, which changes its extent in the entry
+//! above it, so a change is written back up the path as far as extents keep changing.
+
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{Disk, Entry, Extent, Filesystem, FsError, Kind, Path, BLOCK_SIZE, ENTRY_SIZE};
+
+/// A directory on the way down a path: where its entries are, and what they are.
+struct Step {
+    extent: Extent,
+    entries: Vec<Entry>,
+}
+
+fn position(entries: &[Entry], name: &str) -> Option<usize> {
+    entries.iter().position(|entry| entry.name == name)
+}
+
+impl<D: Disk> Filesystem<D> {
+    /// The directories from `path`'s section root down to `path`, which all have to be
+    /// directories.
+    fn steps(&self, path: &Path) -> Result<Vec<Step>, FsError> {
+        let mut steps = Vec::with_capacity(path.names().len() + 1);
+        let mut extent = self.root(path.section());
+        for name in path.names() {
+            let entries = self.read_entries(&extent)?;
+            let entry = &entries[position(&entries, name).ok_or(FsError::NotFound)?];
+            if entry.kind != Kind::Directory {
+                return Err(FsError::NotADirectory);
+            }
+            let next = entry.extent;
+            steps.push(Step { extent, entries });
+            extent = next;
+        }
+        let entries = self.read_entries(&extent)?;
+        steps.push(Step { extent, entries });
+        Ok(steps)
+    }
+
+    /// The entries of the directory in `extent`, by name.
+    fn sorted_entries(&self, extent: &Extent) -> Result<Vec<Entry>, FsError> {
+        let mut entries = self.read_entries(extent)?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// The entry at `path`. A section's root is a directory named after the section.
+    pub fn lookup(&self, path: &Path) -> Result<Entry, FsError> {
+        let (Some(name), Some(parent)) = (path.name(), path.parent()) else {
+            return Ok(Entry {
+                kind: Kind::Directory,
+                name: path.section().name().into(),
+                extent: self.root(path.section()),
+            });
+        };
+        let mut steps = self.steps(&parent)?;
+        let mut entries = steps.pop().map(|step| step.entries).unwrap_or_default();
+        let index = position(&entries, name).ok_or(FsError::NotFound)?;
+        Ok(entries.swap_remove(index))
+    }
+
+    /// The entries of the directory at `path`, by name.
+    pub fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        let entry = self.lookup(path)?;
+        if entry.kind != Kind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(ReadDir {
+            entries: self.sorted_entries(&entry.extent)?.into_iter(),
+        })
+    }
+
+    /// Everything under the directory at `path`, each directory before what's in it.
+    pub fn walk(&self, path: &Path) -> Result<Walk<'_, D>, FsError> {
+        let entry = self.lookup(path)?;
+        if entry.kind != Kind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(Walk {
+            fs: self,
+            stack: vec![(
+                path.clone(),
+                self.sorted_entries(&entry.extent)?.into_iter(),
+            )],
+            visited: BTreeSet::from([entry.extent.start]),
+        })
+    }
+
+    /// The contents of the file at `path`.
+    pub fn read_file(&self, path: &Path) -> Result<Vec<u8>, FsError> {
+        let entry = self.lookup(path)?;
+        if entry.kind != Kind::File {
+            return Err(FsError::IsADirectory);
+        }
+        self.read(&entry.extent)
+    }
+
+    /// Replaces the contents of the file at `path` with `data`, moving it to new blocks if it
+    /// doesn't fit in its own.
+    pub fn write_file(&mut self, path: &Path, data: &[u8]) -> Result<(), FsError> {
+        let (Some(name), Some(parent)) = (path.name(), path.parent()) else {
+            return Err(FsError::IsADirectory);
+        };
+        let old = self.lookup(path)?;
+        if old.kind != Kind::File {
+            return Err(FsError::IsADirectory);
+        }
+        self.check_extent(&old.extent)?;
+
+        let moving = data.len() as u64 > old.extent.capacity();
+        let extent = if moving {
+            self.allocate((data.len() as u64).div_ceil(BLOCK_SIZE as u64))?
+        } else {
+            old.extent
+        };
+        let written = self.write(extent, data).and_then(|extent| {
+            self.change_dir(&parent, |entries| {
+                let index = position(entries, name).ok_or(FsError::NotFound)?;
+                entries[index].extent = extent;
+                Ok(())
+            })
+        });
+        match written {
+            Ok(()) if moving => self.free(old.extent),
+            Ok(()) => Ok(()),
+            Err(error) => {
+                if moving {
+                    self.free(extent)?;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Makes an empty file or directory at `path`, in a directory that already exists.
+    pub fn create(&mut self, path: &Path, kind: Kind) -> Result<Entry, FsError> {
+        let (Some(name), Some(parent)) = (path.name(), path.parent()) else {
+            return Err(FsError::AlreadyExists);
+        };
+        let entry = Entry {
+            kind,
+            name: name.into(),
+            extent: Extent::EMPTY,
+        };
+        self.change_dir(&parent, |entries| {
+            if position(entries, name).is_some() {
+                return Err(FsError::AlreadyExists);
+            }
+            entries.push(entry.clone());
+            Ok(())
+        })?;
+        Ok(entry)
+    }
+
+    /// Removes the file or empty directory at `path`, freeing its blocks.
+    pub fn remove(&mut self, path: &Path) -> Result<(), FsError> {
+        let (Some(name), Some(parent)) = (path.name(), path.parent()) else {
+            return Err(FsError::BadPath);
+        };
+        let entry = self.change_dir(&parent, |entries| {
+            let index = position(entries, name).ok_or(FsError::NotFound)?;
+            // A directory's size is that of its entries.
+            if entries[index].kind == Kind::Directory && entries[index].extent.size > 0 {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+            Ok(entries.remove(index))
+        })?;
+        self.free(entry.extent)
+    }
+
+    /// Moves the entry at `from` to `to`, which mustn't exist yet, in any directory or section
+    /// that isn't inside `from`.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<(), FsError> {
+        let (Some(from_name), Some(from_parent), Some(to_name), Some(to_parent)) =
+            (from.name(), from.parent(), to.name(), to.parent())
+        else {
+            return Err(FsError::BadPath);
+        };
+        if from == to {
+            return self.lookup(from).map(|_| ());
+        }
+        if to.starts_with(from) {
+            return Err(FsError::BadPath);
+        }
+
+        if from_parent == to_parent {
+            return self.change_dir(&from_parent, |entries| {
+                if position(entries, to_name).is_some() {
+                    return Err(FsError::AlreadyExists);
+                }
+                let index = position(entries, from_name).ok_or(FsError::NotFound)?;
+                entries[index].name = to_name.into();
+                Ok(())
+            });
+        }
+        // Added before it's removed, so it can't be lost halfway. Adding it can move the
+        // directories above `to`, so removing it walks down to `from` again.
+        let entry = self.lookup(from)?;
+        self.change_dir(&to_parent, |entries| {
+            if position(entries, to_name).is_some() {
+                return Err(FsError::AlreadyExists);
+            }
+            entries.push(Entry {
+                name: to_name.into(),
+                ..entry
+            });
+            Ok(())
+        })?;
+        self.change_dir(&from_parent, |entries| {
+            let index = position(entries, from_name).ok_or(FsError::NotFound)?;
+            entries.remove(index);
+            Ok(())
+        })
+    }
+
+    /// Changes the entries of the directory at `path` with `change`, then writes them back
+    /// along with every directory above it whose entry changed as a result.
+    fn change_dir<T>(
+        &mut self,
+        path: &Path,
+        change: impl FnOnce(&mut Vec<Entry>) -> Result<T, FsError>,
+    ) -> Result<T, FsError> {
+        let mut steps = self.steps(path)?;
+        let mut index = steps.len() - 1;
+        let result = change(&mut steps[index].entries)?;
+
+        // The blocks of directories that moved, freed once nothing points at them.
+        let mut stale = Vec::new();
+        loop {
+            let step = &steps[index];
+            let extent = self.store_entries(step.extent, &step.entries)?;
+            if extent.start != step.extent.start {
+                stale.push(step.extent);
+            }
+            if extent == step.extent {
+                break;
+            }
+            if index == 0 {
+                self.set_root(path.section(), extent)?;
+                break;
+            }
+            index -= 1;
+            let name = &path.names()[index];
+            let entries = &mut steps[index].entries;
+            let position = position(entries, name).ok_or(FsError::NotFound)?;
+            entries[position].extent = extent;
+        }
+        for extent in stale {
+            self.free(extent)?;
+        }
+        Ok(result)
+    }
+
+    /// Writes `entries` over the directory in `extent`, or to new blocks if they don't fit, and
+    /// returns where they are.
+    fn store_entries(&mut self, extent: Extent, entries: &[Entry]) -> Result<Extent, FsError> {
+        let size = (entries.len() * ENTRY_SIZE) as u64;
+        if size <= extent.capacity() {
+            return self.write_entries(extent, entries);
+        }
+        let moved = self.allocate(size.div_ceil(BLOCK_SIZE as u64))?;
+        self.write_entries(moved, entries).inspect_err(|_| {
+            let _ = self.free(moved);
+        })
+    }
+}
+
+/// The entries of a directory, by name, from [`Filesystem::read_dir`].
+pub struct ReadDir {
+    entries: vec::IntoIter<Entry>,
+}
+
+impl Iterator for ReadDir {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        self.entries.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl ExactSizeIterator for ReadDir {}
+
+/// Everything under a directory with its path, depth first and by name, from
+/// [`Filesystem::walk`]. A directory that can't be read is an error in its place, after which
+/// the walk goes on without what's in it.
+pub struct Walk<'a, D: Disk> {
+    fs: &'a Filesystem<D>,
+    /// The directories being walked, innermost last, with the entries still to come.
+    stack: Vec<(Path, vec::IntoIter<Entry>)>,
+    /// The first block of every directory walked into, so a corrupt loop of them ends.
+    visited: BTreeSet<u64>,
+}
+
+impl<D: Disk> Iterator for Walk<'_, D> {
+    type Item = Result<(Path, Entry), FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (directory, entries) = self.stack.last_mut()?;
+            let Some(entry) = entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let path = match directory.join(&entry.name) {
+                Ok(path) => path,
+                Err(error) => return Some(Err(error)),
+            };
+            if entry.kind == Kind::Directory && entry.extent.blocks > 0 {
+                if !self.visited.insert(entry.extent.start) {
+                    return Some(Err(FsError::Corrupt(
+                        "a directory is in more than one place",
+                    )));
+                }
+                match self.fs.sorted_entries(&entry.extent) {
+                    Ok(children) => self.stack.push((path.clone(), children.into_iter())),
+                    Err(error) => return Some(Err(error)),
+                }
+            }
+            return Some(Ok((path, entry)));
+        }
+    }
+}
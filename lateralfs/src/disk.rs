@@ -0,0 +1,61 @@
+use core::fmt;
+
+/// Where a filesystem is stored, a whole block at a time.
+pub trait Disk {
+    /// Number of [`BLOCK_SIZE`](crate::BLOCK_SIZE) byte blocks.
+    fn block_count(&self) -> u64;
+
+    /// Fills `buf`, a whole number of blocks, starting at block `start`.
+    fn read(&self, start: u64, buf: &mut [u8]) -> Result<(), FsError>;
+
+    /// Writes `buf`, a whole number of blocks, starting at block `start`.
+    fn write(&self, start: u64, buf: &[u8]) -> Result<(), FsError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// The disk couldn't be read or written.
+    Io,
+    /// There's no filesystem on the disk, or not this kind.
+    BadMagic,
+    /// The filesystem is of a version this doesn't know.
+    UnsupportedVersion(u32),
+    /// The disk is too small to hold a filesystem, or smaller than the filesystem on it.
+    TooSmall,
+    /// There aren't enough free contiguous blocks.
+    NoSpace,
+    /// Something on the disk contradicts the format, like an extent past the end.
+    Corrupt(&'static str),
+    /// Nothing has this path.
+    NotFound,
+    /// Something already has this path.
+    AlreadyExists,
+    /// The path goes through, or names, a file where it needs a directory.
+    NotADirectory,
+    /// The path names a directory where it needs a file.
+    IsADirectory,
+    /// The directory can't be removed while it has entries.
+    DirectoryNotEmpty,
+    /// The path isn't `section: name/...` with valid names, or can't be used here, like a
+    /// section's root for a rename.
+    BadPath,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsError::Io => write!(f, "I/O error"),
+            FsError::BadMagic => write!(f, "not a Lateral filesystem"),
+            FsError::UnsupportedVersion(version) => write!(f, "unsupported version {}", version),
+            FsError::TooSmall => write!(f, "the disk is too small"),
+            FsError::NoSpace => write!(f, "no space left"),
+            FsError::Corrupt(what) => write!(f, "corrupt filesystem: {}", what),
+            FsError::NotFound => write!(f, "no such file or directory"),
+            FsError::AlreadyExists => write!(f, "already exists"),
+            FsError::NotADirectory => write!(f, "not a directory"),
+            FsError::IsADirectory => write!(f, "is a directory"),
+            FsError::DirectoryNotEmpty => write!(f, "directory not empty"),
+            FsError::BadPath => write!(f, "bad path"),
+        }
+    }
+}
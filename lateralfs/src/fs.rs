@@ -0,0 +1,263 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    Disk, Entry, Extent, FsError, Section, Superblock, BLOCK_SIZE, ENTRY_SIZE, MIN_BLOCKS,
+};
+
+const BITS_PER_BLOCK: u64 = BLOCK_SIZE as u64 * 8;
+
+/// A mounted filesystem: its disk, with the superblock and allocation bitmap kept in memory.
+///
+/// Every change is written through to the disk before the method making it returns.
+pub struct Filesystem<D: Disk> {
+    disk: D,
+    superblock: Superblock,
+    bitmap: Vec<u8>,
+}
+
+impl<D: Disk> Filesystem<D> {
+    /// Writes an empty filesystem over the whole of `disk`: every section's root, with room for
+    /// a block of entries each, and nothing else.
+    pub fn format(disk: D) -> Result<Self, FsError> {
+        let block_count = disk.block_count();
+        if block_count < MIN_BLOCKS {
+            return Err(FsError::TooSmall);
+        }
+        let bitmap_blocks = block_count.div_ceil(BITS_PER_BLOCK);
+        let mut fs = Filesystem {
+            disk,
+            superblock: Superblock {
+                block_count,
+                bitmap_start: 1,
+                bitmap_blocks,
+                free_blocks: block_count,
+                roots: [Extent::EMPTY; 4],
+            },
+            bitmap: vec![0; (bitmap_blocks * BITS_PER_BLOCK / 8) as usize],
+        };
+
+        // The bits past the last block are never free.
+        for block in block_count..bitmap_blocks * BITS_PER_BLOCK {
+            fs.set(block, true);
+        }
+        for block in 0..1 + bitmap_blocks {
+            fs.set(block, true);
+        }
+        fs.superblock.free_blocks -= 1 + bitmap_blocks;
+        for section in Section::ALL {
+            let start = fs.find_free(1).ok_or(FsError::TooSmall)?;
+            fs.set(start, true);
+            fs.superblock.free_blocks -= 1;
+            fs.superblock.roots[section.index()] = Extent {
+                start,
+                blocks: 1,
+                size: 0,
+            };
+            fs.disk.write(start, &[0; BLOCK_SIZE])?;
+        }
+
+        fs.disk.write(fs.superblock.bitmap_start, &fs.bitmap)?;
+        fs.write_superblock()?;
+        Ok(fs)
+    }
+
+    /// Reads the filesystem on `disk`.
+    pub fn mount(disk: D) -> Result<Self, FsError> {
+        let mut block = vec![0; BLOCK_SIZE];
+        disk.read(0, &mut block)?;
+        let superblock = Superblock::decode(&block)?;
+        if superblock.block_count > disk.block_count() {
+            return Err(FsError::TooSmall);
+        }
+        if superblock.bitmap_start == 0
+            || superblock.bitmap_blocks != superblock.block_count.div_ceil(BITS_PER_BLOCK)
+            || !matches!(
+                superblock.bitmap_start.checked_add(superblock.bitmap_blocks),
+                Some(end) if end <= superblock.block_count
+            )
+        {
+            return Err(FsError::Corrupt("the bitmap is in the wrong place"));
+        }
+
+        let mut bitmap = vec![0; (superblock.bitmap_blocks * BITS_PER_BLOCK / 8) as usize];
+        disk.read(superblock.bitmap_start, &mut bitmap)?;
+        Ok(Filesystem {
+            disk,
+            superblock,
+            bitmap,
+        })
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    pub fn disk(&self) -> &D {
+        &self.disk
+    }
+
+    pub fn root(&self, section: Section) -> Extent {
+        self.superblock.root(section)
+    }
+
+    /// Whether block `block` is marked in use. Blocks past the end always are.
+    pub fn is_used(&self, block: u64) -> bool {
+        match self.bitmap.get((block / 8) as usize) {
+            Some(&bits) => bits & 1 << (block % 8) != 0,
+            None => true,
+        }
+    }
+
+    fn set(&mut self, block: u64, used: bool) {
+        let (byte, bit) = ((block / 8) as usize, block % 8);
+        if used {
+            self.bitmap[byte] |= 1 << bit;
+        } else {
+            self.bitmap[byte] &= !(1 << bit);
+        }
+    }
+
+    /// The first of the lowest `blocks` free contiguous blocks.
+    fn find_free(&self, blocks: u64) -> Option<u64> {
+        let mut start = 0;
+        while start + blocks <= self.superblock.block_count {
+            match (start..start + blocks)
+                .rev()
+                .find(|&block| self.is_used(block))
+            {
+                // No run starting at or before the block in use fits.
+                Some(used) => start = used + 1,
+                None => return Some(start),
+            }
+        }
+        None
+    }
+
+    /// Writes the bitmap blocks that hold the bits of `blocks` blocks from `start`.
+    fn write_bitmap(&self, start: u64, blocks: u64) -> Result<(), FsError> {
+        let first = start / BITS_PER_BLOCK;
+        let last = (start + blocks.max(1) - 1) / BITS_PER_BLOCK;
+        let bytes = &self.bitmap[(first as usize) * BLOCK_SIZE..(last as usize + 1) * BLOCK_SIZE];
+        self.disk.write(self.superblock.bitmap_start + first, bytes)
+    }
+
+    fn write_superblock(&self) -> Result<(), FsError> {
+        let mut block = vec![0; BLOCK_SIZE];
+        self.superblock.encode(&mut block);
+        self.disk.write(0, &block)
+    }
+
+    /// Marks `blocks` free contiguous blocks used and returns them as an empty extent.
+    pub fn allocate(&mut self, blocks: u64) -> Result<Extent, FsError> {
+        if blocks == 0 {
+            return Ok(Extent::EMPTY);
+        }
+        let start = self.find_free(blocks).ok_or(FsError::NoSpace)?;
+        for block in start..start + blocks {
+            self.set(block, true);
+        }
+        self.superblock.free_blocks -= blocks;
+        self.write_bitmap(start, blocks)?;
+        self.write_superblock()?;
+        Ok(Extent {
+            start,
+            blocks,
+            size: 0,
+        })
+    }
+
+    /// Marks the blocks of `extent` free.
+    pub fn free(&mut self, extent: Extent) -> Result<(), FsError> {
+        if extent.blocks == 0 {
+            return Ok(());
+        }
+        self.check_extent(&extent)?;
+        for block in extent.start..extent.start + extent.blocks {
+            if !self.is_used(block) {
+                return Err(FsError::Corrupt("an extent's blocks are already free"));
+            }
+            self.set(block, false);
+        }
+        self.superblock.free_blocks += extent.blocks;
+        self.write_bitmap(extent.start, extent.blocks)?;
+        self.write_superblock()
+    }
+
+    /// Makes `extent` the root of `section`.
+    pub fn set_root(&mut self, section: Section, extent: Extent) -> Result<(), FsError> {
+        self.superblock.roots[section.index()] = extent;
+        self.write_superblock()
+    }
+
+    pub(crate) fn check_extent(&self, extent: &Extent) -> Result<(), FsError> {
+        if extent.size > extent.capacity() {
+            return Err(FsError::Corrupt(
+                "an extent's size is more than its blocks hold",
+            ));
+        }
+        if extent.blocks == 0 {
+            return Ok(());
+        }
+        match extent.end() {
+            Some(end) if extent.start > 0 && end <= self.superblock.block_count => Ok(()),
+            _ => Err(FsError::Corrupt("an extent is past the end of the disk")),
+        }
+    }
+
+    /// The used bytes of `extent`.
+    pub fn read(&self, extent: &Extent) -> Result<Vec<u8>, FsError> {
+        self.check_extent(extent)?;
+        let size = extent.size as usize;
+        let mut data = vec![0; size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE];
+        if !data.is_empty() {
+            self.disk.read(extent.start, &mut data)?;
+        }
+        data.truncate(size);
+        Ok(data)
+    }
+
+    /// Overwrites `extent`'s blocks with `data`, which must fit, and returns the extent with its
+    /// size set to that of `data`.
+    pub fn write(&self, extent: Extent, data: &[u8]) -> Result<Extent, FsError> {
+        self.check_extent(&extent)?;
+        if data.len() as u64 > extent.capacity() {
+            return Err(FsError::NoSpace);
+        }
+        let blocks = data.len().div_ceil(BLOCK_SIZE);
+        if blocks > 0 {
+            let mut padded = vec![0; blocks * BLOCK_SIZE];
+            padded[..data.len()].copy_from_slice(data);
+            self.disk.write(extent.start, &padded)?;
+        }
+        Ok(Extent {
+            size: data.len() as u64,
+            ..extent
+        })
+    }
+
+    /// The entries of the directory in `extent`.
+    pub fn read_entries(&self, extent: &Extent) -> Result<Vec<Entry>, FsError> {
+        let data = self.read(extent)?;
+        if data.len() % ENTRY_SIZE != 0 {
+            return Err(FsError::Corrupt(
+                "a directory ends halfway through an entry",
+            ));
+        }
+        data.chunks(ENTRY_SIZE).map(Entry::decode).collect()
+    }
+
+    /// Replaces the entries of the directory in `extent`, which must have room for them, and
+    /// returns the extent with its new size.
+    pub fn write_entries(&self, extent: Extent, entries: &[Entry]) -> Result<Extent, FsError> {
+        let mut data = vec![0; entries.len() * ENTRY_SIZE];
+        for (entry, bytes) in entries.iter().zip(data.chunks_mut(ENTRY_SIZE)) {
+            entry.encode(bytes);
+        }
+        self.write(extent, &data)
+    }
+
+    pub fn into_disk(self) -> D {
+        self.disk
+    }
+}
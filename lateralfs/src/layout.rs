@@ -0,0 +1,203 @@
+//! The structures on the disk and their encoding, as the crate documentation lays out.
+
+use alloc::string::String;
+
+use crate::{FsError, BLOCK_SIZE, ENTRY_SIZE, MAGIC, NAME_MAX, VERSION};
+
+/// One of the four parts of the filesystem, each with its own root directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Section {
+    /// Executable binaries and scripts.
+    Apps,
+    /// Configuration, or anything else serialized.
+    Configuration,
+    /// Application logs.
+    Logs,
+    /// Everything else.
+    Misc,
+}
+
+impl Section {
+    /// In the order the superblock lists their roots.
+    pub const ALL: [Section; 4] = [
+        Section::Apps,
+        Section::Configuration,
+        Section::Logs,
+        Section::Misc,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Apps => "apps",
+            Section::Configuration => "configuration",
+            Section::Logs => "logs",
+            Section::Misc => "misc",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|section| section.name() == name)
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Where some data is: `blocks` contiguous blocks from `start`, the first `size` bytes of which
+/// are used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Extent {
+    pub start: u64,
+    pub blocks: u64,
+    pub size: u64,
+}
+
+impl Extent {
+    pub const EMPTY: Extent = Extent {
+        start: 0,
+        blocks: 0,
+        size: 0,
+    };
+    const ENCODED_SIZE: usize = 24;
+
+    /// Room for this many bytes.
+    pub fn capacity(&self) -> u64 {
+        self.blocks.saturating_mul(BLOCK_SIZE as u64)
+    }
+
+    /// The block after the last one, if that doesn't overflow.
+    pub fn end(&self) -> Option<u64> {
+        self.start.checked_add(self.blocks)
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        Extent {
+            start: u64_at(bytes, 0),
+            blocks: u64_at(bytes, 8),
+            size: u64_at(bytes, 16),
+        }
+    }
+
+    fn encode(&self, bytes: &mut [u8]) {
+        bytes[0..8].copy_from_slice(&self.start.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.blocks.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.size.to_le_bytes());
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File = 1,
+    Directory = 2,
+}
+
+/// An index entry: a named file or directory in a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub kind: Kind,
+    pub name: String,
+    pub extent: Extent,
+}
+
+impl Entry {
+    /// Whether `name` may name an entry.
+    pub fn valid_name(name: &str) -> bool {
+        (1..=NAME_MAX).contains(&name.len()) && !name.contains(['/', ':', '\0'])
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FsError> {
+        let kind = match bytes[0] {
+            1 => Kind::File,
+            2 => Kind::Directory,
+            _ => return Err(FsError::Corrupt("an index entry has an unknown kind")),
+        };
+        let len = bytes[1] as usize;
+        let name = core::str::from_utf8(bytes.get(32..32 + len).unwrap_or(&[]))
+            .ok()
+            .filter(|name| Entry::valid_name(name))
+            .ok_or(FsError::Corrupt("an index entry has a bad name"))?;
+        Ok(Entry {
+            kind,
+            name: name.into(),
+            extent: Extent::decode(&bytes[8..32]),
+        })
+    }
+
+    /// Encodes the entry into `bytes`, [`ENTRY_SIZE`] of them. The name must be valid.
+    pub fn encode(&self, bytes: &mut [u8]) {
+        assert!(Entry::valid_name(&self.name), "bad name {:?}", self.name);
+        bytes[..ENTRY_SIZE].fill(0);
+        bytes[0] = self.kind as u8;
+        bytes[1] = self.name.len() as u8;
+        self.extent.encode(&mut bytes[8..32]);
+        bytes[32..32 + self.name.len()].copy_from_slice(self.name.as_bytes());
+    }
+}
+
+/// Block 0, which says where everything else is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Superblock {
+    pub block_count: u64,
+    pub bitmap_start: u64,
+    pub bitmap_blocks: u64,
+    pub free_blocks: u64,
+    /// By [`Section::index`].
+    pub roots: [Extent; 4],
+}
+
+impl Superblock {
+    pub fn root(&self, section: Section) -> Extent {
+        self.roots[section.index()]
+    }
+
+    pub fn decode(block: &[u8]) -> Result<Self, FsError> {
+        if block[0..8] != MAGIC {
+            return Err(FsError::BadMagic);
+        }
+        let version = u32_at(block, 8);
+        if version != VERSION {
+            return Err(FsError::UnsupportedVersion(version));
+        }
+        if u32_at(block, 12) as usize != BLOCK_SIZE {
+            return Err(FsError::Corrupt("the block size isn't 4096"));
+        }
+        let mut roots = [Extent::EMPTY; 4];
+        for (index, root) in roots.iter_mut().enumerate() {
+            let offset = 0x30 + index * Extent::ENCODED_SIZE;
+            *root = Extent::decode(&block[offset..offset + Extent::ENCODED_SIZE]);
+        }
+        Ok(Superblock {
+            block_count: u64_at(block, 0x10),
+            bitmap_start: u64_at(block, 0x18),
+            bitmap_blocks: u64_at(block, 0x20),
+            free_blocks: u64_at(block, 0x28),
+            roots,
+        })
+    }
+
+    /// Encodes the superblock into `block`, a whole block.
+    pub fn encode(&self, block: &mut [u8]) {
+        block[..BLOCK_SIZE].fill(0);
+        block[0..8].copy_from_slice(&MAGIC);
+        block[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        block[12..16].copy_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        block[0x10..0x18].copy_from_slice(&self.block_count.to_le_bytes());
+        block[0x18..0x20].copy_from_slice(&self.bitmap_start.to_le_bytes());
+        block[0x20..0x28].copy_from_slice(&self.bitmap_blocks.to_le_bytes());
+        block[0x28..0x30].copy_from_slice(&self.free_blocks.to_le_bytes());
+        for (index, root) in self.roots.iter().enumerate() {
+            let offset = 0x30 + index * Extent::ENCODED_SIZE;
+            root.encode(&mut block[offset..offset + Extent::ENCODED_SIZE]);
+        }
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,76 @@
+//! The on-disk format of Lateral's filesystem, shared by the kernel and the host tools.
+//!
+//! Files are kept apart by what they are, in four sections (see the README): `apps`,
+//! `configuration`, `logs` and `misc`. Each section has its own root directory, which holds a
+//! directory for each user, which holds their files.
+//!
+//! # Layout
+//!
+//! The disk is a sequence of [`BLOCK_SIZE`] byte blocks. Numbers are little-endian, and every
+//! position on the disk is a 64 bit block number.
+//!
+//! Block 0 is the superblock:
+//!
+//! | Offset | Size | Field |
+//! | ------ | ---- | ----- |
+//! | 0x00 | 8 | magic, `LATERLFS` |
+//! | 0x08 | 4 | format version, [`VERSION`] |
+//! | 0x0C | 4 | block size, [`BLOCK_SIZE`] |
+//! | 0x10 | 8 | blocks on the disk |
+//! | 0x18 | 8 | first block of the allocation bitmap |
+//! | 0x20 | 8 | blocks in the allocation bitmap |
+//! | 0x28 | 8 | free blocks |
+//! | 0x30 | 4 × 24 | the root directory of `apps`, `configuration`, `logs` and `misc`, as extents |
+//!
+//! The rest of the block is zero.
+//!
+//! The allocation bitmap has a bit for every block, set if the block is in use: bit `n % 8` of
+//! byte `n / 8` is block `n`'s. The superblock and the bitmap itself are in use, and so are the
+//! bits past the last block, so they're never handed out.
+//!
+//! An extent is where some data is: its first block (8 bytes), how many blocks it has (8 bytes)
+//! and how many bytes of those are used (8 bytes). Data always takes whole, contiguous blocks;
+//! empty data has no blocks and starts at block 0.
+//!
+//! A directory's data is an array of [`ENTRY_SIZE`] byte index entries, in no particular order:
+//!
+//! | Offset | Size | Field |
+//! | ------ | ---- | ----- |
+//! | 0x00 | 1 | kind: 1 for a file, 2 for a directory |
+//! | 0x01 | 1 | length of the name |
+//! | 0x02 | 6 | zero |
+//! | 0x08 | 24 | the entry's data, as an extent |
+//! | 0x20 | 96 | the name, UTF-8, padded with zeros |
+//!
+//! A name is 1 to [`NAME_MAX`] bytes without `/`, `:` or NUL, and unique in its directory.
+//! A directory's size is always that of its entries, so an empty one has a size of zero.
+//!
+//! # Paths
+//!
+//! A [`Path`] names an entry by its section and the names of the directories down to it, as
+//! `apps: carter/hello-world`.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod check;
+mod dir;
+mod disk;
+mod fs;
+mod layout;
+mod path;
+
+pub use self::dir::{ReadDir, Walk};
+pub use self::disk::{Disk, FsError};
+pub use self::fs::Filesystem;
+pub use self::layout::{Entry, Extent, Kind, Section, Superblock};
+pub use self::path::Path;
+
+pub const MAGIC: [u8; 8] = *b"LATERLFS";
+pub const VERSION: u32 = 1;
+pub const BLOCK_SIZE: usize = 4096;
+pub const ENTRY_SIZE: usize = 128;
+pub const NAME_MAX: usize = 96;
+/// The smallest disk worth formatting: the superblock, the bitmap, the roots and some room.
+pub const MIN_BLOCKS: u64 = 16;
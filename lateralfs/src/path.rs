@@ -0,0 +1,101 @@
+//! Paths, as the README writes them: `apps: carter/hello-world` is `hello-world` in `carter`'s
+//! directory in the `apps` section, and `apps:` is the section's root.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::{Entry, FsError, Section};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Path {
+    section: Section,
+    /// Each a valid entry name.
+    names: Vec<String>,
+}
+
+impl Path {
+    /// The root directory of `section`.
+    pub fn root(section: Section) -> Self {
+        Path {
+            section,
+            names: Vec::new(),
+        }
+    }
+
+    /// Parses `section:`, then optional spaces and `/` separated names. `FsError::BadPath` if
+    /// the section is unknown or a name is empty or invalid.
+    pub fn parse(path: &str) -> Result<Self, FsError> {
+        let (section, rest) = path.split_once(':').ok_or(FsError::BadPath)?;
+        let mut path = Path::root(Section::from_name(section).ok_or(FsError::BadPath)?);
+        let rest = rest.trim_start_matches(' ');
+        if !rest.is_empty() {
+            for name in rest.split('/') {
+                path = path.join(name)?;
+            }
+        }
+        Ok(path)
+    }
+
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    /// The names from the root down, empty for the root.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The last name, `None` for the root.
+    pub fn name(&self) -> Option<&str> {
+        self.names.last().map(String::as_str)
+    }
+
+    /// The directory holding this, `None` for the root.
+    pub fn parent(&self) -> Option<Path> {
+        let (_, names) = self.names.split_last()?;
+        Some(Path {
+            section: self.section,
+            names: names.to_vec(),
+        })
+    }
+
+    /// `name` in this directory. `FsError::BadPath` if it isn't a valid entry name.
+    pub fn join(&self, name: &str) -> Result<Path, FsError> {
+        if !Entry::valid_name(name) {
+            return Err(FsError::BadPath);
+        }
+        let mut path = self.clone();
+        path.names.push(name.into());
+        Ok(path)
+    }
+
+    /// Whether this is `other` or somewhere inside it.
+    pub fn starts_with(&self, other: &Path) -> bool {
+        self.section == other.section && self.names.starts_with(&other.names)
+    }
+}
+
+impl FromStr for Path {
+    type Err = FsError;
+
+    fn from_str(path: &str) -> Result<Self, FsError> {
+        Path::parse(path)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.section.name())?;
+        for (index, name) in self.names.iter().enumerate() {
+            let separator = if index == 0 { " " } else { "/" };
+            write!(f, "{}{}", separator, name)?;
+        }
+        Ok(())
+    }
+}
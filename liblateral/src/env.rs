@@ -0,0 +1,47 @@
+//! The arguments and environment the program was started with.
+
+use alloc::vec::Vec;
+use core::ffi::{c_char, CStr};
+
+use spin::Once;
+
+static ARGS: Once<Vec<&'static str>> = Once::new();
+static VARS: Once<Vec<&'static str>> = Once::new();
+
+/// The program's arguments, starting with its name.
+pub fn args() -> &'static [&'static str] {
+    ARGS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Every `KEY=value` pair of the environment, split at the first `=`.
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    VARS.get()
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .map(|var| var.split_once('=').unwrap_or((var, "")))
+}
+
+/// The value of the environment variable `key`.
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find(|&(k, _)| k == key).map(|(_, value)| value)
+}
+
+/// Collects `argv` and `envp` from the stack `_start` was entered with.
+///
+/// # Safety
+/// `argv` and `envp` must be null terminated arrays of null terminated strings that live for the
+/// rest of the program.
+pub(crate) unsafe fn init(argv: *const *const c_char, envp: *const *const c_char) {
+    ARGS.call_once(|| strings(argv));
+    VARS.call_once(|| strings(envp));
+}
+
+unsafe fn strings(mut list: *const *const c_char) -> Vec<&'static str> {
+    let mut strings = Vec::new();
+    while !(*list).is_null() {
+        // Strings that aren't UTF-8 come out empty rather than shifting the rest.
+        strings.push(CStr::from_ptr(*list).to_str().unwrap_or(""));
+        list = list.add(1);
+    }
+    strings
+}
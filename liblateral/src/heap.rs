@@ -0,0 +1,67 @@
+//! The global allocator, on top of the program break.
+//!
+//! The heap starts empty. When an allocation doesn't fit, the break is moved up by at least
+//! `GROW_SIZE` and the new memory is handed to the allocator.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+use crate::syscall;
+use crate::syscall::{SyscallError, SyscallNumber};
+
+const PAGE_SIZE: usize = 4096;
+/// The least the heap grows by at once.
+const GROW_SIZE: usize = PAGE_SIZE * 16;
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator {
+    heap: Mutex::new(Heap::empty()),
+};
+
+struct Allocator {
+    heap: Mutex<Heap>,
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        // Enough for the allocation at any alignment, with room to spare.
+        let needed = layout.size() + layout.align();
+        let by = needed.max(GROW_SIZE).next_multiple_of(PAGE_SIZE);
+        if grow(&mut heap, by).is_err() {
+            return ptr::null_mut();
+        }
+        heap.allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap
+            .lock()
+            .deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
+
+/// Moves the break to `addr` and returns where it is now. `brk(0)` only asks.
+pub fn brk(addr: usize) -> Result<usize, SyscallError> {
+    unsafe { syscall!(SyscallNumber::Brk, addr) }
+}
+
+/// Extends `heap` by `by` bytes past the current break.
+fn grow(heap: &mut Heap, by: usize) -> Result<(), SyscallError> {
+    if heap.size() == 0 {
+        let start = brk(0)?;
+        brk(start + by)?;
+        unsafe { heap.init(start, by) };
+    } else {
+        brk(heap.top() + by)?;
+        unsafe { heap.extend(by) };
+    }
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use alloc::string::String;
+use core::fmt;
+
+use crate::syscall;
+use crate::syscall::{SyscallError, SyscallNumber};
+
+/// Writes `message` to the kernel log as one line, prefixed with the thread's name.
+pub fn log(message: &str) -> Result<(), SyscallError> {
+    unsafe { syscall!(SyscallNumber::Log, message.as_ptr(), message.len()) }.map(|_| ())
+}
+
+/// Logs a formatted line. See [`log`].
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => ($crate::io::_print(format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let mut line = String::new();
+    let _ = fmt::write(&mut line, args);
+    let _ = log(&line);
+}
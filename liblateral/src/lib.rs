@@ -0,0 +1,35 @@
+//! The runtime for programs running on Lateral in ring 3.
+//!
+//! A program is a `#![no_std]`, `#![no_main]` binary that names its `main` with [`entry!`]. The
+//! runtime provides `_start`, a panic handler and a global allocator, and wraps each syscall in a
+//! safe function; build it for `spec/x86_64-lateral-user.json`.
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! liblateral::entry!(main);
+//! fn main() -> i32 {
+//!     liblateral::println!("Hello, world!");
+//!     0
+//! }
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod env;
+pub mod heap;
+pub mod io;
+pub mod mem;
+pub mod pci;
+pub mod process;
+pub mod syscall;
+pub mod thread;
+pub mod time;
+
+mod rt;
+
+pub use self::syscall::{SyscallError, SyscallResult};
+pub use lateral_abi::syscall;
@@ -0,0 +1,58 @@
+//! Mapping memory into the program's address space.
+
+use lateral_abi::{PROT_EXEC, PROT_READ, PROT_WRITE};
+
+use crate::syscall;
+use crate::syscall::{SyscallError, SyscallNumber};
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// How mapped memory may be accessed. It can always be read; it can't be both written and
+/// executed.
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protection {
+    Read = PROT_READ,
+    ReadWrite = PROT_READ | PROT_WRITE,
+    ReadExecute = PROT_READ | PROT_EXEC,
+}
+
+/// Maps `len` bytes of zeroed memory, at `addr` if given (page aligned) or wherever there's room.
+/// Pages are only backed once touched.
+pub fn mmap(addr: Option<*mut u8>, len: usize, prot: Protection) -> Result<*mut u8, SyscallError> {
+    let addr = addr.map_or(0, |addr| addr as usize);
+    unsafe { syscall!(SyscallNumber::Mmap, addr, len, prot as usize) }.map(|addr| addr as *mut u8)
+}
+
+/// Unmaps the pages of `addr..addr + len`. Whatever was there is gone.
+///
+/// # Safety
+/// Nothing may use the memory anymore.
+pub unsafe fn munmap(addr: *mut u8, len: usize) -> Result<(), SyscallError> {
+    syscall!(SyscallNumber::Munmap, addr, len).map(|_| ())
+}
+
+/// Changes how the pages of `addr..addr + len` may be accessed.
+///
+/// # Safety
+/// Nothing may still access the memory in a way `prot` no longer allows.
+pub unsafe fn mprotect(addr: *mut u8, len: usize, prot: Protection) -> Result<(), SyscallError> {
+    syscall!(SyscallNumber::Mprotect, addr, len, prot as usize).map(|_| ())
+}
+
+/// How many frames of physical memory the system has, in `PAGE_SIZE` units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+}
+
+/// How much physical memory is in use, system wide.
+pub fn stats() -> Stats {
+    let mut counts = [0u64; 3];
+    unsafe { syscall!(SyscallNumber::MemStats, counts.as_mut_ptr()) }
+        .expect("the stack is always writable");
+    let [total, used, free] = counts.map(|count| count as usize);
+    Stats { total, used, free }
+}
@@ -0,0 +1,55 @@
+//! The PCI functions the kernel found.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use lateral_abi::PCI_RECORD_SIZE;
+
+use crate::syscall;
+use crate::syscall::SyscallNumber;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub header_type: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub interface: u8,
+    pub revision: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub interrupt_line: Option<u8>,
+}
+
+impl Device {
+    fn decode(record: &[u8]) -> Self {
+        Device {
+            bus: record[0],
+            device: record[1],
+            function: record[2],
+            header_type: record[3],
+            class: record[4],
+            subclass: record[5],
+            interface: record[6],
+            revision: record[7],
+            vendor_id: u16::from_le_bytes([record[8], record[9]]),
+            device_id: u16::from_le_bytes([record[10], record[11]]),
+            interrupt_line: (record[12] != 0xFF).then_some(record[12]),
+        }
+    }
+}
+
+/// Every PCI function, in address order.
+pub fn devices() -> Vec<Device> {
+    let count = unsafe { syscall!(SyscallNumber::PciDevices, 0, 0) }
+        .expect("asking for no records can't fail");
+    let mut records = vec![0u8; count * PCI_RECORD_SIZE];
+    let count = unsafe { syscall!(SyscallNumber::PciDevices, records.as_mut_ptr(), count) }
+        .expect("the buffer is the heap's")
+        .min(count);
+    records[..count * PCI_RECORD_SIZE]
+        .chunks(PCI_RECORD_SIZE)
+        .map(Device::decode)
+        .collect()
+}
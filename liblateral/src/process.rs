@@ -0,0 +1,45 @@
+use alloc::vec::Vec;
+
+use crate::syscall;
+use crate::syscall::{SyscallError, SyscallNumber};
+
+pub use lateral_abi::{FAULT_EXIT_CODE, KILLED_EXIT_CODE};
+
+/// The exit code of a program that panicked.
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProcessId(pub usize);
+
+/// Ends the program with `code`, which is what its parent's [`wait`] returns.
+pub fn exit(code: i32) -> ! {
+    let _ = unsafe { syscall!(SyscallNumber::Exit, code) };
+    unreachable!("exit returned.");
+}
+
+/// Starts the built-in program at `path` (e.g. `system/help`) as a child of this one.
+pub fn spawn(path: &str, args: &[&str]) -> Result<ProcessId, SyscallError> {
+    let mut command = Vec::new();
+    for word in [path].iter().chain(args) {
+        command.extend_from_slice(word.as_bytes());
+        command.push(0);
+    }
+    unsafe { syscall!(SyscallNumber::Spawn, command.as_ptr(), command.len()) }.map(ProcessId)
+}
+
+/// Blocks until the child `pid` exits and returns its exit code.
+pub fn wait(pid: ProcessId) -> Result<i32, SyscallError> {
+    unsafe { syscall!(SyscallNumber::Wait, pid.0) }.map(|code| code as u32 as i32)
+}
+
+/// Ends the process `pid`, which must be this one or one of its children. It then exits with
+/// [`KILLED_EXIT_CODE`].
+pub fn kill(pid: ProcessId) -> Result<(), SyscallError> {
+    unsafe { syscall!(SyscallNumber::Kill, pid.0) }.map(|_| ())
+}
+
+/// The id of this process.
+pub fn id() -> ProcessId {
+    let id = unsafe { syscall!(SyscallNumber::GetPid) };
+    ProcessId(id.expect("a program always runs in a process"))
+}
@@ -0,0 +1,92 @@
+//! Program entry and panics.
+
+use core::arch::global_asm;
+use core::ffi::c_char;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use crate::{env, io, process};
+
+// The kernel enters with `argc` at the stack pointer, followed by `argv` and `envp`, and the
+// stack 16 byte aligned; `call` leaves it the way a function expects it.
+global_asm!(
+    "
+    .global _start
+    _start:
+        xor rbp, rbp
+        mov rdi, rsp
+        call {start}
+        ud2
+    ",
+    start = sym start,
+);
+
+extern "Rust" {
+    /// Defined by [`entry!`](crate::entry).
+    fn __lateral_main() -> i32;
+}
+
+unsafe extern "C" fn start(stack: *const usize) -> ! {
+    let argc = *stack;
+    let argv = stack.add(1) as *const *const c_char;
+    let envp = argv.add(argc + 1);
+    env::init(argv, envp);
+
+    process::exit(__lateral_main())
+}
+
+/// Names the program's `main`, a `fn() -> i32` whose result becomes the exit code.
+#[macro_export]
+macro_rules! entry {
+    ($path:path) => {
+        #[export_name = "__lateral_main"]
+        fn __lateral_main() -> i32 {
+            let f: fn() -> i32 = $path;
+            f()
+        }
+    };
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // Panicking may be what the allocator did, so format into the stack.
+    let mut message = Buffer::<256>::new();
+    let _ = write!(message, "{}", info);
+    let _ = io::log(message.as_str());
+    process::exit(process::PANIC_EXIT_CODE)
+}
+
+/// A fixed size string that cuts off what doesn't fit.
+struct Buffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Buffer<N> {
+    fn new() -> Self {
+        Buffer {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only whole characters are ever written.
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> Write for Buffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut encoded = [0; 4];
+            let encoded = c.encode_utf8(&mut encoded).as_bytes();
+            if self.len + encoded.len() > N {
+                return Err(fmt::Error);
+            }
+            self.bytes[self.len..self.len + encoded.len()].copy_from_slice(encoded);
+            self.len += encoded.len();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,6 @@
+//! The raw syscall interface, as `lateral_abi` defines it for the kernel and programs alike.
+//!
+//! [`syscall!`](crate::syscall!) makes a syscall with the `syscall` instruction and decodes the
+//! result.
+
+pub use lateral_abi::{decode, SyscallError, SyscallNumber, SyscallResult};
@@ -0,0 +1,13 @@
+use crate::syscall;
+use crate::syscall::{SyscallError, SyscallNumber};
+
+pub use lateral_abi::Priority;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadId(pub usize);
+
+/// Changes the scheduling priority of `thread`. `ESRCH` if there is no such thread, `EPERM` if
+/// it's in another process or `priority` is higher than its current one or `Realtime`.
+pub fn set_priority(thread: ThreadId, priority: Priority) -> Result<(), SyscallError> {
+    unsafe { syscall!(SyscallNumber::SetPriority, thread.0, priority as usize) }.map(|_| ())
+}
@@ -0,0 +1,21 @@
+use core::time::Duration;
+
+use crate::syscall;
+use crate::syscall::SyscallNumber;
+
+/// Blocks the calling thread for at least `duration`.
+pub fn sleep(duration: Duration) {
+    let _ = unsafe { syscall!(SyscallNumber::Sleep, duration.as_nanos() as u64) };
+}
+
+/// Time since boot.
+pub fn uptime() -> Duration {
+    let nanos = unsafe { syscall!(SyscallNumber::Uptime) }.unwrap_or(0);
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Time since the Unix epoch, from the real-time clock.
+pub fn realtime() -> Duration {
+    let nanos = unsafe { syscall!(SyscallNumber::Realtime) }.unwrap_or(0);
+    Duration::from_nanos(nanos as u64)
+}
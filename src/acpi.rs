@@ -0,0 +1,88 @@
+//! Finding the firmware's ACPI tables.
+//!
+//! Only as much as locating a table by its signature needs: the RSDP is searched for where the
+//! BIOS leaves it, and the RSDT or XSDT it points to lists every other table. Everything is read
+//! through the physical memory mapping and never written.
+
+use core::ptr;
+
+use x86_64::PhysAddr;
+
+use crate::mem::paging;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Where the BIOS keeps the segment of the extended BIOS data area.
+const EBDA_POINTER: u64 = 0x40E;
+/// Where the RSDP is when it isn't in the first KiB of the EBDA.
+const BIOS_AREA: (u64, u64) = (0xE0000, 0x100000);
+/// Bytes in the header every table starts with.
+pub const HEADER_SIZE: usize = 36;
+
+/// A table found through the RSDT or XSDT.
+#[derive(Debug, Clone, Copy)]
+pub struct Table {
+    pub address: PhysAddr,
+    /// Including the header.
+    pub length: usize,
+}
+
+impl Table {
+    fn at(address: u64) -> Self {
+        Table {
+            address: PhysAddr::new(address),
+            length: read::<u32>(address + 4) as usize,
+        }
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        read(self.address.as_u64())
+    }
+
+    /// Reads a `T` at `offset` into the table, which must be within it.
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        assert!(offset + core::mem::size_of::<T>() <= self.length);
+        read(self.address.as_u64() + offset as u64)
+    }
+}
+
+fn read<T: Copy>(address: u64) -> T {
+    let virt = paging::physical_memory_offset() + address;
+    unsafe { ptr::read_unaligned(virt.as_ptr()) }
+}
+
+fn checksum(address: u64, len: usize) -> bool {
+    (0..len as u64).fold(0u8, |sum, i| sum.wrapping_add(read(address + i))) == 0
+}
+
+/// The RSDP's physical address, if the firmware left one.
+fn rsdp() -> Option<u64> {
+    let ebda = (read::<u16>(EBDA_POINTER) as u64) << 4;
+    let areas = [(ebda, ebda + 1024), BIOS_AREA];
+    areas
+        .into_iter()
+        .filter(|&(start, _)| start != 0)
+        .flat_map(|(start, end)| (start..end).step_by(16))
+        .find(|&address| read::<[u8; 8]>(address) == *RSDP_SIGNATURE && checksum(address, 20))
+}
+
+/// The table with `signature`, like `*b"MCFG"`, if the firmware has one.
+pub fn find(signature: [u8; 4]) -> Option<Table> {
+    let rsdp = rsdp()?;
+    // Revision 2 and later point to the XSDT, with 64 bit entries, as well.
+    let (root, entry_size) = if read::<u8>(rsdp + 15) >= 2 && read::<u64>(rsdp + 24) != 0 {
+        (Table::at(read::<u64>(rsdp + 24)), 8)
+    } else {
+        (Table::at(read::<u32>(rsdp + 16) as u64), 4)
+    };
+
+    (HEADER_SIZE..root.length.saturating_sub(entry_size - 1))
+        .step_by(entry_size)
+        .map(|offset| match entry_size {
+            8 => root.read::<u64>(offset),
+            _ => root.read::<u32>(offset) as u64,
+        })
+        .map(Table::at)
+        .find(|table| {
+            table.signature() == signature && checksum(table.address.as_u64(), table.length)
+        })
+}
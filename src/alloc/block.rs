@@ -4,6 +4,7 @@ use core::mem;
 use core::ptr;
 use core::ptr::NonNull;
 
+use super::heap;
 use super::lock::Locked;
 
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
@@ -43,6 +44,18 @@ impl FixedSizeBlockAllocator {
     }
 
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // Out of room: map more at the end of the heap and try again. Asking for the alignment
+        // on top of the size leaves room to align the block however the free space ends.
+        let needed = layout.size().saturating_add(layout.align());
+        let grown = heap::grow(self.fallback_allocator.size(), needed);
+        if grown == 0 {
+            return ptr::null_mut();
+        }
+        unsafe { self.fallback_allocator.extend(grown) };
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
@@ -58,7 +71,7 @@ fn list_index(layout: &Layout) -> Option<usize> {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
@@ -76,7 +89,11 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             }
             None => allocator.fallback_alloc(layout),
-        }
+        };
+        // Logging may allocate, so only once the allocator is unlocked.
+        drop(allocator);
+        heap::warn_if_grown();
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -1,8 +1,29 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_alloc::format;
+
 use crate::alloc::global::ALLOCATOR;
+use crate::io::logging::kernel_warning;
+use crate::mem::paging;
 
 const KIB: usize = 0x400;
+const MIB: usize = 0x400 * KIB;
+const PAGE_SIZE: usize = 4096;
 pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// How much of the heap `init_heap` maps up front.
 pub const HEAP_SIZE: usize = 10_000 * KIB;
+/// How far the heap may grow unless [`set_limit`] says otherwise.
+pub const DEFAULT_HEAP_LIMIT: usize = 1024 * MIB;
+/// The heap grows by at least this much at a time, so it doesn't map a page per allocation.
+const GROW_SIZE: usize = MIB;
+/// Shares of the limit, in percent, to warn about once the heap grows past them.
+const WARNINGS: [usize; 3] = [50, 75, 90];
+
+static LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_HEAP_LIMIT);
+/// How much of the heap is mapped.
+static MAPPED: AtomicUsize = AtomicUsize::new(0);
+/// The highest of `WARNINGS` already warned about, or 0.
+static WARNED: AtomicUsize = AtomicUsize::new(0);
 
 use x86_64::{
     structures::paging::{
@@ -33,6 +54,72 @@ pub fn init_heap(
     unsafe {
         ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
     }
+    MAPPED.store(HEAP_SIZE, Ordering::Relaxed);
 
     Ok(())
 }
+
+/// How many bytes of the heap are mapped.
+pub fn size() -> usize {
+    MAPPED.load(Ordering::Relaxed)
+}
+
+/// How far the heap may grow.
+pub fn limit() -> usize {
+    LIMIT.load(Ordering::Relaxed)
+}
+
+/// Lets the heap grow up to `limit` bytes, or however large it already is if that's more. The
+/// heap never shrinks.
+pub fn set_limit(limit: usize) {
+    LIMIT.store(limit.max(size()), Ordering::Relaxed);
+}
+
+/// Maps at least `needed` more bytes onto the end of the heap, which `size` bytes are mapped of,
+/// as long as that stays under the limit. Returns how many bytes were mapped, which may be fewer
+/// than asked for if frames ran out.
+///
+/// Called with the allocator locked, so it must not allocate.
+pub(super) fn grow(size: usize, needed: usize) -> usize {
+    let room = limit().saturating_sub(size);
+    let Some(needed) = needed.checked_next_multiple_of(PAGE_SIZE) else {
+        return 0;
+    };
+    if needed > room {
+        return 0;
+    }
+
+    let by = needed.max(GROW_SIZE).min(room);
+    let start = Page::containing_address(VirtAddr::new((HEAP_START + size) as u64));
+    let mut mapped = 0;
+    for page in Page::range(start, start + (by / PAGE_SIZE) as u64) {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        if paging::map_page(page, flags).is_err() {
+            break;
+        }
+        mapped += PAGE_SIZE;
+    }
+    MAPPED.store(size + mapped, Ordering::Relaxed);
+    mapped
+}
+
+/// Logs a warning if the heap has grown past another of the `WARNINGS` since the last one.
+/// Called once the allocator is unlocked again, as logging may allocate.
+pub(super) fn warn_if_grown() {
+    let (size, limit) = (size(), limit());
+    let Some(&share) = WARNINGS
+        .iter()
+        .rev()
+        .find(|&&share| size > limit / 100 * share)
+    else {
+        return;
+    };
+    if WARNED.fetch_max(share, Ordering::Relaxed) < share {
+        kernel_warning(&format!(
+            "The kernel heap has grown past {}% of its limit: {} of {} MiB mapped",
+            share,
+            size / MIB,
+            limit / MIB
+        ));
+    }
+}
@@ -0,0 +1,388 @@
+//! ATA hard disks on the two legacy IDE channels, driven with PIO.
+//!
+//! Every sector moves through the data port one word at a time. The CPU doesn't spin while the
+//! disk seeks, though: a request blocks its thread until the channel's IRQ (14 for the primary,
+//! 15 for the secondary) says the next sector is ready, and fails with `BlockError::Device` if the
+//! IRQ doesn't come within half a minute. Requests on one channel take turns, as both of its
+//! drives share the registers.
+//!
+//! Drives are found with `IDENTIFY DEVICE`; ATAPI drives (CD-ROMs) and empty positions are
+//! skipped. Drives are addressed with 28 bit LBAs where they reach and 48 bit ones beyond.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use rust_alloc::format;
+use rust_alloc::string::String;
+use rust_alloc::sync::Arc;
+use rust_alloc::vec::Vec;
+use x86_64::instructions::port::Port;
+
+use super::{BlockDevice, BlockError};
+use crate::cpu::interrupt::add_irq_handler;
+use crate::io::logging::kernel_info;
+use crate::thread::sync::{Event, Mutex};
+use crate::time::rtc::time_between_ticks;
+
+pub const SECTOR_SIZE: usize = 512;
+const WORDS_PER_SECTOR: usize = SECTOR_SIZE / 2;
+/// Sectors moved by one command. A sector count of 0 means 256.
+const MAX_SECTORS: usize = 256;
+/// The highest sector a 28 bit LBA reaches, exclusive.
+const LBA28_LIMIT: u64 = 1 << 28;
+/// Status reads to wait for the drive before giving up on it.
+const POLL_LIMIT: usize = 1_000_000;
+/// How long to wait for the drive to interrupt before giving up on it. A flush, the slowest
+/// command, may take up to 30 seconds.
+const TIMEOUT_SECONDS: f64 = 30.0;
+
+const STATUS_ERR: u8 = 0x01;
+const STATUS_DRQ: u8 = 0x08;
+const STATUS_DF: u8 = 0x20;
+const STATUS_BSY: u8 = 0x80;
+
+const COMMAND_READ: u8 = 0x20;
+const COMMAND_READ_EXT: u8 = 0x24;
+const COMMAND_WRITE: u8 = 0x30;
+const COMMAND_WRITE_EXT: u8 = 0x34;
+const COMMAND_FLUSH: u8 = 0xE7;
+const COMMAND_FLUSH_EXT: u8 = 0xEA;
+const COMMAND_IDENTIFY: u8 = 0xEC;
+
+static CHANNELS: [Channel; 2] = [
+    Channel::new(0x1F0, 0x3F6, 14),
+    Channel::new(0x170, 0x376, 15),
+];
+
+/// One IDE channel: the registers its two drives share and its IRQ.
+struct Channel {
+    registers: Registers,
+    /// Held for the whole of a command, as both drives answer through `registers`.
+    lock: Mutex<()>,
+    irq: u8,
+    /// Raised by the IRQ handler.
+    interrupt: Event,
+    /// The status the IRQ handler read, which also acknowledged the interrupt.
+    status: AtomicU8,
+}
+
+impl Channel {
+    const fn new(io: u16, control: u16, irq: u8) -> Self {
+        Channel {
+            registers: Registers { io, control },
+            lock: Mutex::new(()),
+            irq,
+            interrupt: Event::new(),
+            status: AtomicU8::new(0),
+        }
+    }
+
+    fn interrupt(&self) {
+        self.status.store(self.registers.read(7), Ordering::Relaxed);
+        self.interrupt.set();
+    }
+
+    /// Forgets any interrupt so far. Called right before what the next one is awaited for.
+    fn arm(&self) {
+        self.interrupt.reset();
+    }
+
+    /// Blocks until the channel interrupts. Fails if that takes longer than `TIMEOUT_SECONDS`, or
+    /// if the status it had then shows an error.
+    fn wait(&self) -> Result<(), BlockError> {
+        let timeout = (TIMEOUT_SECONDS / time_between_ticks()) as usize;
+        if !self.interrupt.wait_timeout(timeout) {
+            return Err(BlockError::Device);
+        }
+        let status = self.status.load(Ordering::Relaxed);
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Device);
+        }
+        Ok(())
+    }
+}
+
+struct Registers {
+    io: u16,
+    control: u16,
+}
+
+impl Registers {
+    fn data(&self) -> Port<u16> {
+        Port::new(self.io)
+    }
+
+    fn write(&self, offset: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.io + offset).write(value) };
+    }
+
+    fn read(&self, offset: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.io + offset).read() }
+    }
+
+    /// Reads the status without acknowledging an interrupt.
+    fn alternate_status(&self) -> u8 {
+        unsafe { Port::<u8>::new(self.control).read() }
+    }
+
+    /// Selects a drive and gives it the 400ns it needs to put its status on the bus.
+    fn select(&self, value: u8) {
+        self.write(6, value);
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    /// Waits for the drive to stop being busy and returns its status, or `None` if it never
+    /// does or nothing is attached.
+    fn poll(&self) -> Option<u8> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.alternate_status();
+            if status == 0xFF {
+                return None;
+            }
+            if status & STATUS_BSY == 0 {
+                return Some(status);
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+
+    /// Waits until the drive wants data, or fails.
+    fn poll_drq(&self) -> Result<(), BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.poll().ok_or(BlockError::Device)?;
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(BlockError::Device);
+            }
+            if status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(BlockError::Device)
+    }
+}
+
+/// An ATA disk on one of the IDE channels.
+pub struct AtaDrive {
+    channel: &'static Channel,
+    slave: bool,
+    sectors: u64,
+    lba48: bool,
+    model: String,
+}
+
+impl AtaDrive {
+    /// Identifies the drive at a position, if there's an ATA one there.
+    fn identify(channel: &'static Channel, slave: bool) -> Option<Self> {
+        let _lock = channel.lock.lock();
+        let registers = &channel.registers;
+        // Keep the channel's interrupts enabled.
+        unsafe { Port::<u8>::new(registers.control).write(0) };
+        registers.select(0xA0 | (slave as u8) << 4);
+        for offset in 2..=5 {
+            registers.write(offset, 0);
+        }
+        channel.arm();
+        registers.write(7, COMMAND_IDENTIFY);
+        if registers.alternate_status() == 0 {
+            return None;
+        }
+        registers.poll()?;
+        // ATAPI and SATA drives answer with a signature in the LBA registers instead.
+        if registers.read(4) != 0 || registers.read(5) != 0 {
+            return None;
+        }
+        registers.poll_drq().ok()?;
+
+        let mut words = [0u16; WORDS_PER_SECTOR];
+        let mut data = registers.data();
+        for word in words.iter_mut() {
+            *word = unsafe { data.read() };
+        }
+
+        let lba48 = words[83] & 1 << 10 != 0;
+        let sectors = if lba48 {
+            words[100..104]
+                .iter()
+                .rev()
+                .fold(0, |sectors, &word| sectors << 16 | word as u64)
+        } else {
+            (words[61] as u64) << 16 | words[60] as u64
+        };
+        // Two characters a word, the first in the high byte.
+        let model: Vec<u8> = words[27..47]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        let model = String::from_utf8_lossy(&model).trim().into();
+
+        Some(AtaDrive {
+            channel,
+            slave,
+            sectors,
+            lba48,
+            model,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Sends a read or write command for `count` sectors (at most `MAX_SECTORS`) from `lba`,
+    /// with 28-bit addressing where it reaches and 48-bit addressing, if the drive has it, past
+    /// that.
+    fn command(
+        &self,
+        registers: &Registers,
+        lba: u64,
+        count: usize,
+        commands: (u8, u8),
+    ) -> Result<(), BlockError> {
+        let lba28 = lba + count as u64 <= LBA28_LIMIT;
+        if !lba28 && !self.lba48 {
+            return Err(BlockError::OutOfRange);
+        }
+
+        let slave = (self.slave as u8) << 4;
+        let count = (count % MAX_SECTORS) as u8;
+        if lba28 {
+            registers.select(0xE0 | slave | (lba >> 24) as u8 & 0x0F);
+            registers.write(2, count);
+            registers.write(3, lba as u8);
+            registers.write(4, (lba >> 8) as u8);
+            registers.write(5, (lba >> 16) as u8);
+            self.channel.arm();
+            registers.write(7, commands.0);
+        } else {
+            registers.select(0x40 | slave);
+            // The high bytes go first, each through the same register as its low byte.
+            registers.write(2, 0);
+            registers.write(3, (lba >> 24) as u8);
+            registers.write(4, (lba >> 32) as u8);
+            registers.write(5, (lba >> 40) as u8);
+            registers.write(2, count);
+            registers.write(3, lba as u8);
+            registers.write(4, (lba >> 8) as u8);
+            registers.write(5, (lba >> 16) as u8);
+            self.channel.arm();
+            registers.write(7, commands.1);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check(start, buf.len())?;
+        let _lock = self.channel.lock.lock();
+        let registers = &self.channel.registers;
+        let mut data = registers.data();
+
+        for (chunk, lba) in buf
+            .chunks_mut(MAX_SECTORS * SECTOR_SIZE)
+            .zip((start..).step_by(MAX_SECTORS))
+        {
+            let count = chunk.len() / SECTOR_SIZE;
+            self.command(registers, lba, count, (COMMAND_READ, COMMAND_READ_EXT))?;
+            for sector in (0..chunk.len()).step_by(SECTOR_SIZE) {
+                // The drive interrupts once each sector is ready to be read.
+                self.channel.wait()?;
+                self.channel.arm();
+                for i in (sector..sector + SECTOR_SIZE).step_by(2) {
+                    chunk[i..i + 2].copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, start: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check(start, buf.len())?;
+        let _lock = self.channel.lock.lock();
+        let registers = &self.channel.registers;
+        let mut data = registers.data();
+
+        for (chunk, lba) in buf
+            .chunks(MAX_SECTORS * SECTOR_SIZE)
+            .zip((start..).step_by(MAX_SECTORS))
+        {
+            let count = chunk.len() / SECTOR_SIZE;
+            self.command(registers, lba, count, (COMMAND_WRITE, COMMAND_WRITE_EXT))?;
+            // The first sector is asked for without an interrupt, every later one with one.
+            registers.poll_drq()?;
+            for sector in (0..chunk.len()).step_by(SECTOR_SIZE) {
+                self.channel.arm();
+                for i in (sector..sector + SECTOR_SIZE).step_by(2) {
+                    unsafe { data.write(u16::from_le_bytes([chunk[i], chunk[i + 1]])) };
+                }
+                self.channel.wait()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let _lock = self.channel.lock.lock();
+        let registers = &self.channel.registers;
+        registers.select(0xA0 | (self.slave as u8) << 4);
+        self.channel.arm();
+        let command = if self.lba48 {
+            COMMAND_FLUSH_EXT
+        } else {
+            COMMAND_FLUSH
+        };
+        registers.write(7, command);
+        self.channel.wait()
+    }
+}
+
+fn primary_interrupt() {
+    CHANNELS[0].interrupt();
+}
+
+fn secondary_interrupt() {
+    CHANNELS[1].interrupt();
+}
+
+/// Installs the IRQ handlers, identifies the drive at each of the four positions and registers
+/// those found as `ata0` (primary master) to `ata3` (secondary slave). Returns how many there
+/// were.
+///
+/// Needs a runtime on the calling CPU, as it blocks on the channels' locks.
+pub fn init() -> usize {
+    add_irq_handler(CHANNELS[0].irq, primary_interrupt);
+    add_irq_handler(CHANNELS[1].irq, secondary_interrupt);
+
+    let mut found = 0;
+    for (index, (channel, slave)) in CHANNELS
+        .iter()
+        .flat_map(|channel| [(channel, false), (channel, true)])
+        .enumerate()
+    {
+        let Some(drive) = AtaDrive::identify(channel, slave) else {
+            continue;
+        };
+        kernel_info(
+            format!(
+                "ata{}: {} ({} MiB)",
+                index,
+                drive.model(),
+                drive.capacity() / (1024 * 1024)
+            )
+            .as_str(),
+        );
+        super::register(&format!("ata{}", index), Arc::new(drive));
+        found += 1;
+    }
+    found
+}
@@ -0,0 +1,87 @@
+//! Block devices: storage read and written a whole sector at a time.
+//!
+//! Drivers register each device they find under a name (`ata0`, `ata1`, …), and whatever wants
+//! to store something, like a filesystem, looks it up here instead of knowing about drivers.
+
+use rust_alloc::collections::BTreeMap;
+use rust_alloc::string::{String, ToString};
+use rust_alloc::sync::Arc;
+use rust_alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub mod ata;
+pub mod virtio;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The buffer isn't a whole number of sectors.
+    BadLength,
+    /// The sectors run past the end of the device.
+    OutOfRange,
+    /// The device reported an error, or stopped responding.
+    Device,
+}
+
+/// A device that stores fixed size sectors, numbered from 0.
+///
+/// Every method takes `&self` so a device can be shared; drivers serialize requests themselves.
+pub trait BlockDevice: Send + Sync {
+    /// Bytes per sector.
+    fn sector_size(&self) -> usize;
+
+    /// Number of sectors.
+    fn sector_count(&self) -> u64;
+
+    /// Fills `buf`, a whole number of sectors, starting at sector `start`.
+    fn read(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes `buf`, a whole number of sectors, starting at sector `start`.
+    fn write(&self, start: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Makes sure everything written so far is stored, not just cached by the device.
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// Size in bytes.
+    fn capacity(&self) -> u64 {
+        self.sector_count() * self.sector_size() as u64
+    }
+
+    /// Checks that `len` bytes from sector `start` are whole sectors on the device. Returns how
+    /// many sectors that is.
+    fn check(&self, start: u64, len: usize) -> Result<u64, BlockError> {
+        let count = len / self.sector_size();
+        if count * self.sector_size() != len {
+            return Err(BlockError::BadLength);
+        }
+        let count = count as u64;
+        match start.checked_add(count) {
+            Some(end) if end <= self.sector_count() => Ok(count),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+}
+
+static DEVICES: Mutex<BTreeMap<String, Arc<dyn BlockDevice>>> = Mutex::new(BTreeMap::new());
+
+/// Makes `device` available as `name`, replacing whatever had that name.
+pub fn register(name: &str, device: Arc<dyn BlockDevice>) {
+    without_interrupts(|| DEVICES.lock().insert(name.to_string(), device));
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    without_interrupts(|| DEVICES.lock().get(name).cloned())
+}
+
+/// Every registered device with its name, by name.
+pub fn devices() -> Vec<(String, Arc<dyn BlockDevice>)> {
+    without_interrupts(|| {
+        DEVICES
+            .lock()
+            .iter()
+            .map(|(name, device)| (name.clone(), device.clone()))
+            .collect()
+    })
+}
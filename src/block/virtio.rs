@@ -0,0 +1,488 @@
+//! Virtio block devices on the PCI bus, like the ones QEMU adds for `-drive if=virtio`.
+//!
+//! The driver speaks the legacy interface through the I/O ports of BAR 0, which QEMU's
+//! transitional devices offer next to the modern one. Requests go through the device's one split
+//! virtqueue, each as a chain of descriptors: a header saying what to do, the data, and a status
+//! byte the device answers with. Data isn't copied; the descriptors point straight at the
+//! caller's buffer, a physically contiguous piece at a time.
+//!
+//! A transfer is split into requests of at most `MAX_REQUEST` bytes, and as many of them as the
+//! queue has room for are handed to the device together, with a single notification. The device
+//! interrupts once it has put finished requests in the used ring; until then the thread blocks.
+
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use rust_alloc::format;
+use rust_alloc::sync::Arc;
+use rust_alloc::vec::Vec;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+
+use super::{BlockDevice, BlockError};
+use crate::cpu::interrupt::add_irq_handler;
+use crate::io::logging::{kernel_info, kernel_warning};
+use crate::mem::frame::FRAME_ALLOCATOR;
+use crate::mem::paging;
+use crate::pci::{self, Bar, Match};
+use crate::thread::sync::{Event, Mutex};
+
+const VENDOR: u16 = 0x1AF4;
+/// The transitional block device, which still has the legacy interface.
+const DEVICE: u16 = 0x1001;
+
+const DEVICE_FEATURES: u16 = 0x00;
+const GUEST_FEATURES: u16 = 0x04;
+const QUEUE_ADDRESS: u16 = 0x08;
+const QUEUE_SIZE: u16 = 0x0C;
+const QUEUE_SELECT: u16 = 0x0E;
+const QUEUE_NOTIFY: u16 = 0x10;
+const DEVICE_STATUS: u16 = 0x12;
+const ISR_STATUS: u16 = 0x13;
+/// The block device's own configuration starts here, with its size in sectors.
+const CAPACITY: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 0x80;
+
+const FEATURE_FLUSH: u32 = 1 << 9;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+const REQUEST_OK: u8 = 0;
+
+const DESCRIPTOR_NEXT: u16 = 1;
+const DESCRIPTOR_WRITE: u16 = 2;
+
+/// Virtio always counts in 512 byte sectors, whatever the device's block size.
+pub const SECTOR_SIZE: usize = 512;
+const PAGE_SIZE: usize = 4096;
+/// Bytes moved by one request.
+const MAX_REQUEST: usize = 64 * 1024;
+/// Pieces one request's data can be in: one a page, plus one for a buffer that isn't aligned.
+const MAX_SEGMENTS: usize = MAX_REQUEST / PAGE_SIZE + 1;
+
+/// Every device found, for the interrupt handler to ask which of them interrupted.
+static DEVICES: spin::Mutex<Vec<Arc<VirtioBlk>>> = spin::Mutex::new(Vec::new());
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct Header {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+#[repr(C)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A split virtqueue in the layout the legacy interface wants, followed by the header and status
+/// of every request that can be in it.
+struct Queue {
+    size: u16,
+    /// Where the queue is, virtually through the physical memory mapping and physically.
+    virt: VirtAddr,
+    phys: u64,
+    /// Descriptors not in any chain.
+    free: Vec<u16>,
+    /// How many entries were added to the available ring, published or not.
+    available: u16,
+    /// How many entries of the used ring were taken back.
+    used: u16,
+}
+
+// The pointers are into memory only this queue uses, behind the device's lock.
+unsafe impl Send for Queue {}
+
+impl Queue {
+    fn available_offset(size: usize) -> usize {
+        size * size_of::<Descriptor>()
+    }
+
+    /// The used ring starts on a new page.
+    fn used_offset(size: usize) -> usize {
+        (Self::available_offset(size) + 6 + 2 * size).next_multiple_of(PAGE_SIZE)
+    }
+
+    fn headers_offset(size: usize) -> usize {
+        (Self::used_offset(size) + 6 + 8 * size).next_multiple_of(PAGE_SIZE)
+    }
+
+    fn statuses_offset(size: usize) -> usize {
+        Self::headers_offset(size) + size * size_of::<Header>()
+    }
+
+    /// Allocates and clears the memory for a queue of `size` entries.
+    fn new(size: u16) -> Option<Self> {
+        let bytes = Self::statuses_offset(size as usize) + size as usize;
+        let frames = without_interrupts(|| {
+            FRAME_ALLOCATOR
+                .lock()
+                .as_mut()
+                .expect("frame allocator is not initialized")
+                .allocate_contiguous(bytes.div_ceil(PAGE_SIZE))
+        })?;
+        let phys = frames.start.start_address().as_u64();
+        let virt = paging::physical_memory_offset() + phys;
+        unsafe { ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, bytes) };
+
+        Some(Queue {
+            size,
+            virt,
+            phys,
+            free: (0..size).collect(),
+            available: 0,
+            used: 0,
+        })
+    }
+
+    fn at<T>(&self, offset: usize) -> *mut T {
+        (self.virt + offset).as_mut_ptr()
+    }
+
+    fn descriptor(&self, index: u16) -> *mut Descriptor {
+        self.at(index as usize * size_of::<Descriptor>())
+    }
+
+    fn status(&self, head: u16) -> *mut u8 {
+        self.at(Self::statuses_offset(self.size as usize) + head as usize)
+    }
+
+    /// Chains a request together: its header, `data` and its status byte. The device writes
+    /// into `data` if `incoming`. Returns `false` if there aren't enough free descriptors.
+    ///
+    /// The request isn't seen by the device until it's [published](Self::publish).
+    fn push(&mut self, kind: u32, sector: u64, data: &[(u64, u32)], incoming: bool) -> bool {
+        if self.free.len() < data.len() + 2 {
+            return false;
+        }
+        let size = self.size as usize;
+        let head = self.free.pop().unwrap();
+        let header_offset = Self::headers_offset(size) + head as usize * size_of::<Header>();
+        unsafe {
+            self.at::<Header>(header_offset).write_volatile(Header {
+                kind,
+                reserved: 0,
+                sector,
+            });
+            self.status(head).write_volatile(0xFF);
+        }
+
+        let status = self.phys + (Self::statuses_offset(size) + head as usize) as u64;
+        let data_flags = if incoming { DESCRIPTOR_WRITE } else { 0 };
+        let parts = core::iter::once((self.phys + header_offset as u64, 16, 0))
+            .chain(data.iter().map(|&(addr, len)| (addr, len, data_flags)))
+            .chain(core::iter::once((status, 1, DESCRIPTOR_WRITE)));
+
+        let mut previous: Option<u16> = None;
+        for (addr, len, flags) in parts {
+            let index = match previous {
+                None => head,
+                Some(previous) => {
+                    let index = self.free.pop().unwrap();
+                    unsafe {
+                        let descriptor = self.descriptor(previous);
+                        (*descriptor).flags |= DESCRIPTOR_NEXT;
+                        (*descriptor).next = index;
+                    }
+                    index
+                }
+            };
+            unsafe {
+                self.descriptor(index).write_volatile(Descriptor {
+                    addr,
+                    len,
+                    flags,
+                    next: 0,
+                })
+            };
+            previous = Some(index);
+        }
+
+        let available = self.at::<u16>(Self::available_offset(size));
+        let slot = (self.available % self.size) as usize;
+        unsafe { available.add(2 + slot).write_volatile(head) };
+        self.available = self.available.wrapping_add(1);
+        true
+    }
+
+    /// Lets the device see every request pushed so far.
+    fn publish(&self) {
+        // The descriptors and ring entries must be there before the index says they are.
+        fence(Ordering::SeqCst);
+        let available = self.at::<u16>(Self::available_offset(self.size as usize));
+        unsafe { available.add(1).write_volatile(self.available) };
+        fence(Ordering::SeqCst);
+    }
+
+    /// Takes back the next request the device has finished, and returns its status.
+    fn pop_used(&mut self) -> Option<u8> {
+        let size = self.size as usize;
+        let used = self.at::<u16>(Self::used_offset(size));
+        if unsafe { used.add(1).read_volatile() } == self.used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let slot = (self.used % self.size) as usize;
+        let element = unsafe {
+            self.at::<UsedElement>(Self::used_offset(size) + 4 + slot * size_of::<UsedElement>())
+                .read_volatile()
+        };
+        self.used = self.used.wrapping_add(1);
+
+        let head = element.id as u16;
+        let status = unsafe { self.status(head).read_volatile() };
+        let mut index = head;
+        loop {
+            self.free.push(index);
+            let descriptor = unsafe { self.descriptor(index).read_volatile() };
+            if descriptor.flags & DESCRIPTOR_NEXT == 0 {
+                break;
+            }
+            index = descriptor.next;
+        }
+        Some(status)
+    }
+}
+
+/// Splits the `len` bytes at `addr` into physically contiguous pieces, as (address, length).
+fn segments(addr: VirtAddr, len: usize) -> Result<Vec<(u64, u32)>, BlockError> {
+    let offset = paging::physical_memory_offset();
+    let mut segments: Vec<(u64, u32)> = Vec::with_capacity(MAX_SEGMENTS);
+    let mut done = 0;
+    while done < len {
+        let virt = addr + done;
+        let phys = paging::translate_addr(virt, offset)
+            .ok_or(BlockError::Device)?
+            .as_u64();
+        let piece = (PAGE_SIZE - u16::from(virt.page_offset()) as usize).min(len - done);
+        match segments.last_mut() {
+            Some((start, length)) if *start + *length as u64 == phys => *length += piece as u32,
+            _ => segments.push((phys, piece as u32)),
+        }
+        done += piece;
+    }
+    Ok(segments)
+}
+
+/// A virtio block device.
+pub struct VirtioBlk {
+    io: u16,
+    queue: Mutex<Queue>,
+    /// Raised by the interrupt handler when the device has used requests.
+    completed: Event,
+    sectors: u64,
+    flush: bool,
+}
+
+impl VirtioBlk {
+    fn port<T>(&self, offset: u16) -> Port<T> {
+        Port::new(self.io + offset)
+    }
+
+    /// Resets and sets up the device behind `device`, if it's usable.
+    fn new(device: &pci::Device) -> Option<Self> {
+        let Some(Bar::Io { port: io, .. }) = device.bar(0) else {
+            return None;
+        };
+        device.enable();
+
+        let mut status = Port::<u8>::new(io + DEVICE_STATUS);
+        let mut write_status = |value: u8| unsafe { status.write(value) };
+        write_status(0);
+        write_status(STATUS_ACKNOWLEDGE);
+        write_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        unsafe {
+            let features = Port::<u32>::new(io + DEVICE_FEATURES).read();
+            Port::new(io + GUEST_FEATURES).write(features & FEATURE_FLUSH);
+            Port::<u16>::new(io + QUEUE_SELECT).write(0);
+        }
+        let size = unsafe { Port::<u16>::new(io + QUEUE_SIZE).read() };
+        // Every request needs room for a whole chain.
+        let queue = (size as usize >= MAX_SEGMENTS + 2)
+            .then(|| Queue::new(size))
+            .flatten();
+        let Some(queue) = queue else {
+            write_status(STATUS_FAILED);
+            return None;
+        };
+
+        let (features, sectors) = unsafe {
+            Port::<u32>::new(io + QUEUE_ADDRESS).write((queue.phys / PAGE_SIZE as u64) as u32);
+            let low = Port::<u32>::new(io + CAPACITY).read() as u64;
+            let high = Port::<u32>::new(io + CAPACITY + 4).read() as u64;
+            (
+                Port::<u32>::new(io + GUEST_FEATURES).read(),
+                high << 32 | low,
+            )
+        };
+        write_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+
+        Some(VirtioBlk {
+            io,
+            queue: Mutex::new(queue),
+            completed: Event::new(),
+            sectors,
+            flush: features & FEATURE_FLUSH != 0,
+        })
+    }
+
+    fn interrupt(&self) {
+        // Reading the status acknowledges the interrupt. Bit 0 says the used ring changed.
+        if unsafe { self.port::<u8>(ISR_STATUS).read() } & 1 != 0 {
+            self.completed.set();
+        }
+    }
+
+    /// Hands the pushed requests, `pending` of them, to the device and waits until all of them
+    /// are done.
+    fn complete(&self, queue: &mut Queue, pending: usize) -> Result<(), BlockError> {
+        self.completed.reset();
+        queue.publish();
+        unsafe { self.port::<u16>(QUEUE_NOTIFY).write(0) };
+
+        let mut result = Ok(());
+        let mut left = pending;
+        while left > 0 {
+            while let Some(status) = queue.pop_used() {
+                if status != REQUEST_OK {
+                    result = Err(BlockError::Device);
+                }
+                left -= 1;
+            }
+            if left > 0 {
+                self.completed.wait();
+            }
+        }
+        result
+    }
+
+    /// Moves `len` bytes between `addr` and the device from sector `start`, in as few batches
+    /// as the queue allows.
+    fn transfer(
+        &self,
+        kind: u32,
+        start: u64,
+        addr: VirtAddr,
+        len: usize,
+    ) -> Result<(), BlockError> {
+        let mut queue = self.queue.lock();
+        let mut offsets = (0..len).step_by(MAX_REQUEST).peekable();
+        while offsets.peek().is_some() {
+            let mut pending = 0;
+            let mut result = Ok(());
+            while let Some(&offset) = offsets.peek() {
+                let data = match segments(addr + offset, (len - offset).min(MAX_REQUEST)) {
+                    Ok(data) => data,
+                    Err(error) => {
+                        result = Err(error);
+                        break;
+                    }
+                };
+                let sector = start + (offset / SECTOR_SIZE) as u64;
+                if !queue.push(kind, sector, &data, kind == REQUEST_IN) {
+                    break;
+                }
+                offsets.next();
+                pending += 1;
+            }
+            // Whatever was handed over has to finish before the buffer can go back.
+            if pending > 0 {
+                self.complete(&mut queue, pending)?;
+            }
+            result?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check(start, buf.len())?;
+        let addr = VirtAddr::from_ptr(buf.as_mut_ptr());
+        self.transfer(REQUEST_IN, start, addr, buf.len())
+    }
+
+    fn write(&self, start: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check(start, buf.len())?;
+        self.transfer(
+            REQUEST_OUT,
+            start,
+            VirtAddr::from_ptr(buf.as_ptr()),
+            buf.len(),
+        )
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        if !self.flush {
+            return Ok(());
+        }
+        let mut queue = self.queue.lock();
+        queue.push(REQUEST_FLUSH, 0, &[], false);
+        self.complete(&mut queue, 1)
+    }
+}
+
+fn interrupt() {
+    // Devices can share a line, so every one of them is asked.
+    for device in DEVICES.lock().iter() {
+        device.interrupt();
+    }
+}
+
+/// Sets up every virtio block device on the PCI bus and registers them as `virtio0`, `virtio1`,
+/// and so on. Returns how many there were.
+pub fn init() -> usize {
+    let mut found = 0;
+    for device in pci::claim(Match::id(VENDOR, DEVICE), "virtio-blk") {
+        let Some(line) = device.interrupt_line() else {
+            kernel_warning(&format!("virtio: {} has no interrupt line", device.address));
+            pci::release(device.address);
+            continue;
+        };
+        let Some(blk) = VirtioBlk::new(&device) else {
+            kernel_warning(&format!("virtio: couldn't set up {}", device.address));
+            pci::release(device.address);
+            continue;
+        };
+        let blk = Arc::new(blk);
+        without_interrupts(|| DEVICES.lock().push(blk.clone()));
+        add_irq_handler(line, interrupt);
+
+        let name = format!("virtio{}", found);
+        kernel_info(&format!(
+            "{}: {} ({} MiB)",
+            name,
+            device.address,
+            blk.capacity() / (1024 * 1024)
+        ));
+        super::register(&name, blk);
+        found += 1;
+    }
+    found
+}
@@ -0,0 +1,160 @@
+//! The local APIC: inter-processor interrupts and the per-CPU timer.
+//!
+//! The legacy PICs still deliver every device IRQ to the BSP; the local APIC is only used to
+//! start the other CPUs, to give each of them a timer to preempt on and to have them flush their
+//! TLBs.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::percpu;
+use crate::mem::paging::map_physical;
+use crate::time::rtc::ticks;
+
+/// Where the local APIC registers are mapped. Every CPU sees its own APIC at the same address.
+const LAPIC_BASE: u64 = 0x_4444_0000_0000;
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_ENABLE: u64 = 1 << 11;
+
+const ID: u64 = 0x20;
+const EOI: u64 = 0xB0;
+const SPURIOUS: u64 = 0xF0;
+const ICR_LOW: u64 = 0x300;
+const ICR_HIGH: u64 = 0x310;
+const LVT_TIMER: u64 = 0x320;
+const TIMER_INITIAL: u64 = 0x380;
+const TIMER_CURRENT: u64 = 0x390;
+const TIMER_DIVIDE: u64 = 0x3E0;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_MASKED: u32 = 1 << 16;
+const DIVIDE_BY_16: u32 = 0b0011;
+const DELIVERY_PENDING: u32 = 1 << 12;
+
+/// Interrupt vector of the local APIC timer, just past the PICs.
+pub const TIMER_VECTOR: u8 = 0x30;
+/// Interrupt vector another CPU sends to have this one flush stale mappings from its TLB.
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0x31;
+/// Interrupt vector for spurious APIC interrupts, which need no EOI.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Local APIC timer counts per PIT tick, measured once on the BSP.
+static COUNTS_PER_TICK: AtomicU32 = AtomicU32::new(0);
+static MAPPED: AtomicBool = AtomicBool::new(false);
+
+/// Which CPUs an IPI is sent to.
+pub enum Destination {
+    Apic(u8),
+    AllButSelf,
+}
+
+/// Maps the local APIC registers, enables the calling CPU's APIC and records its id for other
+/// CPUs to send it interrupts.
+pub fn init() -> Result<(), MapToError<Size4KiB>> {
+    let mut base_msr = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { base_msr.read() };
+
+    if !MAPPED.swap(true, Ordering::AcqRel) {
+        let frame = PhysFrame::containing_address(PhysAddr::new(base & 0x000F_FFFF_FFFF_F000));
+        let page = Page::containing_address(VirtAddr::new(LAPIC_BASE));
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_CACHE
+            | PageTableFlags::WRITE_THROUGH;
+        map_physical(page, frame, flags)?;
+    }
+
+    unsafe {
+        base_msr.write(base | APIC_ENABLE);
+        write(SPURIOUS, SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+    percpu::current().set_apic_id(id());
+    Ok(())
+}
+
+/// The calling CPU's APIC id.
+pub fn id() -> u8 {
+    unsafe { (read(ID) >> 24) as u8 }
+}
+
+pub fn end_of_interrupt() {
+    unsafe { write(EOI, 0) };
+}
+
+/// Sends an INIT IPI, which resets the targets into the wait-for-SIPI state.
+pub fn send_init(destination: Destination) {
+    send_ipi(destination, 0b101 << 8 | 1 << 14);
+}
+
+/// Sends a startup IPI; the targets start executing in real mode at `page * 0x1000`.
+pub fn send_startup(destination: Destination, page: u8) {
+    send_ipi(destination, 0b110 << 8 | 1 << 14 | page as u32);
+}
+
+/// Raises interrupt `vector` on the targets.
+pub fn send_interrupt(destination: Destination, vector: u8) {
+    send_ipi(destination, 1 << 14 | vector as u32);
+}
+
+fn send_ipi(destination: Destination, command: u32) {
+    let (high, shorthand) = match destination {
+        Destination::Apic(id) => ((id as u32) << 24, 0),
+        Destination::AllButSelf => (0, 0b11 << 18),
+    };
+
+    unsafe {
+        write(ICR_HIGH, high);
+        write(ICR_LOW, command | shorthand);
+        while read(ICR_LOW) & DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Measures the local APIC timer against the PIT. Needs PIT interrupts to be running.
+pub fn calibrate_timer() {
+    const SAMPLE_TICKS: usize = 10;
+
+    unsafe {
+        write(TIMER_DIVIDE, DIVIDE_BY_16);
+        write(LVT_TIMER, TIMER_MASKED);
+
+        let start = ticks() + 1;
+        while ticks() < start {
+            core::hint::spin_loop();
+        }
+        write(TIMER_INITIAL, u32::MAX);
+        while ticks() < start + SAMPLE_TICKS {
+            core::hint::spin_loop();
+        }
+        let elapsed = u32::MAX - read(TIMER_CURRENT);
+        write(TIMER_INITIAL, 0);
+
+        COUNTS_PER_TICK.store(elapsed / SAMPLE_TICKS as u32, Ordering::Relaxed);
+    }
+}
+
+/// Starts the calling CPU's timer, firing `TIMER_VECTOR` about once per PIT tick.
+pub fn start_timer() {
+    let counts = COUNTS_PER_TICK.load(Ordering::Relaxed);
+    assert!(counts != 0, "the APIC timer hasn't been calibrated.");
+
+    unsafe {
+        write(TIMER_DIVIDE, DIVIDE_BY_16);
+        write(LVT_TIMER, TIMER_PERIODIC | TIMER_VECTOR as u32);
+        write(TIMER_INITIAL, counts);
+    }
+}
+
+unsafe fn read(register: u64) -> u32 {
+    core::ptr::read_volatile((LAPIC_BASE + register) as *const u32)
+}
+
+unsafe fn write(register: u64, value: u32) {
+    core::ptr::write_volatile((LAPIC_BASE + register) as *mut u32, value);
+}
@@ -1,51 +1,114 @@
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
 use lazy_static::lazy_static;
+use rust_alloc::boxed::Box;
+use rust_alloc::vec;
 use x86_64::instructions::tables::load_tss;
-use x86_64::registers::segmentation::{Segment, CS};
+use x86_64::registers::segmentation::{Segment, CS, DS, ES, SS};
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
-use x86_64::VirtAddr;
+use x86_64::{PrivilegeLevel, VirtAddr};
+
+use super::percpu::{self, MAX_CPUS};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0x0;
+pub const PAGE_FAULT_IST_INDEX: u16 = 0x1;
 pub const STACK_SIZE: usize = 4096 * 5;
 
+// Every CPU's GDT has the same layout. User data comes before user code so `sysret` can derive
+// both selectors from a single base.
+pub const KERNEL_CODE: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+pub const KERNEL_DATA: SegmentSelector = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+pub const USER_DATA: SegmentSelector = SegmentSelector::new(3, PrivilegeLevel::Ring3);
+pub const USER_CODE: SegmentSelector = SegmentSelector::new(4, PrivilegeLevel::Ring3);
+const TSS_SELECTOR: SegmentSelector = SegmentSelector::new(5, PrivilegeLevel::Ring0);
+
+/// The BSP's TSS. Its stacks are filled in by `init`, and `privilege_stack_table[0]` changes with
+/// every context switch, so it can't live behind a `lazy_static`.
+static mut BSP_TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// Each CPU's TSS, for `set_kernel_stack`.
+static TSS: [AtomicPtr<TaskStateSegment>; MAX_CPUS] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_CPUS];
+
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+    static ref GDT: GlobalDescriptorTable = build(unsafe { &*addr_of!(BSP_TSS) });
+}
+
+fn build(tss: &'static TaskStateSegment) -> GlobalDescriptorTable {
+    let mut gdt = GlobalDescriptorTable::new();
+    let selectors = [
+        gdt.add_entry(Descriptor::kernel_code_segment()),
+        gdt.add_entry(Descriptor::kernel_data_segment()),
+        gdt.add_entry(Descriptor::user_data_segment()),
+        gdt.add_entry(Descriptor::user_code_segment()),
+        gdt.add_entry(Descriptor::tss_segment(tss)),
+    ];
+    debug_assert_eq!(
+        selectors,
+        [KERNEL_CODE, KERNEL_DATA, USER_DATA, USER_CODE, TSS_SELECTOR]
+    );
+    gdt
+}
+
+fn load(gdt: &'static GlobalDescriptorTable, tss: *mut TaskStateSegment) {
+    TSS[percpu::id()].store(tss, Ordering::Release);
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(KERNEL_CODE);
+        SS::set_reg(KERNEL_DATA);
+        DS::set_reg(KERNEL_DATA);
+        ES::set_reg(KERNEL_DATA);
+        load_tss(TSS_SELECTOR);
+    }
+}
+
+/// Loads the BSP's GDT and TSS.
+pub fn init() {
+    unsafe {
+        let tss = addr_of_mut!(BSP_TSS);
+        (*tss).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
             static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
             let stack_start = VirtAddr::from_ptr(&raw const STACK);
 
             stack_start + STACK_SIZE
         };
-        tss
-    };
-}
+        // Page faults get their own stack so a thread's stack can grow, or be reported when it
+        // overflows into its guard page, instead of escalating into a double fault.
+        (*tss).interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
-lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-            },
-        )
-    };
-}
+            let stack_start = VirtAddr::from_ptr(&raw const STACK);
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+            stack_start + STACK_SIZE
+        };
+        load(&GDT, tss);
+    }
 }
 
-pub fn init() {
-    GDT.0.load();
-    unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+/// Gives an application processor a GDT and TSS of its own. The layout matches the BSP's, only
+/// the interrupt stacks differ, since a TSS (and so its GDT entry) can't be shared between CPUs.
+pub fn init_ap() {
+    let mut tss = TaskStateSegment::new();
+    for index in [DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX] {
+        let stack = vec![0u8; STACK_SIZE].leak();
+        tss.interrupt_stack_table[index as usize] = VirtAddr::from_ptr(stack.as_ptr()) + STACK_SIZE;
     }
+
+    let tss = Box::leak(Box::new(tss));
+    let gdt = Box::leak(Box::new(build(unsafe {
+        &*(tss as *const TaskStateSegment)
+    })));
+    load(gdt, tss);
+}
+
+/// Sets the stack the calling CPU switches to when an interrupt or a `syscall` arrives in ring 3.
+pub fn set_kernel_stack(top: VirtAddr) {
+    let cpu = percpu::current();
+    let tss = TSS[cpu.id()].load(Ordering::Acquire);
+    unsafe { (*tss).privilege_stack_table[0] = top };
+    cpu.set_kernel_stack(top);
 }
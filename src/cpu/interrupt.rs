@@ -1,7 +1,11 @@
-use crate::cpu::gdt;
+use crate::cpu::{apic, gdt, percpu};
 use crate::halt_loop;
 use crate::io::logging::kernel_error;
+use crate::mem::space::{USER_END, USER_START};
+use crate::process;
 use crate::syscall::dispatcher;
+use crate::thread;
+use crate::thread::stack::{self, StackFault};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use rust_alloc::format;
@@ -10,6 +14,7 @@ use x86_64::instructions::interrupts::without_interrupts;
 use x86_64::instructions::port::Port;
 use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::{PrivilegeLevel, VirtAddr};
 
 use core::arch::naked_asm;
 
@@ -22,8 +27,15 @@ const PIC2: u16 = 0xA1;
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+/// How many handlers can share one IRQ line.
+const HANDLERS_PER_IRQ: usize = 4;
+
+/// The handlers on one IRQ line, in the order they were added.
+type IrqHandlers = [Option<fn()>; HANDLERS_PER_IRQ];
+
+static IRQ_HANDLERS: Mutex<[IrqHandlers; 16]> = Mutex::new([[None; HANDLERS_PER_IRQ]; 16]);
+
 lazy_static! {
-    pub static ref IRQ_HANDLERS: Mutex<[fn(); 16]> = Mutex::new([default_irq_handler; 16]);
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
@@ -33,48 +45,109 @@ lazy_static! {
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
 
-        idt[interrupt_index(0) as usize].set_handler_fn(irq0_handler);
-        idt[interrupt_index(1) as usize].set_handler_fn(irq1_handler);
-        idt[interrupt_index(2) as usize].set_handler_fn(irq2_handler);
-        idt[interrupt_index(3) as usize].set_handler_fn(irq3_handler);
-        idt[interrupt_index(4) as usize].set_handler_fn(irq4_handler);
-        idt[interrupt_index(5) as usize].set_handler_fn(irq5_handler);
-        idt[interrupt_index(6) as usize].set_handler_fn(irq6_handler);
-        idt[interrupt_index(7) as usize].set_handler_fn(irq7_handler);
-        idt[interrupt_index(8) as usize].set_handler_fn(irq8_handler);
-        idt[interrupt_index(9) as usize].set_handler_fn(irq9_handler);
-        idt[interrupt_index(10) as usize].set_handler_fn(irq10_handler);
-        idt[interrupt_index(11) as usize].set_handler_fn(irq11_handler);
-        idt[interrupt_index(12) as usize].set_handler_fn(irq12_handler);
-        idt[interrupt_index(13) as usize].set_handler_fn(irq13_handler);
-        idt[interrupt_index(14) as usize].set_handler_fn(irq14_handler);
-        idt[interrupt_index(15) as usize].set_handler_fn(irq15_handler);
-        idt[0x80].set_handler_fn(unsafe {
-            core::mem::transmute::<
-                *mut fn(),
-                extern "x86-interrupt" fn(x86_64::structures::idt::InterruptStackFrame),
-            >(wrapped_syscall_handler as *mut fn())
-        });
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        // Everything that can interrupt ring 3 and touches per-CPU data goes through `wrap!`,
+        // which swaps `GS` back to the kernel's.
+        idt[interrupt_index(0) as usize].set_handler_fn(wrapped(wrapped_timer_handler));
+        idt[interrupt_index(1) as usize].set_handler_fn(wrapped(wrapped_irq1_handler));
+        idt[interrupt_index(2) as usize].set_handler_fn(wrapped(wrapped_irq2_handler));
+        idt[interrupt_index(3) as usize].set_handler_fn(wrapped(wrapped_irq3_handler));
+        idt[interrupt_index(4) as usize].set_handler_fn(wrapped(wrapped_irq4_handler));
+        idt[interrupt_index(5) as usize].set_handler_fn(wrapped(wrapped_irq5_handler));
+        idt[interrupt_index(6) as usize].set_handler_fn(wrapped(wrapped_irq6_handler));
+        idt[interrupt_index(7) as usize].set_handler_fn(wrapped(wrapped_irq7_handler));
+        idt[interrupt_index(8) as usize].set_handler_fn(wrapped(wrapped_irq8_handler));
+        idt[interrupt_index(9) as usize].set_handler_fn(wrapped(wrapped_irq9_handler));
+        idt[interrupt_index(10) as usize].set_handler_fn(wrapped(wrapped_irq10_handler));
+        idt[interrupt_index(11) as usize].set_handler_fn(wrapped(wrapped_irq11_handler));
+        idt[interrupt_index(12) as usize].set_handler_fn(wrapped(wrapped_irq12_handler));
+        idt[interrupt_index(13) as usize].set_handler_fn(wrapped(wrapped_irq13_handler));
+        idt[interrupt_index(14) as usize].set_handler_fn(wrapped(wrapped_irq14_handler));
+        idt[interrupt_index(15) as usize].set_handler_fn(wrapped(wrapped_irq15_handler));
+        idt[apic::TIMER_VECTOR as usize].set_handler_fn(wrapped(wrapped_apic_timer_handler));
+        idt[apic::TLB_SHOOTDOWN_VECTOR as usize]
+            .set_handler_fn(wrapped(wrapped_tlb_shootdown_handler));
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_handler);
+        // Reachable from ring 3 with `int 0x80`.
+        idt[0x80]
+            .set_handler_fn(wrapped(wrapped_syscall_handler))
+            .set_privilege_level(PrivilegeLevel::Ring3);
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(core::mem::transmute::<
+                    unsafe extern "sysv64" fn(),
+                    extern "x86-interrupt" fn(InterruptStackFrame, PageFaultErrorCode),
+                >(wrapped_page_fault_handler))
+                // Needed for faults on a kernel stack, which can't take the interrupt frame
+                // itself. `wrapped_page_fault_handler` moves off it for everything else.
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
 
         idt
     };
 }
 
 macro_rules! irq_handler {
-    ($handler:ident, $irq:expr) => {
-        pub extern "x86-interrupt" fn $handler(_stack_frame: InterruptStackFrame) {
-            let handlers = IRQ_HANDLERS.lock();
-            handlers[$irq]();
+    ($handler:ident => $w:ident, $irq:expr) => {
+        extern "sysv64" fn $handler(_stack_frame: &mut InterruptStackFrame, _regs: &mut Registers) {
+            run_irq_handlers($irq);
             unsafe {
                 crate::cpu::interrupt::PICS
                     .lock()
                     .notify_end_of_interrupt(interrupt_index($irq));
             }
         }
+
+        wrap!($handler => $w);
+    };
+}
+
+macro_rules! push_registers {
+    () => {
+        "
+        push rbp
+        push rax
+        push rbx
+        push rcx
+        push rdx
+        push rsi
+        push rdi
+        push r8
+        push r9
+        push r10
+        push r11
+        push r12
+        push r13
+        push r14
+        push r15
+        "
     };
 }
 
+macro_rules! pop_registers {
+    () => {
+        "
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rdi
+        pop rsi
+        pop rdx
+        pop rcx
+        pop rbx
+        pop rax
+        pop rbp
+        "
+    };
+}
+
+/// Gives `$fn` the interrupt frame and every general purpose register. If the interrupt came
+/// from ring 3 (the RPL of the saved `CS` is 3), `swapgs` on the way in and out, so the kernel
+/// always runs on its own per-CPU data.
 macro_rules! wrap {
     ($fn: ident => $w:ident) => {
         #[naked]
@@ -83,40 +156,25 @@ macro_rules! wrap {
         pub unsafe extern "sysv64" fn $w() {
             naked_asm!(
                 "
-                push rbp
-                push rax
-                push rbx
-                push rcx
-                push rdx
-                push rsi
-                push rdi
-                push r8
-                push r9
-                push r10
-                push r11
-                push r12
-                push r13
-                push r14
-                push r15
+                test byte ptr [rsp + 8], 3
+                jz 1f
+                swapgs
+            1:
+                ",
+                push_registers!(),
+                "
                 mov rsi, rsp  // arg2: register list
                 mov rdi, rsp
                 add rdi, 15*8 // arg1: interupt frame
                 call {}
-                pop r15
-                pop r14
-                pop r13
-                pop r12
-                pop r11
-                pop r10
-                pop r9
-                pop r8
-                pop rdi
-                pop rsi
-                pop rdx
-                pop rcx
-                pop rbx
-                pop rax
-                pop rbp
+                cli
+                ",
+                pop_registers!(),
+                "
+                test byte ptr [rsp + 8], 3
+                jz 2f
+                swapgs
+            2:
                 iretq
                 ",
                 sym $fn,
@@ -125,15 +183,63 @@ macro_rules! wrap {
     };
 }
 
+/// Lets a `wrap!`ped handler go in the IDT.
+fn wrapped(handler: unsafe extern "sysv64" fn()) -> extern "x86-interrupt" fn(InterruptStackFrame) {
+    unsafe {
+        core::mem::transmute::<
+            unsafe extern "sysv64" fn(),
+            extern "x86-interrupt" fn(InterruptStackFrame),
+        >(handler)
+    }
+}
+
 extern "sysv64" fn syscall_handler(_stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
     let n = regs.rax;
     let arg1 = regs.rdi;
     let arg2 = regs.rsi;
     let arg3 = regs.rdx;
     regs.rax = dispatcher(n, arg1, arg2, arg3);
-    unsafe { PICS.lock().notify_end_of_interrupt(0x80) };
 }
 
+/// IRQ0 gets the full register frame so the scheduler can switch threads from inside it.
+extern "sysv64" fn timer_handler(stack_frame: &mut InterruptStackFrame, _regs: &mut Registers) {
+    run_irq_handlers(0);
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(interrupt_index(0));
+    }
+    exit_if_killed_in_user_mode(stack_frame);
+    crate::thread::preempt();
+}
+
+/// The local APIC timer, which drives preemption on every CPU but the BSP.
+extern "sysv64" fn apic_timer_handler(
+    stack_frame: &mut InterruptStackFrame,
+    _regs: &mut Registers,
+) {
+    crate::thread::tick();
+    apic::end_of_interrupt();
+    exit_if_killed_in_user_mode(stack_frame);
+    crate::thread::preempt();
+}
+
+/// Another CPU changed the mappings of an address space this one may have loaded.
+extern "sysv64" fn tlb_shootdown_handler(
+    _stack_frame: &mut InterruptStackFrame,
+    _regs: &mut Registers,
+) {
+    crate::mem::tlb::serve();
+    apic::end_of_interrupt();
+}
+
+/// Lets a killed process's thread that is spinning in ring 3 notice on the next tick.
+fn exit_if_killed_in_user_mode(stack_frame: &InterruptStackFrame) {
+    if stack_frame.code_segment & 3 == 3 {
+        process::exit_if_killed();
+    }
+}
+
+extern "x86-interrupt" fn spurious_handler(_stack_frame: InterruptStackFrame) {}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     kernel_error(format!("BREAKPOINT\n{:#?}", stack_frame).as_str());
 }
@@ -145,32 +251,212 @@ extern "x86-interrupt" fn double_fault_handler(
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
+/// Everything the CPU and `push_registers!` put on the stack for a page fault: the registers, the
+/// error code and the interrupt frame.
+const PAGE_FAULT_FRAME_SIZE: u64 = (15 + 1 + 5) * 8;
+
+/// `wrap!` for the page fault handler, which also takes the error code the CPU pushed.
+///
+/// The CPU always switches to the page fault IST stack, which every fault on this CPU shares. Only
+/// faults on a kernel stack are handled there, by `page_fault_entry`. Anything else can block or
+/// fault again, so it is moved, along with the saved registers, to the stack `page_fault_entry`
+/// returns before `page_fault_handler` runs, as Linux does for exceptions from user mode.
+#[naked]
+/// # Safety
+/// lmao
+pub unsafe extern "sysv64" fn wrapped_page_fault_handler() {
+    naked_asm!(
+        "
+        test byte ptr [rsp + 16], 3
+        jz 1f
+        swapgs
+    1:
+        ",
+        push_registers!(),
+        "
+        cld
+        mov rdx, [rsp + 15*8] // arg3: error code
+        mov rsi, rsp          // arg2: register list
+        mov rdi, rsp
+        add rdi, 16*8         // arg1: interupt frame
+        sub rsp, 8            // the error code left the stack misaligned
+        call {entry}
+        add rsp, 8
+        test rax, rax
+        jz 3f
+
+        lea rdi, [rax - {size}]
+        mov rsi, rsp
+        mov rcx, {size} / 8
+        rep movsq
+        lea rsp, [rax - {size}]
+
+        mov rdx, [rsp + 15*8]
+        mov rsi, rsp
+        mov rdi, rsp
+        add rdi, 16*8
+        sub rsp, 8
+        call {handler}
+        add rsp, 8
+    3:
+        cli
+        ",
+        pop_registers!(),
+        "
+        add rsp, 8
+        test byte ptr [rsp + 8], 3
+        jz 2f
+        swapgs
+    2:
+        iretq
+        ",
+        entry = sym page_fault_entry,
+        handler = sym page_fault_handler,
+        size = const PAGE_FAULT_FRAME_SIZE,
+    );
+}
+
+/// Runs on the page fault IST stack. Grows the faulting kernel stack if that's what the fault is,
+/// and returns 0. Otherwise returns the top of the stack the rest of the handler should run on:
+/// the thread's kernel stack for a fault from user mode, which has nothing on it yet, or the
+/// interrupted stack for one from the kernel.
+extern "sysv64" fn page_fault_entry(
+    stack_frame: &mut InterruptStackFrame,
+    _regs: &mut Registers,
+    error_code: u64,
+) -> u64 {
+    use x86_64::registers::control::Cr2;
+
+    if stack_frame.code_segment & 3 == 3 {
+        return percpu::current().kernel_stack().as_u64();
+    }
+
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        if let StackFault::Grown = grow_stack(stack_frame, Cr2::read()) {
+            return 0;
+        }
+    }
+
+    // The moved frame goes right below the interrupted code's, so that much of its stack has to
+    // be mapped before leaving the IST stack. Past that, a fault is taken like any other.
+    let top = stack_frame.stack_pointer.align_down(16u64);
+    grow_stack(stack_frame, top - PAGE_FAULT_FRAME_SIZE);
+    top.as_u64()
+}
+
+/// Grows the kernel stack `addr` is on, halting if it overflowed or there was no frame for it.
+fn grow_stack(stack_frame: &InterruptStackFrame, addr: VirtAddr) -> StackFault {
+    match stack::handle_page_fault(addr) {
+        StackFault::Overflow(thread) => {
+            kernel_error(format!("thread {} overflowed its stack", thread).as_str());
+            kernel_error(format!("{:#?}", stack_frame).as_str());
+            halt_loop();
+        }
+        StackFault::NoFrame(thread) => {
+            kernel_error(format!("thread {} couldn't grow its stack", thread).as_str());
+            kernel_error(format!("{:#?}", stack_frame).as_str());
+            halt_loop();
+        }
+        fault => fault,
+    }
+}
+
+extern "sysv64" fn page_fault_handler(
+    stack_frame: &mut InterruptStackFrame,
+    _regs: &mut Registers,
+    error_code: u64,
 ) {
     use x86_64::registers::control::Cr2;
 
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    let addr = Cr2::read();
+
+    // Faults on a process's memory, from its code or from the kernel acting for it, are the
+    // process's business: either an area backs the page, or the process is killed. Nothing else
+    // goes down with it.
+    let in_user_half = (USER_START..USER_END).contains(&addr.as_u64());
+    if in_user_half || error_code.contains(PageFaultErrorCode::USER_MODE) {
+        if let Some(current) = process::current() {
+            let write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+            let execute = error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH);
+            if in_user_half && current.handle_fault(addr, write, execute) {
+                return;
+            }
+
+            kernel_error(
+                format!(
+                    "process {} ({}) killed: thread {} faulted at {:?} ({:?}) from {:?}",
+                    current.id(),
+                    current.name(),
+                    thread::current(),
+                    addr,
+                    error_code,
+                    stack_frame.instruction_pointer
+                )
+                .as_str(),
+            );
+            drop(current);
+            process::exit(thread::FAULT_EXIT_CODE);
+        } else if error_code.contains(PageFaultErrorCode::USER_MODE) {
+            kernel_error(
+                format!(
+                    "thread {} killed: page fault at {:?}",
+                    thread::current(),
+                    addr
+                )
+                .as_str(),
+            );
+            thread::exit(thread::FAULT_EXIT_CODE);
+        }
+    }
+
     kernel_error("PAGE FAULT");
-    kernel_error(format!("Accessed Address: {:?}", Cr2::read()).as_str());
+    kernel_error(format!("Accessed Address: {:?}", addr).as_str());
     kernel_error(format!("Error Code: {:?}", error_code).as_str());
     kernel_error(format!("{:#?}", stack_frame).as_str());
     halt_loop();
 }
 
+/// Loads the IDT. Every CPU shares the same one.
 pub fn init_idt() {
     IDT.load();
 }
 
-pub fn set_irq_handler(irq: u8, handler: fn()) {
+/// Adds `handler` to those run on `irq`, and unmasks it. Devices can share a line, so handlers
+/// already on it stay, and each must check whether its device raised the interrupt. Adding the
+/// same handler twice does nothing.
+///
+/// # Panics
+/// If the line already has `HANDLERS_PER_IRQ` handlers.
+pub fn add_irq_handler(irq: u8, handler: fn()) {
     without_interrupts(|| {
         let mut handlers = IRQ_HANDLERS.lock();
-        handlers[irq as usize] = handler;
+        let line = &mut handlers[irq as usize];
+        if !line
+            .iter()
+            .flatten()
+            .any(|&added| core::ptr::fn_addr_eq(added, handler))
+        {
+            let free = line
+                .iter_mut()
+                .find(|slot| slot.is_none())
+                .expect("too many handlers share an IRQ line");
+            *free = Some(handler);
+        }
 
         clear_irq_mask(irq);
     });
 }
 
+/// Runs every handler on `irq`, with the handler list unlocked.
+fn run_irq_handlers(irq: usize) {
+    let handlers = IRQ_HANDLERS.lock()[irq];
+    for handler in handlers.into_iter().flatten() {
+        handler();
+    }
+}
+
 pub fn set_irq_mask(irq: u8) {
     let mut port: Port<u8> = Port::new(if irq < 8 { PIC1 } else { PIC2 });
     unsafe {
@@ -191,26 +477,26 @@ fn interrupt_index(irq: u8) -> u8 {
     crate::cpu::interrupt::PIC_1_OFFSET + irq
 }
 
-fn default_irq_handler() {}
-
 wrap!(syscall_handler => wrapped_syscall_handler);
+wrap!(timer_handler => wrapped_timer_handler);
+wrap!(apic_timer_handler => wrapped_apic_timer_handler);
+wrap!(tlb_shootdown_handler => wrapped_tlb_shootdown_handler);
 
-irq_handler!(irq0_handler, 0);
-irq_handler!(irq1_handler, 1);
-irq_handler!(irq2_handler, 2);
-irq_handler!(irq3_handler, 3);
-irq_handler!(irq4_handler, 4);
-irq_handler!(irq5_handler, 5);
-irq_handler!(irq6_handler, 6);
-irq_handler!(irq7_handler, 7);
-irq_handler!(irq8_handler, 8);
-irq_handler!(irq9_handler, 9);
-irq_handler!(irq10_handler, 10);
-irq_handler!(irq11_handler, 11);
-irq_handler!(irq12_handler, 12);
-irq_handler!(irq13_handler, 13);
-irq_handler!(irq14_handler, 14);
-irq_handler!(irq15_handler, 15);
+irq_handler!(irq1_handler => wrapped_irq1_handler, 1);
+irq_handler!(irq2_handler => wrapped_irq2_handler, 2);
+irq_handler!(irq3_handler => wrapped_irq3_handler, 3);
+irq_handler!(irq4_handler => wrapped_irq4_handler, 4);
+irq_handler!(irq5_handler => wrapped_irq5_handler, 5);
+irq_handler!(irq6_handler => wrapped_irq6_handler, 6);
+irq_handler!(irq7_handler => wrapped_irq7_handler, 7);
+irq_handler!(irq8_handler => wrapped_irq8_handler, 8);
+irq_handler!(irq9_handler => wrapped_irq9_handler, 9);
+irq_handler!(irq10_handler => wrapped_irq10_handler, 10);
+irq_handler!(irq11_handler => wrapped_irq11_handler, 11);
+irq_handler!(irq12_handler => wrapped_irq12_handler, 12);
+irq_handler!(irq13_handler => wrapped_irq13_handler, 13);
+irq_handler!(irq14_handler => wrapped_irq14_handler, 14);
+irq_handler!(irq15_handler => wrapped_irq15_handler, 15);
 
 #[repr(align(8), C)]
 #[derive(Debug, Clone, Default)]
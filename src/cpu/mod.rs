@@ -1,2 +1,7 @@
+pub mod apic;
 pub mod gdt;
 pub mod interrupt;
+pub mod percpu;
+pub mod smp;
+pub mod syscall;
+pub mod user;
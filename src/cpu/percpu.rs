@@ -0,0 +1,139 @@
+//! Data private to each CPU, found through the `GS` base.
+
+use core::arch::asm;
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use x86_64::registers::control::Cr3;
+use x86_64::registers::model_specific::{GsBase, KernelGsBase};
+use x86_64::structures::paging::PhysFrame;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The most CPUs the kernel will bring up. Any further application processors are left halted.
+pub const MAX_CPUS: usize = 8;
+
+#[repr(C)]
+pub struct PerCpu {
+    /// Points back at this struct so `current` can find it with a single `GS`-relative load.
+    this: *const PerCpu,
+    id: usize,
+    online: AtomicBool,
+    /// The `Runtime` scheduling threads on this CPU, or 0 if there is none yet.
+    pub runtime: AtomicUsize,
+    /// Top of the running thread's kernel stack, which `syscall` switches to. Mirrors the TSS's
+    /// `RSP0`.
+    kernel_stack: AtomicU64,
+    /// Where the `syscall` entry parks the user stack pointer while it switches stacks.
+    user_stack: AtomicU64,
+    /// The local APIC's id, set by `apic::init`.
+    apic_id: AtomicU8,
+    /// Physical address of the level 4 table in `CR3`, so a CPU changing an address space knows
+    /// whose TLBs may hold it.
+    address_space: AtomicU64,
+}
+
+/// Offsets for finding the stacks from assembly through `GS`.
+pub const KERNEL_STACK_OFFSET: usize = offset_of!(PerCpu, kernel_stack);
+pub const USER_STACK_OFFSET: usize = offset_of!(PerCpu, user_stack);
+
+unsafe impl Sync for PerCpu {}
+
+impl PerCpu {
+    const fn new(id: usize) -> Self {
+        PerCpu {
+            this: core::ptr::null(),
+            id,
+            online: AtomicBool::new(false),
+            runtime: AtomicUsize::new(0),
+            kernel_stack: AtomicU64::new(0),
+            user_stack: AtomicU64::new(0),
+            apic_id: AtomicU8::new(0),
+            address_space: AtomicU64::new(0),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    pub fn kernel_stack(&self) -> VirtAddr {
+        VirtAddr::new(self.kernel_stack.load(Ordering::Relaxed))
+    }
+
+    pub fn set_kernel_stack(&self, top: VirtAddr) {
+        self.kernel_stack.store(top.as_u64(), Ordering::Relaxed);
+    }
+
+    pub fn apic_id(&self) -> u8 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn set_apic_id(&self, id: u8) {
+        self.apic_id.store(id, Ordering::Relaxed);
+    }
+
+    /// The level 4 table this CPU has loaded.
+    pub fn address_space(&self) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.address_space.load(Ordering::SeqCst)))
+    }
+
+    /// Records `table` as loaded. Called before it's written to `CR3`; see `mem::tlb`.
+    pub fn set_address_space(&self, table: PhysFrame) {
+        self.address_space
+            .store(table.start_address().as_u64(), Ordering::SeqCst);
+    }
+}
+
+static mut CPUS: [PerCpu; MAX_CPUS] = {
+    let mut cpus = [const { PerCpu::new(0) }; MAX_CPUS];
+    let mut id = 0;
+    while id < MAX_CPUS {
+        cpus[id].id = id;
+        id += 1;
+    }
+    cpus
+};
+
+/// Points `GS` at CPU `id`'s data. Called once on every CPU before anything uses `current`.
+///
+/// The kernel always runs with `GS` on this data. Entries from and exits to ring 3 `swapgs`, so
+/// `KernelGsBase` holds it while user code runs and the user's base the rest of the time.
+pub fn init(id: usize) {
+    assert!(id < MAX_CPUS, "cpu {} is past MAX_CPUS.", id);
+    unsafe {
+        let cpu = &raw mut CPUS[id];
+        (*cpu).this = cpu;
+        (*cpu).set_address_space(Cr3::read().0);
+        (*cpu).online.store(true, Ordering::Release);
+        GsBase::write(VirtAddr::from_ptr(cpu));
+        KernelGsBase::write(VirtAddr::zero());
+    }
+}
+
+/// The calling CPU's data.
+pub fn current() -> &'static PerCpu {
+    let this: *const PerCpu;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) this, options(nostack, readonly, preserves_flags));
+        &*this
+    }
+}
+
+/// The index of the calling CPU; the BSP is always 0.
+pub fn id() -> usize {
+    current().id
+}
+
+/// Every CPU that has called `init`.
+pub fn online() -> impl Iterator<Item = &'static PerCpu> {
+    #[allow(static_mut_refs)]
+    unsafe { CPUS.iter() }.filter(|cpu| cpu.is_online())
+}
+
+pub fn count() -> usize {
+    online().count()
+}
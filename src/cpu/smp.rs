@@ -0,0 +1,216 @@
+//! Bringing up the application processors.
+//!
+//! The BSP copies a small trampoline below 1MiB and broadcasts INIT and startup IPIs. Every AP
+//! wakes up in real mode at the trampoline, switches to long mode on the BSP's page tables, takes
+//! a CPU index and a stack, and calls `ap_main`, which gives it its own GDT, TSS and `Runtime`.
+
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_alloc::boxed::Box;
+use rust_alloc::format;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::apic::{self, Destination};
+use super::percpu::{self, MAX_CPUS};
+use crate::io::logging::*;
+use crate::mem::paging::map_physical;
+use crate::thread::Runtime;
+use crate::time::rtc::{nanowait, ticks};
+
+/// Physical (and identity-mapped virtual) address the trampoline is copied to. It falls inside
+/// the bootloader's real mode image, which nothing needs once the kernel is running.
+const TRAMPOLINE: u64 = 0x8000;
+const AP_STACK_SIZE: usize = 4096 * 16;
+/// How long to wait for the APs to check in, in PIT ticks.
+const STARTUP_TIMEOUT: usize = 100;
+
+#[repr(C, align(16))]
+struct ApStack([u8; AP_STACK_SIZE]);
+
+/// Boot stacks for CPUs 1 and up. Each AP keeps running its base thread on it.
+static mut AP_STACKS: [ApStack; MAX_CPUS - 1] =
+    [const { ApStack([0; AP_STACK_SIZE]) }; MAX_CPUS - 1];
+
+/// Number of CPUs that have finished `ap_main`'s setup, including the BSP.
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+/// Filled in by the BSP before the startup IPIs; must match the layout at `ap_trampoline_data`.
+#[repr(C)]
+struct TrampolineData {
+    cr3: u64,
+    /// CPU `n` starts with its stack pointer at `stacks + n * stack_size`.
+    stacks: u64,
+    stack_size: u64,
+    entry: u64,
+    /// The next CPU index to hand out, bumped atomically by each AP.
+    next_cpu: u64,
+}
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+global_asm!(
+    r#"
+    .pushsection .rodata.ap_trampoline, "a"
+    .code16
+    .global ap_trampoline_start
+ap_trampoline_start:
+    cli
+    cld
+    xorw %ax, %ax
+    movw %ax, %ds
+    lgdtl (ap_gdt_pointer - ap_trampoline_start + {base})
+    movl %cr0, %eax
+    orl $1, %eax
+    movl %eax, %cr0
+    ljmpl $0x08, $(ap_protected - ap_trampoline_start + {base})
+
+    .code32
+ap_protected:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    // PAE
+    movl %cr4, %eax
+    orl $(1 << 5), %eax
+    movl %eax, %cr4
+    movl (ap_trampoline_data - ap_trampoline_start + {base}), %eax
+    movl %eax, %cr3
+    // EFER: long mode and no-execute, which the BSP's page tables use
+    movl $0xC0000080, %ecx
+    rdmsr
+    orl $((1 << 8) | (1 << 11)), %eax
+    wrmsr
+    // paging and write protect
+    movl %cr0, %eax
+    orl $((1 << 31) | (1 << 16)), %eax
+    movl %eax, %cr0
+    ljmpl $0x18, $(ap_long - ap_trampoline_start + {base})
+
+    .code64
+ap_long:
+    xorw %ax, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    movq $1, %rdi
+    lock xaddq %rdi, (ap_trampoline_data + 32 - ap_trampoline_start + {base})
+    cmpq ${max_cpus}, %rdi
+    jae 2f
+    movq %rdi, %rax
+    imulq (ap_trampoline_data + 16 - ap_trampoline_start + {base}), %rax
+    addq (ap_trampoline_data + 8 - ap_trampoline_start + {base}), %rax
+    movq %rax, %rsp
+    movq (ap_trampoline_data + 24 - ap_trampoline_start + {base}), %rax
+    callq *%rax
+2:
+    cli
+    hlt
+    jmp 2b
+
+    .align 8
+ap_gdt:
+    .quad 0
+    .quad 0x00CF9A000000FFFF
+    .quad 0x00CF92000000FFFF
+    .quad 0x00AF9A000000FFFF
+ap_gdt_pointer:
+    .word ap_gdt_pointer - ap_gdt - 1
+    .long ap_gdt - ap_trampoline_start + {base}
+
+    .align 8
+    .global ap_trampoline_data
+ap_trampoline_data:
+    .fill 5, 8, 0
+    .global ap_trampoline_end
+ap_trampoline_end:
+    .popsection
+    "#,
+    base = const TRAMPOLINE,
+    max_cpus = const MAX_CPUS,
+    options(att_syntax)
+);
+
+/// Starts every other CPU and waits for them to come online. Returns the number of CPUs now
+/// running, including the BSP.
+///
+/// Needs the heap, the frame allocator and PIT interrupts.
+pub fn init() -> Result<usize, MapToError<x86_64::structures::paging::Size4KiB>> {
+    apic::init()?;
+    apic::calibrate_timer();
+
+    let page = Page::containing_address(VirtAddr::new(TRAMPOLINE));
+    let frame = PhysFrame::containing_address(PhysAddr::new(TRAMPOLINE));
+    match map_physical(
+        page,
+        frame,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+    ) {
+        Ok(()) => {}
+        Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => {}
+        Err(e) => return Err(e),
+    }
+
+    unsafe {
+        let start = &raw const ap_trampoline_start;
+        let len = &raw const ap_trampoline_end as usize - start as usize;
+        core::ptr::copy_nonoverlapping(start, TRAMPOLINE as *mut u8, len);
+
+        let offset = &raw const ap_trampoline_data as usize - start as usize;
+        let data = (TRAMPOLINE as usize + offset) as *mut TrampolineData;
+        data.write_volatile(TrampolineData {
+            cr3: Cr3::read().0.start_address().as_u64(),
+            stacks: &raw const AP_STACKS as u64,
+            stack_size: AP_STACK_SIZE as u64,
+            entry: ap_main as usize as u64,
+            next_cpu: 1,
+        });
+    }
+
+    apic::send_init(Destination::AllButSelf);
+    nanowait(10_000_000);
+    for _ in 0..2 {
+        apic::send_startup(Destination::AllButSelf, (TRAMPOLINE >> 12) as u8);
+        nanowait(200_000);
+    }
+
+    let deadline = ticks() + STARTUP_TIMEOUT;
+    let mut online = ONLINE.load(Ordering::Acquire);
+    while ticks() < deadline && online < MAX_CPUS {
+        x86_64::instructions::hlt();
+        online = ONLINE.load(Ordering::Acquire);
+    }
+
+    kernel_event(format!("{} CPUs online.", online).as_str());
+    Ok(online)
+}
+
+pub fn online() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Where each AP lands from the trampoline, on its own boot stack with interrupts disabled.
+extern "sysv64" fn ap_main(cpu: usize) -> ! {
+    percpu::init(cpu);
+    super::gdt::init_ap();
+    super::syscall::init();
+    super::interrupt::init_idt();
+    apic::init().expect("the local APIC is mapped by the BSP.");
+    apic::start_timer();
+
+    let runtime = Box::leak(Box::new(Runtime::new()));
+    runtime.init();
+    ONLINE.fetch_add(1, Ordering::AcqRel);
+
+    interrupts::enable();
+    runtime.park();
+}
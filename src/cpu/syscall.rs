@@ -0,0 +1,78 @@
+//! The `syscall`/`sysret` fast path into `syscall::dispatcher`.
+//!
+//! It takes the same registers as `int 0x80`: the number in `rax`, arguments in `rdi`, `rsi` and
+//! `rdx`, and the result back in `rax`. As the instruction demands, `rcx` and `r11` are
+//! clobbered; every other register is preserved.
+
+use core::arch::naked_asm;
+
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+
+use super::gdt::{KERNEL_CODE, KERNEL_DATA, USER_CODE, USER_DATA};
+use super::percpu::{KERNEL_STACK_OFFSET, USER_STACK_OFFSET};
+use crate::syscall::dispatcher;
+
+/// Enables `syscall` on the calling CPU. Needs the CPU's GDT and per-CPU data to be set up.
+pub fn init() {
+    unsafe {
+        Star::write(USER_CODE, USER_DATA, KERNEL_CODE, KERNEL_DATA)
+            .expect("the GDT layout doesn't suit sysret.");
+        LStar::write(VirtAddr::new(entry as usize as u64));
+        // Run the kernel side with interrupts off, like the `int 0x80` gate does.
+        SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+    }
+}
+
+extern "sysv64" fn handler(n: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
+    dispatcher(n, arg1, arg2, arg3)
+}
+
+/// Where `syscall` lands, still on the user stack with the user's `GS`, the return address in
+/// `rcx` and the user's flags in `r11`.
+///
+/// `sysret` can't fault on the return address: it's the one `syscall` came from, which lies in
+/// the user half and so is canonical.
+#[naked]
+unsafe extern "sysv64" fn entry() {
+    naked_asm!(
+        "
+        swapgs
+        mov gs:[{user_stack}], rsp
+        mov rsp, gs:[{kernel_stack}]
+        push qword ptr gs:[{user_stack}]
+        push r11
+        push rcx
+        push rdi
+        push rsi
+        push rdx
+        push r8
+        push r9
+        push r10
+        sub rsp, 8 // keep the stack 16 byte aligned for the call
+        mov rcx, rdx
+        mov rdx, rsi
+        mov rsi, rdi
+        mov rdi, rax
+        call {handler}
+        cli
+        add rsp, 8
+        pop r10
+        pop r9
+        pop r8
+        pop rdx
+        pop rsi
+        pop rdi
+        pop rcx
+        pop r11
+        pop rsp
+        swapgs
+        sysretq
+        ",
+        user_stack = const USER_STACK_OFFSET,
+        kernel_stack = const KERNEL_STACK_OFFSET,
+        handler = sym handler,
+    );
+}
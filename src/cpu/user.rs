@@ -0,0 +1,41 @@
+//! Dropping into ring 3.
+
+use core::arch::asm;
+
+use x86_64::VirtAddr;
+
+use super::gdt::{USER_CODE, USER_DATA};
+
+/// Interrupts enabled, plus the reserved bit that always reads as one.
+const USER_RFLAGS: u64 = 0x202;
+
+/// Jumps to `entry` in ring 3 with the stack pointer at `stack`. Both must be mapped
+/// `USER_ACCESSIBLE`.
+///
+/// Interrupts and syscalls from ring 3 land at the top of the calling thread's kernel stack, so
+/// whatever the thread had on it is gone; it only comes back to the kernel through a syscall or a
+/// fault, and leaves through `thread::exit`.
+///
+/// User code starts with a `GS` base of 0; the kernel's per-CPU data waits in `KernelGsBase`.
+///
+/// # Safety
+/// `entry` must point at code meant to run in user mode.
+pub unsafe fn enter_user_mode(entry: VirtAddr, stack: VirtAddr) -> ! {
+    asm!(
+        // No interrupt may see the user's `GS` while still in ring 0.
+        "cli",
+        "swapgs",
+        "push {ss}",
+        "push {rsp}",
+        "push {rflags}",
+        "push {cs}",
+        "push {rip}",
+        "iretq",
+        ss = in(reg) u64::from(USER_DATA.0),
+        rsp = in(reg) stack.as_u64(),
+        rflags = in(reg) USER_RFLAGS,
+        cs = in(reg) u64::from(USER_CODE.0),
+        rip = in(reg) entry.as_u64(),
+        options(noreturn)
+    );
+}
@@ -1,103 +1,77 @@
-use self::file::{Directory, File, FileType};
+//! The filesystem, stored on a block device in the format the `lateralfs` crate documents.
+//!
+//! The format itself lives in `lateralfs` so the host tools (`tools/lfs`) read and write disk
+//! images exactly as the kernel does; this plugs it into the kernel's block devices.
 
-pub mod file;
+use rust_alloc::sync::Arc;
 
-const FS_MAX_SIZE: usize = 0x400 * 0x400 * 10;
-const DATA_SEP: u8 = 0x1E;
-// const UNIT_SEP: u8 = 0x1F;
+pub use lateralfs::check::{check, Problem};
+pub use lateralfs::{
+    Entry, Extent, FsError, Kind, Path, ReadDir, Section, Superblock, Walk, BLOCK_SIZE, ENTRY_SIZE,
+    MIN_BLOCKS, VERSION,
+};
 
-const TYPE_FILE: u8 = 0x11;
-const TYPE_DIR: u8 = 0x12;
+use crate::block::{self, BlockDevice};
 
-macro_rules! write_unit_filesystem {
-    ($self: ident type $ft: ident ... $label: expr, $data: expr) => {
-        $self.heading = $self.ptr;
-        for i in $label {
-            $self.index[$self.heading] = *i;
-            $self.heading += 1;
-        }
+/// A filesystem on one of the kernel's block devices.
+pub type Filesystem = lateralfs::Filesystem<Disk>;
 
-        $self.index[$self.heading] = DATA_SEP;
-        $self.heading += 1;
-        $self.index[$self.heading] = $ft;
-
-        for i in 0..4 {
-            $self.heading += 1;
-            $self.index[$self.heading] = ($data & (0x000F << (i * 8))) as u8 >> (i * 8)
-        }
-
-        $self.heading += 1;
-    };
+/// A block device, seen a filesystem block at a time.
+pub struct Disk {
+    device: Arc<dyn BlockDevice>,
 }
 
-pub struct Filesystem {
-    index: [u8; FS_MAX_SIZE / 8],
-    fs: [u8; (FS_MAX_SIZE * 7) / 8],
-    heading: usize,
-    ptr: usize,
-}
-
-impl Default for Filesystem {
-    fn default() -> Self {
-        Self::new()
+impl Disk {
+    /// `None` if the device's sectors don't evenly divide a block.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Option<Self> {
+        let sector_size = device.sector_size();
+        (sector_size <= BLOCK_SIZE && BLOCK_SIZE / sector_size * sector_size == BLOCK_SIZE)
+            .then_some(Disk { device })
     }
-}
 
-impl Filesystem {
-    pub fn new() -> Self {
-        Self {
-            index: [0; FS_MAX_SIZE / 8],
-            fs: [0; (FS_MAX_SIZE * 7) / 8],
-            heading: 0,
-            ptr: 0,
-        }
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
     }
 
-    pub fn read_unit(&mut self) -> FileType {
-        self.heading = self.ptr;
-        while self.index[self.heading] != DATA_SEP {
-            self.heading += 1;
-        }
-
-        let end_of_label = self.heading;
-
-        self.heading += 1;
-        let filetype = self.index[self.heading];
-
-        let mut data = 0usize;
-
-        for i in 0..4 {
-            self.heading += 1;
-            data |= (self.index[self.heading] as usize) << (8 * i);
-        }
-
-        self.heading += 1;
+    fn sectors_per_block(&self) -> u64 {
+        (BLOCK_SIZE / self.device.sector_size()) as u64
+    }
+}
 
-        if filetype == TYPE_FILE {
-            FileType::Directory(Directory {
-                label: &self.fs[self.ptr..end_of_label],
-                data,
-            })
-        } else {
-            FileType::File(File {
-                label: &self.fs[self.ptr..end_of_label],
-                data,
-            })
-        }
+impl lateralfs::Disk for Disk {
+    fn block_count(&self) -> u64 {
+        self.device.sector_count() / self.sectors_per_block()
     }
 
-    pub fn forward(&mut self) {
-        self.ptr = self.heading;
+    fn read(&self, start: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let sector = start
+            .checked_mul(self.sectors_per_block())
+            .ok_or(FsError::Io)?;
+        self.device.read(sector, buf).map_err(|_| FsError::Io)
     }
 
-    pub fn write_unit(&mut self, file: FileType) {
-        match file {
-            FileType::Directory(dir) => {
-                write_unit_filesystem!(self type TYPE_DIR ... dir.label, dir.data);
-            }
-            FileType::File(file) => {
-                write_unit_filesystem!(self type TYPE_FILE ... file.label, file.data);
-            }
-        }
+    fn write(&self, start: u64, buf: &[u8]) -> Result<(), FsError> {
+        let sector = start
+            .checked_mul(self.sectors_per_block())
+            .ok_or(FsError::Io)?;
+        self.device.write(sector, buf).map_err(|_| FsError::Io)
     }
 }
+
+/// The block device registered as `name`, as a disk. `FsError::Io` if there's no such device or
+/// its sectors don't fit blocks.
+fn disk(name: &str) -> Result<Disk, FsError> {
+    block::find(name).and_then(Disk::new).ok_or(FsError::Io)
+}
+
+/// Mounts the filesystem on the block device registered as `name`.
+pub fn mount(name: &str) -> Result<Filesystem, FsError> {
+    Filesystem::mount(disk(name)?)
+}
+
+/// Writes an empty filesystem over the block device registered as `name`, and mounts it.
+pub fn format(name: &str) -> Result<Filesystem, FsError> {
+    let fs = Filesystem::format(disk(name)?)?;
+    fs.disk().device().flush().map_err(|_| FsError::Io)?;
+    Ok(fs)
+}
@@ -7,7 +7,6 @@ use spin::RwLock;
 
 use crate::gui::wm::{Desktop, Window};
 use crate::syscall::service::sleep;
-use crate::thread::yield_thread;
 
 use self::lgtk::widgets::header::Header;
 
@@ -74,7 +73,6 @@ pub fn terminal() {
         desktop.redraw();
         desktop.display();
         core::mem::drop(desktop);
-        yield_thread();
         sleep(0.016667);
     }
 
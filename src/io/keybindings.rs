@@ -68,8 +68,4 @@ pub fn handle_input(scancode: OsChar) {
     let formatted = input_mode.to_string();
     let ref_formatted = formatted.as_str();
     write_line!(ref_formatted, 0, 0);
-
-    while !crate::thread::ps2::SCANCODE_QUEUE.is_empty() {
-        crate::thread::ps2::SCANCODE_QUEUE.pop();
-    }
 }
@@ -10,41 +10,50 @@
 #![allow(redundant_semicolons)] // weird rust-analyzer bug
 
 use thread::queue::ThreadQueue;
+use thread::JoinHandle;
 use x86_64::instructions::port::Port;
 
 use crate::io::vga_buffer::{BgColor, FgColor};
 
+/// Kernel threads make syscalls with `syscall!(int 0x80; ...)`.
+pub use lateral_abi::syscall;
+
 extern crate alloc as rust_alloc;
 
+pub mod acpi;
 pub mod alloc;
+pub mod block;
 pub mod cpu;
 pub mod fs;
 pub mod gui;
 pub mod io;
+pub mod loader;
 pub mod mem;
+pub mod pci;
+pub mod process;
 pub mod syscall;
+pub mod task;
 pub mod test;
 pub mod thread;
 pub mod time;
 pub mod util;
 
-pub static mut THREAD_QUEUE: ThreadQueue = ThreadQueue::new();
+/// Threads spawned before the first `Runtime` was initialized.
+pub static THREAD_QUEUE: spin::Mutex<ThreadQueue> = spin::Mutex::new(ThreadQueue::new());
 
-pub fn spawn_thread(thread: fn()) {
-    unsafe {
-        #[allow(static_mut_refs)]
-        THREAD_QUEUE.push(thread);
-    }
+pub fn spawn_thread<F>(name: &str, thread: F) -> JoinHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    thread::spawn(name, thread)
 }
 
 const MESSAGE: &str = "Kernel Version 0.2.1";
 
 #[macro_export]
 macro_rules! exit {
-    ($id: expr, $code: literal) => {
-        $crate::future::task::RETURN_CODES
-            .lock()
-            .insert($id.clone(), $code);
+    ($code: expr) => {
+        $crate::thread::exit($code)
     };
 }
 
@@ -55,7 +64,9 @@ pub fn halt_loop() -> ! {
 }
 
 pub fn init() {
+    cpu::percpu::init(0);
     cpu::gdt::init();
+    cpu::syscall::init();
     cpu::interrupt::init_idt();
     unsafe { cpu::interrupt::PICS.lock().initialize() };
     x86_64::instructions::interrupts::enable();
@@ -0,0 +1,55 @@
+//! Programs built into the kernel image, found by path the way the command bar names them (e.g.
+//! `system/help`).
+
+use rust_alloc::sync::Arc;
+use rust_alloc::vec::Vec;
+
+use super::elf::{self, ElfError};
+use crate::process::Process;
+
+/// Built from `apps/` by the build script.
+static APPS: &[(&str, &[u8])] = &[
+    ("system/help", include_bytes!(env!("LATERAL_APP_HELP"))),
+    (
+        "system/hello-world",
+        include_bytes!(env!("LATERAL_APP_HELLO_WORLD")),
+    ),
+    ("system/lspci", include_bytes!(env!("LATERAL_APP_LSPCI"))),
+];
+
+#[derive(Debug)]
+pub enum SpawnError {
+    /// There is no program at that path.
+    NotFound,
+    Elf(ElfError),
+}
+
+impl From<ElfError> for SpawnError {
+    fn from(e: ElfError) -> Self {
+        SpawnError::Elf(e)
+    }
+}
+
+/// The executable at `path`.
+pub fn find(path: &str) -> Option<&'static [u8]> {
+    APPS.iter()
+        .find(|&&(app, _)| app == path)
+        .map(|&(_, data)| data)
+}
+
+/// The path of every built-in program.
+pub fn paths() -> impl Iterator<Item = &'static str> {
+    APPS.iter().map(|&(path, _)| path)
+}
+
+/// Starts the program at `path` in a new process, with `path` and then `args` as its arguments.
+/// The process is a child of the calling thread's process, if any.
+pub fn spawn(path: &str, args: &[&str]) -> Result<Arc<Process>, SpawnError> {
+    let data = find(path).ok_or(SpawnError::NotFound)?;
+    let argv: Vec<&str> = [path].into_iter().chain(args.iter().copied()).collect();
+    let program = elf::load(path, data, &argv, &[])?;
+    let process = program.process().clone();
+    // The process is waited on through the process table, not the thread.
+    drop(program.spawn());
+    Ok(process)
+}
@@ -0,0 +1,382 @@
+//! Statically linked ELF64 executables for x86_64.
+//!
+//! Every `PT_LOAD` segment is mapped into a fresh process with the page flags its header asks
+//! for, a stack is set up the way the System V ABI lays it out for `_start`, the program break is
+//! placed right above the highest segment, and a thread enters the program in ring 3.
+//!
+//! Programs are loaded from a byte slice; reading them from disk is up to the caller.
+
+use core::mem::size_of;
+
+use rust_alloc::collections::BTreeMap;
+use rust_alloc::string::ToString;
+use rust_alloc::sync::Arc;
+use rust_alloc::vec::Vec;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::cpu::user::enter_user_mode;
+use crate::mem::space::{USER_END, USER_START};
+use crate::process::{MapError, Process};
+use crate::thread::JoinHandle;
+
+const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3E;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+/// The top of every program's stack, at the very end of the user half.
+pub const STACK_TOP: u64 = USER_END;
+pub const STACK_SIZE: u64 = 4096 * 16;
+
+#[derive(Debug)]
+pub enum ElfError {
+    /// Shorter than the headers it claims to have.
+    Truncated,
+    /// Doesn't start with the ELF magic.
+    NotElf,
+    /// Not a little-endian, 64 bit, current version ELF file.
+    Unsupported,
+    /// Not an executable (e.g. a relocatable object or a shared library).
+    NotExecutable,
+    /// Built for a machine other than x86_64.
+    WrongMachine,
+    /// A segment's file contents lie past the end of the file, or it's larger in the file than
+    /// in memory.
+    BadSegment,
+    /// A segment or the entry point lies outside the user half.
+    OutOfBounds,
+    /// `argv` and `envp` don't fit on the stack.
+    ArgumentsTooLong,
+    Map(MapError),
+}
+
+impl From<MapError> for ElfError {
+    fn from(e: MapError) -> Self {
+        ElfError::Map(e)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for ElfError {
+    fn from(_: MapToError<Size4KiB>) -> Self {
+        ElfError::Map(MapError::OutOfMemory)
+    }
+}
+
+/// A loadable segment, from a `PT_LOAD` program header.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub offset: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl Segment {
+    fn flags(&self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT;
+        if self.writable {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !self.executable {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+}
+
+/// A validated ELF executable.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    entry: u64,
+    segments: Vec<Segment>,
+}
+
+impl<'a> Elf<'a> {
+    /// Checks the headers of `data` and every loadable segment in it.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[0..4] != MAGIC {
+            return Err(ElfError::NotElf);
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LITTLE_ENDIAN || data[6] != VERSION_CURRENT {
+            return Err(ElfError::Unsupported);
+        }
+        if read_u16(data, 16) != TYPE_EXEC {
+            return Err(ElfError::NotExecutable);
+        }
+        if read_u16(data, 18) != MACHINE_X86_64 {
+            return Err(ElfError::WrongMachine);
+        }
+
+        let entry = read_u64(data, 24);
+        let ph_offset = read_u64(data, 32) as usize;
+        let ph_size = read_u16(data, 54) as usize;
+        let ph_count = read_u16(data, 56) as usize;
+        if ph_size < PROGRAM_HEADER_SIZE {
+            return Err(ElfError::Unsupported);
+        }
+        let ph_end = ph_size
+            .checked_mul(ph_count)
+            .and_then(|len| len.checked_add(ph_offset));
+        if ph_end.is_none_or(|end| end > data.len()) {
+            return Err(ElfError::Truncated);
+        }
+
+        let mut segments = Vec::new();
+        for index in 0..ph_count {
+            let header = &data[ph_offset + index * ph_size..];
+            if read_u32(header, 0) != PT_LOAD {
+                continue;
+            }
+
+            let flags = read_u32(header, 4);
+            let segment = Segment {
+                offset: read_u64(header, 8),
+                vaddr: read_u64(header, 16),
+                file_size: read_u64(header, 32),
+                mem_size: read_u64(header, 40),
+                writable: flags & PF_W != 0,
+                executable: flags & PF_X != 0,
+            };
+
+            let file_end = segment.offset.checked_add(segment.file_size);
+            if segment.file_size > segment.mem_size
+                || file_end.is_none_or(|end| end > data.len() as u64)
+            {
+                return Err(ElfError::BadSegment);
+            }
+            let mem_end = segment.vaddr.checked_add(segment.mem_size);
+            if segment.vaddr < USER_START || mem_end.is_none_or(|end| end > USER_END) {
+                return Err(ElfError::OutOfBounds);
+            }
+            if segment.mem_size != 0 {
+                segments.push(segment);
+            }
+        }
+
+        let runnable = segments.iter().any(|segment| {
+            segment.executable && (segment.vaddr..segment.vaddr + segment.mem_size).contains(&entry)
+        });
+        if !runnable {
+            return Err(ElfError::OutOfBounds);
+        }
+
+        Ok(Elf {
+            data,
+            entry,
+            segments,
+        })
+    }
+
+    pub fn entry(&self) -> VirtAddr {
+        VirtAddr::new(self.entry)
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Maps every segment into a new process named `name` and sets up its stack, without
+    /// starting it.
+    pub fn load(&self, name: &str, argv: &[&str], envp: &[&str]) -> Result<Program, ElfError> {
+        let arguments = Arguments::measure(argv, envp)?;
+        let process = Process::new(name)?;
+        // The process is already in the process table, and without threads it never leaves it
+        // unless it's killed.
+        match self.populate(&process, &arguments, argv, envp) {
+            Ok(stack) => Ok(Program {
+                process,
+                entry: self.entry(),
+                stack,
+            }),
+            Err(err) => {
+                process.kill();
+                Err(err)
+            }
+        }
+    }
+
+    /// Maps the segments and the stack into `process`. Returns the initial stack pointer.
+    fn populate(
+        &self,
+        process: &Process,
+        arguments: &Arguments,
+        argv: &[&str],
+        envp: &[&str],
+    ) -> Result<VirtAddr, ElfError> {
+        // Segments may share a page at their edges; such a page gets the union of their flags.
+        let mut pages: BTreeMap<Page, PageTableFlags> = BTreeMap::new();
+        for segment in &self.segments {
+            let first = Page::containing_address(VirtAddr::new(segment.vaddr));
+            let last =
+                Page::containing_address(VirtAddr::new(segment.vaddr + segment.mem_size - 1));
+            for page in Page::range_inclusive(first, last) {
+                let flags = pages.entry(page).or_insert(PageTableFlags::NO_EXECUTE);
+                let wanted = segment.flags();
+                *flags |= wanted & PageTableFlags::WRITABLE;
+                if !wanted.contains(PageTableFlags::NO_EXECUTE) {
+                    flags.remove(PageTableFlags::NO_EXECUTE);
+                }
+            }
+        }
+        // Map runs of neighbouring pages with the same flags as one area each.
+        let mut pages = pages.into_iter().peekable();
+        while let Some((first, flags)) = pages.next() {
+            let mut end = first + 1;
+            while pages
+                .next_if(|&(page, next)| page == end && next == flags)
+                .is_some()
+            {
+                end += 1;
+            }
+            process.map(Page::range(first, end), flags)?;
+        }
+
+        // Fresh frames are already zeroed, which takes care of `.bss`.
+        for segment in &self.segments {
+            let start = segment.offset as usize;
+            let contents = &self.data[start..start + segment.file_size as usize];
+            let copied = process.write(VirtAddr::new(segment.vaddr), contents);
+            debug_assert!(copied, "a segment's pages were just mapped.");
+        }
+
+        // The heap starts on the first page above the highest segment and may grow up to the
+        // stack.
+        let image_end = self
+            .segments
+            .iter()
+            .map(|segment| segment.vaddr + segment.mem_size)
+            .max()
+            .unwrap_or(USER_START);
+        process.init_break(
+            VirtAddr::new(image_end).align_up(4096u64),
+            VirtAddr::new(STACK_TOP - STACK_SIZE),
+        );
+
+        setup_stack(process, arguments, argv, envp)
+    }
+}
+
+/// A program loaded into its own process, ready to run.
+pub struct Program {
+    process: Arc<Process>,
+    entry: VirtAddr,
+    stack: VirtAddr,
+}
+
+impl Program {
+    pub fn process(&self) -> &Arc<Process> {
+        &self.process
+    }
+
+    pub fn entry(&self) -> VirtAddr {
+        self.entry
+    }
+
+    /// Starts the program on a new thread of its process, which enters it in ring 3.
+    pub fn spawn(self) -> JoinHandle {
+        let (entry, stack) = (self.entry, self.stack);
+        let name = self.process.name().to_string();
+        self.process
+            .spawn(&name, move || unsafe { enter_user_mode(entry, stack) })
+    }
+}
+
+/// Parses `data` and loads it into a new process named `name`. Call [`Program::spawn`] to run
+/// it.
+pub fn load(name: &str, data: &[u8], argv: &[&str], envp: &[&str]) -> Result<Program, ElfError> {
+    Elf::parse(data)?.load(name, argv, envp)
+}
+
+/// Adds the stack, backed as it grows, and lays out what `_start` expects at its stack pointer:
+/// `argc`, the `argv` pointers, a null, the `envp` pointers, a null, and an empty auxiliary
+/// vector, with the strings themselves above. Returns the initial stack pointer, which is 16 byte
+/// aligned.
+/// How much of the stack a program's arguments and environment take up.
+#[derive(Clone, Copy)]
+struct Arguments {
+    /// Bytes of strings, each with its NUL.
+    strings_len: usize,
+    /// argc, both arrays with their nulls, and the AT_NULL auxiliary entry.
+    words: usize,
+}
+
+impl Arguments {
+    fn measure(argv: &[&str], envp: &[&str]) -> Result<Self, ElfError> {
+        let strings_len: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+        let words = 1 + argv.len() + 1 + envp.len() + 1 + 2;
+        // Leave at least half the stack to the program itself.
+        let needed = (strings_len + words * size_of::<u64>()) as u64 + 16;
+        if needed > STACK_SIZE / 2 {
+            return Err(ElfError::ArgumentsTooLong);
+        }
+        Ok(Arguments { strings_len, words })
+    }
+}
+
+fn setup_stack(
+    process: &Process,
+    arguments: &Arguments,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<VirtAddr, ElfError> {
+    process.map_lazy(
+        Some(VirtAddr::new(STACK_TOP - STACK_SIZE)),
+        STACK_SIZE,
+        PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    )?;
+
+    let Arguments { strings_len, words } = *arguments;
+    let string_addr = STACK_TOP - strings_len as u64;
+    let mut strings = Vec::with_capacity(strings_len);
+    let mut vector = Vec::with_capacity(words);
+    vector.push(argv.len() as u64);
+    for list in [argv, envp] {
+        for s in list {
+            vector.push(string_addr + strings.len() as u64);
+            strings.extend_from_slice(s.as_bytes());
+            strings.push(0);
+        }
+        vector.push(0);
+    }
+    vector.extend([0, 0]);
+
+    let sp = (string_addr - (words * size_of::<u64>()) as u64) & !0xF;
+    let vector: Vec<u8> = vector.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let written = process.write(VirtAddr::new(string_addr), &strings)
+        && process.write(VirtAddr::new(sp), &vector);
+    debug_assert!(written, "the stack was just mapped.");
+
+    Ok(VirtAddr::new(sp))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
@@ -0,0 +1,4 @@
+//! Loading programs into processes of their own.
+
+pub mod apps;
+pub mod elf;
@@ -13,31 +13,38 @@ bootloader::entry_point!(kernel::main);
 // #[cfg(not(test))]
 mod kernel {
     extern crate alloc as rust_alloc;
+    use lateral::block;
+    use lateral::cpu::smp;
     use lateral::gui::terminal;
-    use lateral::io::logging::kernel_fatal;
-    use lateral::mem::frame::BootInfoFrameAllocator;
-    use lateral::mem::paging;
+    use lateral::io::logging::{kernel_error, kernel_fatal, kernel_info};
+    use lateral::pci;
+    use lateral::task::executor::Executor;
+    use lateral::task::{keyboard, Task};
     use lateral::thread::ps2::init_ps2;
-    use lateral::thread::Runtime;
+    use lateral::thread::{Priority, Runtime};
     use rust_alloc::format;
-    use x86_64::VirtAddr;
 
     pub fn main(boot_info: &'static bootloader::BootInfo) -> ! {
         lateral::init();
-
-        let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-        let mut mapper = unsafe { paging::init(phys_mem_offset) };
-        let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-
-        lateral::alloc::heap::init_heap(&mut mapper, &mut frame_allocator)
-            .expect("heap initialization failed");
+        lateral::mem::init(boot_info);
 
         init_ps2();
 
         let mut runtime = Runtime::new();
 
         runtime.init();
-        runtime.spawn(terminal);
+        kernel_info(format!("pci: {} functions", pci::init()).as_str());
+        block::ata::init();
+        block::virtio::init();
+        if let Err(e) = smp::init() {
+            kernel_error(format!("Couldn't start the other CPUs: {:?}", e).as_str());
+        }
+        runtime.spawn_with_priority("terminal", Priority::Interactive, terminal);
+        runtime.spawn_with_priority("input", Priority::Interactive, || {
+            let mut executor = Executor::new();
+            executor.spawn(Task::new(keyboard::handle_keypresses()));
+            executor.run();
+        });
         runtime.run();
     }
 
@@ -1,39 +1,198 @@
+use core::mem::size_of;
+
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
-use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
-use x86_64::PhysAddr;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The kernel's frame allocator, installed by `mem::init`.
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
 
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
+const FRAME_SIZE: u64 = 4096;
+const BITS: usize = u64::BITS as usize;
+
+/// How many of the usable frames of physical memory are handed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+}
+
+/// A FrameAllocator that keeps one bit per frame of physical memory, set while the frame is in
+/// use or isn't usable at all.
+///
+/// The bitmaps themselves live in the first usable region large enough to hold them, reached
+/// through the physical memory mapping, so they need no heap.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    /// One bit per frame, set if it's usable memory the allocator hands out. Never changes after
+    /// `init`, so frees of anything else can be told apart from frees of free frames.
+    usable: &'static [u64],
+    total: usize,
+    free: usize,
+    /// No word before this one has a clear bit.
     next: usize,
 }
 
-impl BootInfoFrameAllocator {
+impl BitmapFrameAllocator {
     /// Create a FrameAllocator from the passed memory map.
     ///
     /// # Safety
-    /// Caller must guarantee that the passed memory map is valid.
+    /// Caller must guarantee that the passed memory map is valid and that all of physical memory
+    /// is mapped at `physical_memory_offset`.
     /// All frames marked as `USABLE` must stay unused.
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+                .map(|r| r.range.start_frame_number as usize..r.range.end_frame_number as usize)
+        };
+        let frames = usable().map(|r| r.end).max().unwrap_or(0);
+        let words = frames.div_ceil(BITS);
+        let bitmap_frames = (2 * words * size_of::<u64>()).div_ceil(FRAME_SIZE as usize);
+
+        let home = usable()
+            .find(|r| r.len() >= bitmap_frames)
+            .expect("no usable region can hold the frame bitmaps.");
+        let (bitmap, usable_bitmap) = core::slice::from_raw_parts_mut(
+            (physical_memory_offset + home.start as u64 * FRAME_SIZE).as_mut_ptr::<u64>(),
+            2 * words,
+        )
+        .split_at_mut(words);
+        bitmap.fill(u64::MAX);
+        usable_bitmap.fill(0);
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            usable: &[],
+            total: 0,
+            free: 0,
             next: 0,
+        };
+        for region in usable() {
+            for frame in region {
+                allocator.clear(frame);
+                usable_bitmap[frame / BITS] |= 1 << (frame % BITS);
+            }
         }
+        allocator.total = allocator.free;
+        // The bitmaps' own frames are never handed out, so they can't be freed either.
+        for frame in home.start..home.start + bitmap_frames {
+            allocator.set(frame);
+            usable_bitmap[frame / BITS] &= !(1 << (frame % BITS));
+        }
+        allocator.usable = usable_bitmap;
+        allocator
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            used: self.total - self.free,
+            free: self.free,
+        }
+    }
+
+    /// Hands out `count` physically contiguous frames, e.g. for a device to reach with DMA.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrameRange> {
+        if count == 0 {
+            return None;
+        }
+        let frames = self.bitmap.len() * BITS;
+        let is_free = |frame: usize| self.bitmap[frame / BITS] & 1 << (frame % BITS) == 0;
+
+        let mut start = self.next * BITS;
+        while start + count <= frames {
+            match (start..start + count).rev().find(|&frame| !is_free(frame)) {
+                // No run starting at or before the frame in use fits.
+                Some(used) => start = used + 1,
+                None => {
+                    for frame in start..start + count {
+                        self.set(frame);
+                    }
+                    let first =
+                        PhysFrame::containing_address(PhysAddr::new(start as u64 * FRAME_SIZE));
+                    return Some(PhysFrame::range(first, first + count as u64));
+                }
+            }
+        }
+        None
+    }
+
+    fn set(&mut self, frame: usize) {
+        let (word, bit) = (frame / BITS, frame % BITS);
+        assert!(
+            self.bitmap[word] & 1 << bit == 0,
+            "frame {:#x} is already in use.",
+            frame as u64 * FRAME_SIZE
+        );
+        self.bitmap[word] |= 1 << bit;
+        self.free -= 1;
+    }
+
+    fn clear(&mut self, frame: usize) {
+        let (word, bit) = (frame / BITS, frame % BITS);
+        assert!(
+            self.bitmap[word] & 1 << bit != 0,
+            "frame {:#x} is already free.",
+            frame as u64 * FRAME_SIZE
+        );
+        self.bitmap[word] &= !(1 << bit);
+        self.free += 1;
+        self.next = self.next.min(word);
     }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        let word = self.next
+            + self.bitmap[self.next..]
+                .iter()
+                .position(|&w| w != u64::MAX)?;
+        self.next = word;
+        let frame = word * BITS + self.bitmap[word].trailing_ones() as usize;
+        self.set(frame);
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            frame as u64 * FRAME_SIZE,
+        )))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    /// Frees of frames that aren't usable memory, e.g. a device's MMIO, are ignored.
+    ///
+    /// # Panics
+    /// If `frame` is usable but isn't in use, i.e. on a double free. Debug builds also panic on
+    /// frees of frames that aren't usable.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let frame = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        let usable = self
+            .usable
+            .get(frame / BITS)
+            .is_some_and(|word| word & 1 << (frame % BITS) != 0);
+        if !usable {
+            debug_assert!(
+                false,
+                "frame {:#x} isn't usable memory.",
+                frame as u64 * FRAME_SIZE
+            );
+            return;
+        }
+        self.clear(frame);
     }
 }
+
+/// How many frames the kernel's frame allocator has handed out.
+pub fn stats() -> FrameStats {
+    without_interrupts(|| {
+        FRAME_ALLOCATOR
+            .lock()
+            .as_ref()
+            .expect("frame allocator is not initialized")
+            .stats()
+    })
+}
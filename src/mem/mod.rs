@@ -1,2 +1,24 @@
 pub mod frame;
 pub mod paging;
+pub mod space;
+pub mod tlb;
+
+use bootloader::BootInfo;
+use x86_64::VirtAddr;
+
+use self::frame::BitmapFrameAllocator;
+
+/// Sets up paging and the kernel heap, then hands the page tables and frame allocator over to
+/// [`paging::MAPPER`] and [`frame::FRAME_ALLOCATOR`] for the rest of the kernel to use.
+pub fn init(boot_info: &'static BootInfo) {
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { paging::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+
+    crate::alloc::heap::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+
+    *paging::MAPPER.lock() = Some(mapper);
+    *frame::FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+}
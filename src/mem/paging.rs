@@ -1,13 +1,27 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::MapToError;
 use x86_64::structures::paging::{
-    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
 };
 use x86_64::{PhysAddr, VirtAddr};
 
+use super::frame::FRAME_ALLOCATOR;
+
+/// The kernel's active page tables, installed by `mem::init`.
+pub static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+
+/// Where the bootloader mapped all of physical memory, recorded by `init`.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Physical address of the kernel's own level 4 table, recorded by `init`.
+static KERNEL_TABLE: AtomicU64 = AtomicU64::new(0);
+
 /// # Safety
 /// good luck
 pub unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
-    use x86_64::registers::control::Cr3;
-
     let (level_4_table_frame, _) = Cr3::read();
 
     let phys = level_4_table_frame.start_address();
@@ -18,7 +32,6 @@ pub unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static
 }
 
 pub fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
-    use x86_64::registers::control::Cr3;
     use x86_64::structures::paging::page_table::FrameError;
 
     let (level_4_table_frame, _) = Cr3::read();
@@ -50,6 +63,9 @@ pub fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Optio
 /// # Safety
 /// Caller must guarantee that `physical_memory_offset` is correct.
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Release);
+    KERNEL_TABLE.store(Cr3::read().0.start_address().as_u64(), Ordering::Release);
+
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
@@ -67,3 +83,61 @@ pub fn create_example_mapping(
     let map_to_result = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
     map_to_result.expect("map_to failed").flush();
 }
+
+/// Backs `page` with a fresh frame from the kernel's frame allocator.
+pub fn map_page(page: Page, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let mapper = mapper.as_mut().expect("paging is not initialized");
+        let frame_allocator = frame_allocator
+            .as_mut()
+            .expect("frame allocator is not initialized");
+
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        Ok(())
+    })
+}
+
+/// Maps `page` to a specific physical `frame`, e.g. for memory-mapped device registers.
+pub fn map_physical(
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let mapper = mapper.as_mut().expect("paging is not initialized");
+        let frame_allocator = frame_allocator
+            .as_mut()
+            .expect("frame allocator is not initialized");
+
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        Ok(())
+    })
+}
+
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Acquire))
+}
+
+/// The level 4 table the kernel booted with, which kernel threads run on.
+pub fn kernel_table() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_TABLE.load(Ordering::Acquire)))
+}
+
+/// Loads `table` into `CR3` unless it's already active, which would needlessly flush the TLB.
+///
+/// # Safety
+/// `table` must map the kernel half the same way the kernel's own table does.
+pub unsafe fn activate(table: PhysFrame) {
+    let (active, flags) = Cr3::read();
+    if active != table {
+        crate::cpu::percpu::current().set_address_space(table);
+        Cr3::write(table, flags);
+    }
+}
@@ -0,0 +1,341 @@
+//! Address spaces, each with a level 4 table of its own.
+//!
+//! The kernel half of every address space points at the same lower-level tables as the kernel's
+//! own, so code, the heap and thread stacks are mapped identically everywhere. Level 4 entries
+//! `USER_START..USER_END` are left out and belong to the address space alone.
+
+use core::ops::Range;
+
+use rust_alloc::vec::Vec;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::mapper::{
+    CleanUp, FlagUpdateError, MapToError, TranslateResult, UnmapError,
+};
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::frame::{BitmapFrameAllocator, FRAME_ALLOCATOR};
+use super::{paging, tlb};
+
+/// First address of the private user half.
+pub const USER_START: u64 = 0x0000_0800_0000_0000;
+/// End of the private user half, exclusive. Everything past it is kernel.
+pub const USER_END: u64 = 0x0000_4000_0000_0000;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Level 4 entries covering the user half.
+const USER_ENTRIES: Range<usize> = (USER_START >> 39) as usize..(USER_END >> 39) as usize;
+
+pub struct AddressSpace {
+    table: PhysFrame,
+}
+
+impl AddressSpace {
+    /// Creates an address space sharing the kernel half of the active kernel table, with nothing
+    /// mapped in the user half.
+    ///
+    /// Kernel mappings added later show up in every address space as long as they fall under a
+    /// level 4 entry that already existed when it was created.
+    pub fn new() -> Result<Self, MapToError<Size4KiB>> {
+        let table = without_interrupts(|| {
+            FRAME_ALLOCATOR
+                .lock()
+                .as_mut()
+                .expect("frame allocator is not initialized")
+                .allocate_frame()
+        })
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+        let kernel = unsafe { &*table_ptr(paging::kernel_table()) };
+        let private = unsafe { &mut *table_ptr(table) };
+        private.zero();
+        for (index, entry) in kernel.iter().enumerate() {
+            if !USER_ENTRIES.contains(&index) {
+                private[index] = entry.clone();
+            }
+        }
+
+        Ok(AddressSpace { table })
+    }
+
+    /// The level 4 table to load into `CR3`.
+    pub fn table(&self) -> PhysFrame {
+        self.table
+    }
+
+    /// Backs `pages` with fresh, zeroed frames. `USER_ACCESSIBLE` is added to `flags`.
+    ///
+    /// Pages mapped before a failure stay mapped.
+    ///
+    /// # Panics
+    /// If `pages` reaches outside the user half.
+    pub fn map(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert_user(pages);
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let parent_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+        without_interrupts(|| {
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator
+                .as_mut()
+                .expect("frame allocator is not initialized");
+            let mut mapper = self.mapper();
+
+            for page in pages {
+                let frame = frame_allocator
+                    .allocate_frame()
+                    .ok_or(MapToError::FrameAllocationFailed)?;
+                unsafe {
+                    let data = paging::physical_memory_offset() + frame.start_address().as_u64();
+                    data.as_mut_ptr::<u8>()
+                        .write_bytes(0, frame.size() as usize);
+                    mapper
+                        .map_to_with_table_flags(page, frame, flags, parent_flags, frame_allocator)?
+                        .flush();
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Unmaps whichever of `pages` are mapped, along with any page table left empty. The frames
+    /// behind them are freed by [`Stale::flush`] once no CPU's TLB can reach them anymore.
+    ///
+    /// Pages unmapped before an error are stale all the same.
+    ///
+    /// # Panics
+    /// If `pages` reaches outside the user half.
+    pub fn unmap(&mut self, pages: PageRange<Size4KiB>) -> (Stale, Result<(), UnmapError>) {
+        assert_user(pages);
+        let mut stale = Stale {
+            table: self.table,
+            pages,
+            frames: Vec::new(),
+        };
+        if pages.is_empty() {
+            return (stale, Ok(()));
+        }
+
+        let mut mapper = self.mapper();
+        for page in pages {
+            match mapper.unmap(page) {
+                // Flushed along with every other CPU's TLB.
+                Ok((frame, flush)) => {
+                    flush.ignore();
+                    stale.frames.push(frame);
+                }
+                Err(UnmapError::PageNotMapped) => {}
+                Err(e) => return (stale, Err(e)),
+            }
+        }
+        // User half tables belong to this address space alone.
+        unsafe {
+            mapper.clean_up_addr_range(
+                Page::range_inclusive(pages.start, pages.end - 1),
+                &mut Deferred(&mut stale.frames),
+            )
+        };
+        (stale, Ok(()))
+    }
+
+    /// Replaces the flags of whichever of `pages` are mapped. `PRESENT` and `USER_ACCESSIBLE`
+    /// are kept. CPUs may go on using the old flags until the result is flushed.
+    ///
+    /// # Panics
+    /// If `pages` reaches outside the user half.
+    pub fn protect(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> (Stale, Result<(), FlagUpdateError>) {
+        assert_user(pages);
+        let stale = Stale {
+            table: self.table,
+            pages,
+            frames: Vec::new(),
+        };
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let mut mapper = self.mapper();
+        for page in pages {
+            match unsafe { mapper.update_flags(page, flags) } {
+                Ok(flush) => flush.ignore(),
+                Err(FlagUpdateError::PageNotMapped) => {}
+                Err(e) => return (stale, Err(e)),
+            }
+        }
+        (stale, Ok(()))
+    }
+
+    /// The flags of the page `addr` is in, if it's mapped.
+    pub fn flags(&mut self, addr: VirtAddr) -> Option<PageTableFlags> {
+        match self.mapper().translate(addr) {
+            TranslateResult::Mapped { flags, .. } => Some(flags),
+            _ => None,
+        }
+    }
+
+    /// The physical address `addr` maps to in this address space, active or not.
+    pub fn translate(&mut self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.mapper().translate_addr(addr)
+    }
+
+    /// Copies `bytes` to `addr` through the physical memory mapping, so it works on an address
+    /// space that isn't active and ignores page flags. Returns false, having copied a prefix, if
+    /// the range runs into an unmapped page.
+    pub fn write(&mut self, addr: VirtAddr, bytes: &[u8]) -> bool {
+        self.copy(addr, bytes.len(), |data, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(bytes[offset..].as_ptr(), data, len)
+        })
+    }
+
+    /// Copies from `addr` into `buf` the same way `write` copies out.
+    pub fn read(&mut self, addr: VirtAddr, buf: &mut [u8]) -> bool {
+        self.copy(addr, buf.len(), |data, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(data, buf[offset..].as_mut_ptr(), len)
+        })
+    }
+
+    /// Calls `f` with the kernel's pointer to each page-bounded piece of `addr..addr + len`, and
+    /// its offset into the range.
+    fn copy(
+        &mut self,
+        addr: VirtAddr,
+        len: usize,
+        mut f: impl FnMut(*mut u8, usize, usize),
+    ) -> bool {
+        let mut offset = 0;
+        while offset < len {
+            let addr = addr + offset;
+            let Some(phys) = self.translate(addr) else {
+                return false;
+            };
+            let chunk = (PAGE_SIZE - usize::from(addr.page_offset())).min(len - offset);
+            f(
+                (paging::physical_memory_offset() + phys.as_u64()).as_mut_ptr(),
+                offset,
+                chunk,
+            );
+            offset += chunk;
+        }
+        true
+    }
+
+    /// Loads this address space on the calling CPU.
+    pub fn activate(&self) {
+        unsafe { paging::activate(self.table) };
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        unsafe {
+            OffsetPageTable::new(
+                &mut *table_ptr(self.table),
+                paging::physical_memory_offset(),
+            )
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees every frame mapped in the user half, the tables mapping them and the level 4 table.
+    /// The address space must not be active on any CPU anymore.
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator
+                .as_mut()
+                .expect("frame allocator is not initialized");
+
+            let table = unsafe { &*table_ptr(self.table) };
+            for index in USER_ENTRIES {
+                if let Ok(frame) = table[index].frame() {
+                    unsafe { free_table(frame, 3, frame_allocator) };
+                }
+            }
+            unsafe { frame_allocator.deallocate_frame(self.table) };
+        });
+    }
+}
+
+/// Pages of an address space whose mappings were removed or changed, which CPUs may still have
+/// in their TLBs, and the frames that were behind them.
+#[must_use = "stale pages have to be flushed, and their frames freed"]
+pub struct Stale {
+    table: PhysFrame,
+    pages: PageRange<Size4KiB>,
+    frames: Vec<PhysFrame>,
+}
+
+impl Stale {
+    /// Flushes the pages from the TLB of every CPU that has the address space loaded, then
+    /// frees the frames.
+    ///
+    /// Waits on the other CPUs, so nothing they may be spinning on may be locked: neither the
+    /// frame allocator nor the process the address space belongs to. See [`tlb`].
+    pub fn flush(self) {
+        tlb::shootdown(self.table, self.pages);
+        if self.frames.is_empty() {
+            return;
+        }
+        without_interrupts(|| {
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator
+                .as_mut()
+                .expect("frame allocator is not initialized");
+            for frame in self.frames {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        });
+    }
+}
+
+/// Collects the page tables `clean_up_addr_range` empties, to be freed with the rest.
+struct Deferred<'a>(&'a mut Vec<PhysFrame>);
+
+impl FrameDeallocator<Size4KiB> for Deferred<'_> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.0.push(frame);
+    }
+}
+
+/// Frees `table`, a page table of the given level, and every frame below it.
+///
+/// # Safety
+/// Nothing may use any of those frames anymore.
+unsafe fn free_table(table: PhysFrame, level: u8, frame_allocator: &mut BitmapFrameAllocator) {
+    for entry in (*table_ptr(table)).iter() {
+        // Huge pages are never mapped in the user half, so `frame` only skips unused entries.
+        if let Ok(frame) = entry.frame() {
+            if level > 1 {
+                free_table(frame, level - 1, frame_allocator);
+            } else {
+                frame_allocator.deallocate_frame(frame);
+            }
+        }
+    }
+    frame_allocator.deallocate_frame(table);
+}
+
+fn table_ptr(frame: PhysFrame) -> *mut PageTable {
+    (paging::physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+fn assert_user(pages: PageRange<Size4KiB>) {
+    let start = pages.start.start_address().as_u64();
+    let end = pages.end.start_address().as_u64();
+    assert!(
+        USER_START <= start && end <= USER_END,
+        "{:#x}..{:#x} is outside the user half.",
+        start,
+        end
+    );
+}
@@ -0,0 +1,115 @@
+//! Keeping every CPU's TLB in step with an address space whose mappings were removed or changed.
+//!
+//! Each CPU records the level 4 table it has loaded before writing it to `CR3`. A shootdown
+//! flushes the changed pages on every CPU that has the address space loaded: the calling CPU
+//! flushes its own, and the others get `TLB_SHOOTDOWN_VECTOR` and are waited on until they have
+//! flushed theirs. A CPU that loads another table drops the user half from its TLB anyway, as
+//! none of it is global. The kernel half is shared by every address space, so changes to it are
+//! flushed on every CPU.
+//!
+//! One shootdown runs at a time. Until it's done its initiator spins with interrupts disabled, so
+//! it mustn't hold any lock the others could be spinning on with theirs disabled, or it waits
+//! forever on a CPU that can't take the interrupt.
+
+use core::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::tlb;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::cpu::apic::{self, Destination};
+use crate::cpu::percpu::{self, PerCpu};
+
+/// Past this many pages a CPU reloads `CR3` rather than flushing them one by one.
+const FLUSH_ALL_PAGES: u64 = 32;
+
+/// Held by the CPU whose shootdown is under way.
+static BUSY: AtomicBool = AtomicBool::new(false);
+/// The pages to flush, set while `BUSY` is held and before `PENDING`.
+static START: AtomicU64 = AtomicU64::new(0);
+static END: AtomicU64 = AtomicU64::new(0);
+/// The CPUs that have yet to flush them, a bit each.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Flushes `pages` from the TLB of every CPU that has `table` loaded, this one included, and
+/// returns once they all have. `table`'s entries for `pages` must already be changed.
+pub fn shootdown(table: PhysFrame, pages: PageRange<Size4KiB>) {
+    shootdown_where(pages, |cpu| cpu.address_space() == table);
+}
+
+/// Flushes `pages`, which must be in the kernel half, from every CPU's TLB and returns once they
+/// all have.
+pub fn shootdown_kernel(pages: PageRange<Size4KiB>) {
+    shootdown_where(pages, |_| true);
+}
+
+/// Flushes `pages` on every CPU `affected` picks out.
+fn shootdown_where(pages: PageRange<Size4KiB>, affected: impl Fn(&PerCpu) -> bool) {
+    if pages.is_empty() {
+        return;
+    }
+
+    without_interrupts(|| {
+        // Pairs with the store in `set_address_space`: a CPU either shows up here with the table
+        // loaded, or it loads the table after the changed entries are visible to its page walks.
+        fence(Ordering::SeqCst);
+        let this = percpu::id();
+        let mut targets = 0;
+        for cpu in percpu::online() {
+            if affected(cpu) {
+                if cpu.id() == this {
+                    flush(pages);
+                } else {
+                    targets |= 1 << cpu.id();
+                }
+            }
+        }
+        if targets == 0 {
+            return;
+        }
+
+        // Another CPU's shootdown may be waiting on this one.
+        while BUSY
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            serve();
+            core::hint::spin_loop();
+        }
+        START.store(pages.start.start_address().as_u64(), Ordering::Relaxed);
+        END.store(pages.end.start_address().as_u64(), Ordering::Relaxed);
+        PENDING.store(targets, Ordering::Release);
+        for cpu in percpu::online().filter(|cpu| targets & 1 << cpu.id() != 0) {
+            apic::send_interrupt(Destination::Apic(cpu.apic_id()), apic::TLB_SHOOTDOWN_VECTOR);
+        }
+        while PENDING.load(Ordering::Acquire) != 0 {
+            core::hint::spin_loop();
+        }
+        BUSY.store(false, Ordering::Release);
+    });
+}
+
+/// Flushes the pages of the shootdown under way, if the calling CPU is one it waits on. Called
+/// on `TLB_SHOOTDOWN_VECTOR`.
+pub fn serve() {
+    let bit = 1 << percpu::id();
+    // The request can't change until this CPU clears its bit.
+    if PENDING.load(Ordering::Acquire) & bit != 0 {
+        let start = Page::containing_address(VirtAddr::new(START.load(Ordering::Relaxed)));
+        let end = Page::containing_address(VirtAddr::new(END.load(Ordering::Relaxed)));
+        flush(Page::range(start, end));
+        PENDING.fetch_and(!bit, Ordering::Release);
+    }
+}
+
+fn flush(pages: PageRange<Size4KiB>) {
+    if pages.end - pages.start > FLUSH_ALL_PAGES {
+        tlb::flush_all();
+    } else {
+        for page in pages {
+            tlb::flush(page.start_address());
+        }
+    }
+}
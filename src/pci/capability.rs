@@ -0,0 +1,116 @@
+//! The capability list in a function's configuration space, which says what optional features,
+//! like message signaled interrupts, it has.
+
+use rust_alloc::vec::Vec;
+
+use super::Address;
+
+const STATUS: u8 = 0x06;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const CAPABILITIES_POINTER: u8 = 0x34;
+/// More entries than fit in configuration space means the list loops.
+const MAX_CAPABILITIES: usize = 48;
+
+pub const ID_MSI: u8 = 0x05;
+pub const ID_VENDOR: u8 = 0x09;
+pub const ID_PCI_EXPRESS: u8 = 0x10;
+pub const ID_MSI_X: u8 = 0x11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Msi(Msi),
+    MsiX(MsiX),
+    /// Anything this doesn't decode, by its id and where it starts.
+    Other {
+        id: u8,
+        offset: u8,
+    },
+}
+
+impl Capability {
+    pub fn id(&self) -> u8 {
+        match self {
+            Capability::Msi(_) => ID_MSI,
+            Capability::MsiX(_) => ID_MSI_X,
+            Capability::Other { id, .. } => *id,
+        }
+    }
+
+    pub fn offset(&self) -> u8 {
+        match self {
+            Capability::Msi(msi) => msi.offset,
+            Capability::MsiX(msi_x) => msi_x.offset,
+            Capability::Other { offset, .. } => *offset,
+        }
+    }
+}
+
+/// Message signaled interrupts: the function interrupts by writing to an address instead of
+/// with a pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Msi {
+    pub offset: u8,
+    pub enabled: bool,
+    /// How many vectors the function can use, a power of two up to 32.
+    pub vectors: u8,
+    /// Whether the message address may be above 4 GiB.
+    pub address_64: bool,
+    /// Whether each vector can be masked on its own.
+    pub masking: bool,
+}
+
+/// MSI-X: message signaled interrupts with a table of addresses in one of the function's BARs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiX {
+    pub offset: u8,
+    pub enabled: bool,
+    /// Entries in the table, one a vector.
+    pub vectors: u16,
+    /// The BAR the vector table is in, and where in it.
+    pub table: (u8, u32),
+    /// The BAR the pending bit array is in, and where in it.
+    pub pending: (u8, u32),
+}
+
+/// Every capability `address` lists, in order.
+pub fn read_all(address: Address) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if address.read_u16(STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+    let mut offset = address.read_u8(CAPABILITIES_POINTER) & 0xFC;
+    while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+        capabilities.push(decode(address, offset));
+        offset = address.read_u8(offset + 1) & 0xFC;
+    }
+    capabilities
+}
+
+fn decode(address: Address, offset: u8) -> Capability {
+    let id = address.read_u8(offset);
+    let control = address.read_u16(offset + 2);
+    match id {
+        ID_MSI => Capability::Msi(Msi {
+            offset,
+            enabled: control & 1 != 0,
+            vectors: 1 << ((control >> 1) & 7).min(5),
+            address_64: control & 1 << 7 != 0,
+            masking: control & 1 << 8 != 0,
+        }),
+        ID_MSI_X => {
+            // The low 3 bits say which BAR, the rest is the offset into it.
+            let location = |register: u8| {
+                let value = address.read_u32(offset.wrapping_add(register));
+                ((value & 7) as u8, value & !7)
+            };
+            Capability::MsiX(MsiX {
+                offset,
+                enabled: control & 1 << 15 != 0,
+                vectors: (control & 0x7FF) + 1,
+                table: location(4),
+                pending: location(8),
+            })
+        }
+        _ => Capability::Other { id, offset },
+    }
+}
@@ -0,0 +1,130 @@
+//! Reaching configuration space.
+//!
+//! With an ACPI MCFG table, each bus's configuration space is memory-mapped (ECAM): a MiB per
+//! bus, 4 KiB per function. A bus is mapped the first time it's accessed. Buses outside what
+//! MCFG covers, or all of them without it, go through ports `0xCF8` and `0xCFC`, one dword at a
+//! time under a lock as it takes two accesses.
+
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::Address;
+use crate::acpi::{self, HEADER_SIZE};
+use crate::mem::paging::map_physical;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// Where the ECAM region is mapped, at a MiB per bus from bus 0.
+const ECAM_BASE: u64 = 0x_4445_0000_0000;
+const BUS_SIZE: u64 = 1 << 20;
+const PAGE_SIZE: u64 = 4096;
+
+/// Serializes the two-step port access, which every CPU shares.
+static PORTS: Mutex<()> = Mutex::new(());
+
+/// The ECAM region of segment group 0, looked up on first access.
+static ECAM: OnceCell<Option<Ecam>> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy)]
+struct Ecam {
+    /// Physical address of bus 0's configuration space, even if the region starts at a later bus.
+    base: u64,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+/// One bit for each bus whose configuration space is mapped.
+static MAPPED: Mutex<[u64; 4]> = Mutex::new([0; 4]);
+
+/// Finds segment group 0 in the MCFG table, whose entries follow its header and 8 reserved
+/// bytes.
+fn mcfg() -> Option<Ecam> {
+    let table = acpi::find(*b"MCFG")?;
+    (HEADER_SIZE + 8..table.length.saturating_sub(15))
+        .step_by(16)
+        .find(|&entry| table.read::<u16>(entry + 8) == 0)
+        .map(|entry| Ecam {
+            base: table.read(entry),
+            start_bus: table.read(entry + 10),
+            end_bus: table.read(entry + 11),
+        })
+}
+
+/// Whether configuration space is memory-mapped, for at least some buses.
+pub fn ecam() -> bool {
+    ECAM.get_or_init(mcfg).is_some()
+}
+
+/// Where `bus`'s configuration space is mapped, if it's in the ECAM region and could be mapped.
+fn bus_base(bus: u8) -> Option<VirtAddr> {
+    let ecam = (*ECAM.get_or_init(mcfg))?;
+    if bus < ecam.start_bus || bus > ecam.end_bus {
+        return None;
+    }
+    let virt = VirtAddr::new(ECAM_BASE + bus as u64 * BUS_SIZE);
+    let phys = PhysAddr::new(ecam.base + bus as u64 * BUS_SIZE);
+
+    without_interrupts(|| {
+        let mut mapped = MAPPED.lock();
+        let (word, bit) = (bus as usize / 64, bus % 64);
+        if mapped[word] & 1 << bit == 0 {
+            let flags =
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+            for offset in (0..BUS_SIZE).step_by(PAGE_SIZE as usize) {
+                let page = Page::containing_address(virt + offset);
+                let frame = PhysFrame::containing_address(phys + offset);
+                map_physical(page, frame, flags).ok()?;
+            }
+            mapped[word] |= 1 << bit;
+        }
+        Some(virt)
+    })
+}
+
+fn port_address(address: Address, offset: u8) -> u32 {
+    1 << 31
+        | (address.bus as u32) << 16
+        | (address.device as u32) << 11
+        | (address.function as u32) << 8
+        | (offset & 0xFC) as u32
+}
+
+/// Reads the dword at `offset`, rounded down to a multiple of 4.
+pub fn read(address: Address, offset: u8) -> u32 {
+    if let Some(base) = bus_base(address.bus) {
+        let virt = base + ecam_offset(address, offset);
+        return unsafe { virt.as_ptr::<u32>().read_volatile() };
+    }
+    without_interrupts(|| {
+        let _ports = PORTS.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(port_address(address, offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    })
+}
+
+/// Writes the dword at `offset`, rounded down to a multiple of 4.
+pub fn write(address: Address, offset: u8, value: u32) {
+    if let Some(base) = bus_base(address.bus) {
+        let virt = base + ecam_offset(address, offset);
+        unsafe { virt.as_mut_ptr::<u32>().write_volatile(value) };
+        return;
+    }
+    without_interrupts(|| {
+        let _ports = PORTS.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(port_address(address, offset));
+            Port::new(CONFIG_DATA).write(value);
+        }
+    })
+}
+
+fn ecam_offset(address: Address, offset: u8) -> u64 {
+    (address.device as u64) << 15 | (address.function as u64) << 12 | (offset & 0xFC) as u64
+}
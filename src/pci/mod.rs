@@ -0,0 +1,380 @@
+//! PCI devices.
+//!
+//! Configuration space is reached through memory where ACPI says where (ECAM), and through ports
+//! `0xCF8` and `0xCFC` otherwise (see [`config`]). The buses are scanned once, from the host
+//! bridges down through every PCI-to-PCI bridge, into a registry of every function found. A
+//! driver looks up the functions it handles with a [`Match`] and [`claim`]s them, so no other
+//! driver takes them too.
+
+use core::fmt;
+
+use conquer_once::spin::OnceCell;
+use rust_alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub mod capability;
+pub mod config;
+
+use self::capability::Capability;
+
+const COMMAND: u8 = 0x04;
+const COMMAND_IO: u16 = 1 << 0;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const HEADER_TYPE: u8 = 0x0E;
+const HEADER_MULTI_FUNCTION: u8 = 0x80;
+const SECONDARY_BUS: u8 = 0x19;
+
+pub const CLASS_BRIDGE: u8 = 0x06;
+pub const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+/// Every function found, scanned the first time it's asked for.
+static REGISTRY: OnceCell<Mutex<Vec<Device>>> = OnceCell::uninit();
+
+/// Where a function sits on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Address {
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        config::read(*self, offset)
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        config::write(*self, offset, value)
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset & !3) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Replaces the 16 bits at `offset` and leaves the rest of their dword alone.
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset & !3) & !(0xFFFF << shift);
+        self.write_u32(offset & !3, dword | (value as u32) << shift);
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A base address register, decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    /// A range of I/O ports.
+    Io { port: u16, size: u32 },
+    /// Memory-mapped registers at a physical address.
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// Whether it takes the next register for its upper half.
+        wide: bool,
+    },
+}
+
+/// A function found on the bus, with the identifying part of its configuration header.
+#[derive(Debug, Clone, Copy)]
+pub struct Device {
+    pub address: Address,
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub interface: u8,
+    pub revision: u8,
+    /// Without the multi-function bit: 0 for a device, 1 for a PCI-to-PCI bridge.
+    pub header_type: u8,
+    /// Decoded once, when the function was found.
+    pub bars: [Option<Bar>; 6],
+    /// The driver that had claimed the function when this copy was made.
+    pub driver: Option<&'static str>,
+}
+
+impl Device {
+    /// The function at `address`, if there is one.
+    pub fn probe(address: Address) -> Option<Self> {
+        let id = address.read_u32(0x00);
+        if id as u16 == 0xFFFF {
+            return None;
+        }
+        let class = address.read_u32(0x08);
+        let mut device = Device {
+            address,
+            vendor: id as u16,
+            device: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            interface: (class >> 8) as u8,
+            revision: class as u8,
+            header_type: address.read_u8(HEADER_TYPE) & !HEADER_MULTI_FUNCTION,
+            bars: [None; 6],
+            driver: None,
+        };
+        device.bars = device.decode_bars();
+        Some(device)
+    }
+
+    /// Base address register `index`. `None` if it's unused, or the upper half of the one
+    /// before.
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        self.bars.get(index).copied().flatten()
+    }
+
+    /// Finds the address and size of each BAR. The size is what the function leaves of all ones
+    /// written to it, so decoding is off meanwhile.
+    fn decode_bars(&self) -> [Option<Bar>; 6] {
+        let mut bars = [None; 6];
+        // Bridges only have two.
+        let count = match self.header_type {
+            0 => 6,
+            1 => 2,
+            _ => return bars,
+        };
+        let command = self.address.read_u16(COMMAND);
+        without_interrupts(|| {
+            self.address
+                .write_u16(COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+            let mut index = 0;
+            while index < count {
+                let offset = 0x10 + index as u8 * 4;
+                let low = self.size_register(offset);
+                let (bar, width) = if low.0 & 1 != 0 {
+                    let port = low.0 & !3;
+                    let size = (!(low.1 & !3) & 0xFFFF).wrapping_add(1);
+                    let bar = (port != 0).then_some(Bar::Io {
+                        port: port as u16,
+                        size,
+                    });
+                    (bar, 1)
+                } else {
+                    let wide = (low.0 >> 1) & 3 == 2 && index + 1 < count;
+                    let high = if wide {
+                        self.size_register(offset + 4)
+                    } else {
+                        (0, u32::MAX)
+                    };
+                    let address = (high.0 as u64) << 32 | (low.0 & !0xF) as u64;
+                    let mask = (high.1 as u64) << 32 | (low.1 & !0xF) as u64;
+                    let bar = (address != 0 && mask != 0).then_some(Bar::Memory {
+                        address,
+                        size: (!mask).wrapping_add(1),
+                        prefetchable: low.0 & 1 << 3 != 0,
+                        wide,
+                    });
+                    (bar, if wide { 2 } else { 1 })
+                };
+                bars[index] = bar;
+                index += width;
+            }
+            self.address.write_u16(COMMAND, command);
+        });
+        bars
+    }
+
+    /// Returns a BAR register's value and what's left of all ones written to it, then puts the
+    /// value back.
+    fn size_register(&self, offset: u8) -> (u32, u32) {
+        let value = self.address.read_u32(offset);
+        self.address.write_u32(offset, u32::MAX);
+        let mask = self.address.read_u32(offset);
+        self.address.write_u32(offset, value);
+        (value, mask)
+    }
+
+    /// Every capability the function lists.
+    pub fn capabilities(&self) -> Vec<Capability> {
+        capability::read_all(self.address)
+    }
+
+    /// The legacy interrupt line firmware routed the function's `INTx` pin to, if any.
+    pub fn interrupt_line(&self) -> Option<u8> {
+        let pin = self.address.read_u8(0x3D);
+        let line = self.address.read_u8(0x3C);
+        (pin != 0 && line < 16).then_some(line)
+    }
+
+    /// Lets the function decode its BARs and master the bus, i.e. do DMA.
+    pub fn enable(&self) {
+        let command = self.address.read_u16(COMMAND);
+        self.address.write_u16(
+            COMMAND,
+            command | COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER,
+        );
+    }
+}
+
+/// Which functions a driver handles. Fields left `None` match anything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Match {
+    pub vendor: Option<u16>,
+    pub device: Option<u16>,
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+    pub interface: Option<u8>,
+}
+
+impl Match {
+    /// Functions with these vendor and device ids.
+    pub const fn id(vendor: u16, device: u16) -> Self {
+        Match {
+            vendor: Some(vendor),
+            device: Some(device),
+            class: None,
+            subclass: None,
+            interface: None,
+        }
+    }
+
+    /// Functions of this class and subclass, from any vendor.
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Match {
+            vendor: None,
+            device: None,
+            class: Some(class),
+            subclass: Some(subclass),
+            interface: None,
+        }
+    }
+
+    pub fn matches(&self, device: &Device) -> bool {
+        fn field<T: PartialEq>(want: Option<T>, have: T) -> bool {
+            !matches!(want, Some(want) if want != have)
+        }
+        field(self.vendor, device.vendor)
+            && field(self.device, device.device)
+            && field(self.class, device.class)
+            && field(self.subclass, device.subclass)
+            && field(self.interface, device.interface)
+    }
+}
+
+/// Scans every bus reachable from the host bridges, in address order.
+fn scan() -> Vec<Device> {
+    let mut devices = Vec::new();
+    let mut scanned = [0u64; 4];
+    let host = Address {
+        bus: 0,
+        device: 0,
+        function: 0,
+    };
+    // With more than one host bridge, bridge `n` is function `n` of 00:00 and owns bus `n`.
+    if host.read_u8(HEADER_TYPE) & HEADER_MULTI_FUNCTION == 0 {
+        scan_bus(0, &mut devices, &mut scanned);
+    } else {
+        for function in 0..8 {
+            if Device::probe(Address { function, ..host }).is_some() {
+                scan_bus(function, &mut devices, &mut scanned);
+            }
+        }
+    }
+    devices.sort_by_key(|device| device.address);
+    devices
+}
+
+fn scan_bus(bus: u8, devices: &mut Vec<Device>, scanned: &mut [u64; 4]) {
+    // A bridge misconfigured into a loop would otherwise scan forever.
+    let (word, bit) = (bus as usize / 64, bus % 64);
+    if scanned[word] & 1 << bit != 0 {
+        return;
+    }
+    scanned[word] |= 1 << bit;
+
+    for device in 0..32 {
+        let first = Address {
+            bus,
+            device,
+            function: 0,
+        };
+        let Some(found) = Device::probe(first) else {
+            continue;
+        };
+        // Only multi-function devices answer on functions 1 to 7.
+        let others = if first.read_u8(HEADER_TYPE) & HEADER_MULTI_FUNCTION != 0 {
+            1..8
+        } else {
+            1..1
+        };
+        let functions = others.filter_map(|function| Device::probe(Address { function, ..first }));
+        for found in core::iter::once(found).chain(functions) {
+            devices.push(found);
+            if found.class == CLASS_BRIDGE
+                && found.subclass == SUBCLASS_PCI_BRIDGE
+                && found.header_type == 1
+            {
+                let secondary = found.address.read_u8(SECONDARY_BUS);
+                if secondary != 0 {
+                    scan_bus(secondary, devices, scanned);
+                }
+            }
+        }
+    }
+}
+
+fn registry() -> &'static Mutex<Vec<Device>> {
+    REGISTRY.get_or_init(|| Mutex::new(scan()))
+}
+
+/// Scans the buses, unless that already happened. Returns how many functions there are.
+pub fn init() -> usize {
+    without_interrupts(|| registry().lock().len())
+}
+
+/// Every function, in address order.
+pub fn devices() -> Vec<Device> {
+    without_interrupts(|| registry().lock().clone())
+}
+
+/// Every function `filter` matches, claimed or not.
+pub fn matching(filter: Match) -> Vec<Device> {
+    without_interrupts(|| {
+        registry()
+            .lock()
+            .iter()
+            .filter(|device| filter.matches(device))
+            .copied()
+            .collect()
+    })
+}
+
+/// Hands the functions `filter` matches and no driver has claimed yet to `driver`, and returns
+/// them.
+pub fn claim(filter: Match, driver: &'static str) -> Vec<Device> {
+    without_interrupts(|| {
+        registry()
+            .lock()
+            .iter_mut()
+            .filter(|device| device.driver.is_none() && filter.matches(device))
+            .map(|device| {
+                device.driver = Some(driver);
+                *device
+            })
+            .collect()
+    })
+}
+
+/// Gives up a claimed function, e.g. because the driver couldn't set it up after all.
+pub fn release(address: Address) {
+    without_interrupts(|| {
+        if let Some(device) = registry()
+            .lock()
+            .iter_mut()
+            .find(|device| device.address == address)
+        {
+            device.driver = None;
+        }
+    })
+}
@@ -0,0 +1,540 @@
+//! Processes: threads sharing a private address space.
+//!
+//! Kernel threads belong to no process and run on the kernel's own page tables. A thread spawned
+//! into a process runs on the process's, which the scheduler loads whenever it switches to one.
+//!
+//! Every process is listed in a table from its creation until it has been reaped. A process
+//! exits once its last thread has. If it was created by a thread of another process, that
+//! parent is notified and the process stays in the table as a zombie, holding its exit code,
+//! until the parent [`wait`](Process::wait)s for it and [`reap`]s it. Processes created by the
+//! kernel, and those whose parent has exited, are reaped as soon as they exit.
+//!
+//! The user half is made up of [areas](vma), whose pages are backed as they're first touched. A
+//! fault anywhere else, or one an area doesn't allow, kills the process.
+
+pub mod vma;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_alloc::collections::BTreeMap;
+use rust_alloc::string::{String, ToString};
+use rust_alloc::sync::Arc;
+use rust_alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::tlb;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::mem::space::{AddressSpace, USER_END};
+use crate::thread::{self, JoinHandle, Priority, ThreadId};
+
+pub use self::vma::{Area, MapError};
+pub use lateral_abi::KILLED_EXIT_CODE;
+
+use self::vma::Areas;
+
+const PAGE_SIZE: u64 = 4096;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+static PROCESSES: Mutex<BTreeMap<ProcessId, Arc<Process>>> = Mutex::new(BTreeMap::new());
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct ProcessId(usize);
+
+impl ProcessId {
+    fn next() -> Self {
+        ProcessId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn from_usize(id: usize) -> Self {
+        ProcessId(id)
+    }
+
+    pub fn as_usize(&self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for ProcessId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub struct Process {
+    id: ProcessId,
+    name: String,
+    /// Copied out of `space` so the scheduler can switch to it without taking the lock.
+    table: PhysFrame,
+    space: Mutex<AddressSpace>,
+    memory: Mutex<Memory>,
+    lifecycle: Mutex<Lifecycle>,
+}
+
+/// Taken before `space` when both are needed.
+struct Memory {
+    areas: Areas,
+    heap: Option<Heap>,
+}
+
+struct Lifecycle {
+    /// The process that created this one, until it exits.
+    parent: Option<ProcessId>,
+    threads: Vec<ThreadId>,
+    /// Set by `kill` or `exit`: every thread is to end, and the process with this code.
+    killed: Option<i32>,
+    /// Set once the last thread has exited.
+    exit_code: Option<i32>,
+    /// The threads blocked in `wait` on this process.
+    waiters: Vec<ThreadId>,
+}
+
+/// The program break: the heap runs from `start` to `end` and may grow up to `limit`.
+#[derive(Debug, Clone, Copy)]
+struct Heap {
+    start: u64,
+    end: u64,
+    limit: u64,
+}
+
+impl Process {
+    /// Creates a process with an empty user half and adds it to the process table. Its parent
+    /// is the calling thread's process, if any.
+    pub fn new(name: &str) -> Result<Arc<Self>, MapToError<Size4KiB>> {
+        let space = AddressSpace::new()?;
+        let process = Arc::new(Process {
+            id: ProcessId::next(),
+            name: name.to_string(),
+            table: space.table(),
+            space: Mutex::new(space),
+            memory: Mutex::new(Memory {
+                areas: Areas::new(),
+                heap: None,
+            }),
+            lifecycle: Mutex::new(Lifecycle {
+                parent: current().map(|parent| parent.id),
+                threads: Vec::new(),
+                killed: None,
+                exit_code: None,
+                waiters: Vec::new(),
+            }),
+        });
+        without_interrupts(|| PROCESSES.lock().insert(process.id, process.clone()));
+        Ok(process)
+    }
+
+    pub fn id(&self) -> ProcessId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The level 4 table threads of this process run on.
+    pub fn table(&self) -> PhysFrame {
+        self.table
+    }
+
+    /// The process that created this one, or `None` if it was the kernel or has exited since.
+    pub fn parent(&self) -> Option<ProcessId> {
+        without_interrupts(|| self.lifecycle.lock().parent)
+    }
+
+    /// The exit code, once every thread has exited.
+    pub fn exit_code(&self) -> Option<i32> {
+        without_interrupts(|| self.lifecycle.lock().exit_code)
+    }
+
+    /// The code the process was killed with, if it was.
+    pub fn killed(&self) -> Option<i32> {
+        without_interrupts(|| self.lifecycle.lock().killed)
+    }
+
+    /// Ends the process with [`KILLED_EXIT_CODE`]. See [`Process::kill_with`].
+    pub fn kill(&self) {
+        self.kill_with(KILLED_EXIT_CODE);
+    }
+
+    /// Ends every thread of the process, and the process with `code` unless it was already
+    /// killed. Threads notice the next time they make a syscall or are interrupted in ring 3;
+    /// blocked ones are woken to do so.
+    pub fn kill_with(&self, code: i32) {
+        let (threads, waiters) = without_interrupts(|| {
+            let mut lifecycle = self.lifecycle.lock();
+            lifecycle.killed.get_or_insert(code);
+            // A process without threads won't get another chance to exit.
+            if lifecycle.threads.is_empty() && lifecycle.exit_code.is_none() {
+                lifecycle.exit_code = lifecycle.killed;
+                drop(lifecycle);
+                return (Vec::new(), self.exited());
+            }
+            (lifecycle.threads.clone(), Vec::new())
+        });
+
+        let me = thread::current();
+        for id in threads.into_iter().filter(|&id| id != me) {
+            thread::wake(id);
+        }
+        for id in waiters {
+            thread::wake(id);
+        }
+    }
+
+    /// Blocks until every thread of the process has exited and returns its exit code. Returns
+    /// `None` instead if the calling thread's own process is killed in the meantime.
+    pub fn wait(&self) -> Option<i32> {
+        loop {
+            if current().is_some_and(|me| me.killed().is_some()) {
+                return None;
+            }
+            let exit_code = without_interrupts(|| {
+                let mut lifecycle = self.lifecycle.lock();
+                if lifecycle.exit_code.is_none() {
+                    lifecycle.waiters.push(thread::current());
+                }
+                lifecycle.exit_code
+            });
+            match exit_code {
+                Some(code) => return Some(code),
+                None => thread::block(),
+            }
+        }
+    }
+
+    /// Whether thread `id` belongs to this process and hasn't exited.
+    pub fn has_thread(&self, id: ThreadId) -> bool {
+        without_interrupts(|| self.lifecycle.lock().threads.contains(&id))
+    }
+
+    /// Called by the thread module before thread `id` is spawned into this process.
+    pub(crate) fn thread_started(&self, id: ThreadId) {
+        without_interrupts(|| self.lifecycle.lock().threads.push(id));
+    }
+
+    /// Called by the thread module when thread `id` of this process exits with `code`. The last
+    /// thread to exit ends the process.
+    pub(crate) fn thread_exited(&self, id: ThreadId, code: i32) {
+        let waiters = without_interrupts(|| {
+            let mut lifecycle = self.lifecycle.lock();
+            lifecycle.threads.retain(|&thread| thread != id);
+            if !lifecycle.threads.is_empty() || lifecycle.exit_code.is_some() {
+                return Vec::new();
+            }
+            lifecycle.exit_code = Some(lifecycle.killed.unwrap_or(code));
+            drop(lifecycle);
+            self.exited()
+        });
+
+        for id in waiters {
+            thread::wake(id);
+        }
+    }
+
+    /// Orphans the process's children, reaping those that already exited, and reaps the process
+    /// itself if nobody is left to. Returns the threads waiting on it, to be woken.
+    ///
+    /// Must be called with interrupts disabled and without the lifecycle lock.
+    fn exited(&self) -> Vec<ThreadId> {
+        let mut processes = PROCESSES.lock();
+        processes.retain(|_, process| {
+            let mut lifecycle = process.lifecycle.lock();
+            if lifecycle.parent != Some(self.id) {
+                return true;
+            }
+            lifecycle.parent = None;
+            lifecycle.exit_code.is_none()
+        });
+
+        let mut lifecycle = self.lifecycle.lock();
+        if lifecycle.parent.is_none() {
+            processes.remove(&self.id);
+        }
+        core::mem::take(&mut lifecycle.waiters)
+    }
+
+    /// Spawns a thread running `f` inside this process, on the calling CPU's runtime.
+    pub fn spawn<F>(self: &Arc<Self>, name: &str, f: F) -> JoinHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::spawn_in(self.clone(), name, Priority::Normal, f)
+    }
+
+    /// Adds an area over `pages` and backs it with fresh, zeroed, user accessible frames right
+    /// away. See [`AddressSpace::map`].
+    pub fn map(&self, pages: PageRange<Size4KiB>, flags: PageTableFlags) -> Result<(), MapError> {
+        let (start, end) = bounds(pages);
+        let failed = without_interrupts(|| {
+            let mut memory = self.memory.lock();
+            memory.areas.insert(start, end, flags)?;
+            let mut space = self.space.lock();
+            let Err(e) = space.map(pages, flags) else {
+                return Ok(None);
+            };
+            // Give back whatever was mapped before the failure.
+            let (stale, _) = space.unmap(pages);
+            let _ = memory.areas.remove(start, end);
+            Ok(Some((stale, e)))
+        })?;
+        match failed {
+            None => Ok(()),
+            Some((stale, e)) => {
+                stale.flush();
+                Err(match e {
+                    MapToError::FrameAllocationFailed => MapError::OutOfMemory,
+                    _ => MapError::NoSpace,
+                })
+            }
+        }
+    }
+
+    /// Adds an area of `len` bytes, rounded up to whole pages, whose pages are backed as they're
+    /// first touched. It starts at `addr` if given, which must be page aligned, or else in the
+    /// highest free range below the heap's limit.
+    pub fn map_lazy(
+        &self,
+        addr: Option<VirtAddr>,
+        len: u64,
+        flags: PageTableFlags,
+    ) -> Result<VirtAddr, MapError> {
+        let len = len
+            .checked_next_multiple_of(PAGE_SIZE)
+            .ok_or(MapError::Invalid)?;
+        without_interrupts(|| {
+            let mut memory = self.memory.lock();
+            let start = match addr {
+                Some(addr) => addr.as_u64(),
+                None => {
+                    let top = memory.heap.map_or(USER_END, |heap| heap.limit);
+                    memory.areas.find_free(len, top).ok_or(MapError::NoSpace)?
+                }
+            };
+            let end = start.checked_add(len).ok_or(MapError::Invalid)?;
+            memory.areas.insert(start, end, flags)?;
+            Ok(VirtAddr::new(start))
+        })
+    }
+
+    /// Removes `pages` from the process's areas and unmaps and frees those that were backed, once
+    /// no CPU running the process can reach them anymore. See [`AddressSpace::unmap`].
+    pub fn unmap(&self, pages: PageRange<Size4KiB>) -> Result<(), MapError> {
+        let (start, end) = bounds(pages);
+        let (stale, result) = without_interrupts(|| {
+            let mut memory = self.memory.lock();
+            memory.areas.remove(start, end)?;
+            Ok(self.space.lock().unmap(pages))
+        })?;
+        stale.flush();
+        result.map_err(|_| MapError::Invalid)
+    }
+
+    /// Changes the flags of `pages`, which must all lie in areas, backed or not, on every CPU
+    /// running the process. See [`AddressSpace::protect`].
+    pub fn protect(
+        &self,
+        pages: PageRange<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        let (start, end) = bounds(pages);
+        let (stale, result) = without_interrupts(|| {
+            let mut memory = self.memory.lock();
+            memory.areas.protect(start, end, flags)?;
+            Ok(self.space.lock().protect(pages, flags))
+        })?;
+        stale.flush();
+        result.map_err(|_| MapError::Invalid)
+    }
+
+    /// A snapshot of the process's areas.
+    pub fn areas(&self) -> Vec<Area> {
+        without_interrupts(|| self.memory.lock().areas.iter().copied().collect())
+    }
+
+    /// Resolves a page fault at `addr` by backing its page, if it lies in an area that allows the
+    /// access. Returns false for a fault the process had no business causing.
+    pub fn handle_fault(&self, addr: VirtAddr, write: bool, execute: bool) -> bool {
+        without_interrupts(|| {
+            let memory = self.memory.lock();
+            let Some(area) = memory.areas.find(addr.as_u64()) else {
+                return false;
+            };
+            if (write && !area.flags.contains(PageTableFlags::WRITABLE))
+                || (execute && area.flags.contains(PageTableFlags::NO_EXECUTE))
+            {
+                return false;
+            }
+
+            let mut space = self.space.lock();
+            if space.flags(addr).is_some() {
+                // Another thread got here first, or this CPU faulted on the page the way it was
+                // before `protect` and before its shootdown got here.
+                tlb::flush(addr);
+                return true;
+            }
+            let page = Page::containing_address(addr);
+            space.map(Page::range(page, page + 1), area.flags).is_ok()
+        })
+    }
+
+    pub fn flags(&self, addr: VirtAddr) -> Option<PageTableFlags> {
+        without_interrupts(|| self.space.lock().flags(addr))
+    }
+
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        without_interrupts(|| self.space.lock().translate(addr))
+    }
+
+    /// Copies `bytes` into the process's memory at `addr`, backing any pages of areas that
+    /// weren't yet. See [`AddressSpace::write`].
+    pub fn write(&self, addr: VirtAddr, bytes: &[u8]) -> bool {
+        self.populate(addr, bytes.len());
+        without_interrupts(|| self.space.lock().write(addr, bytes))
+    }
+
+    /// Copies the process's memory at `addr` into `buf`, the same way `write` copies in.
+    pub fn read(&self, addr: VirtAddr, buf: &mut [u8]) -> bool {
+        self.populate(addr, buf.len());
+        without_interrupts(|| self.space.lock().read(addr, buf))
+    }
+
+    /// Backs every page of `addr..addr + len` that lies in an area and isn't backed yet,
+    /// whatever the area's flags. The kernel's own copies go through the physical memory
+    /// mapping, so they never fault them in.
+    fn populate(&self, addr: VirtAddr, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let Some(last) = addr.as_u64().checked_add(len as u64 - 1) else {
+            return;
+        };
+        without_interrupts(|| {
+            let memory = self.memory.lock();
+            let mut space = self.space.lock();
+            let first = Page::<Size4KiB>::containing_address(addr);
+            let last = Page::containing_address(VirtAddr::new(last));
+            for page in Page::range_inclusive(first, last) {
+                let start = page.start_address();
+                if let Some(area) = memory.areas.find(start.as_u64()) {
+                    if space.flags(start).is_none() {
+                        let _ = space.map(Page::range(page, page + 1), area.flags);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Starts an empty heap at `start`, which [`brk`](Self::brk) may grow up to `limit`. Lazy
+    /// areas are placed below `limit` too.
+    pub fn init_break(&self, start: VirtAddr, limit: VirtAddr) {
+        let (start, limit) = (start.as_u64(), limit.as_u64());
+        without_interrupts(|| {
+            self.memory.lock().heap = Some(Heap {
+                start,
+                end: start,
+                limit,
+            })
+        });
+    }
+
+    /// Moves the end of the heap to `end` and returns the new end. Pages are added to or
+    /// removed from the process's areas; new ones are backed as they're touched. An `end` of 0
+    /// leaves the heap as it is. `None` if the process has no heap, or `end` is below its start,
+    /// past its limit or runs into another area.
+    pub fn brk(&self, end: u64) -> Option<u64> {
+        let (end, stale) = without_interrupts(|| {
+            let mut memory = self.memory.lock();
+            let mut heap = memory.heap?;
+            if end == 0 {
+                return Some((Some(heap.end), None));
+            }
+            if end < heap.start || end > heap.limit {
+                return None;
+            }
+
+            let old = heap.end.next_multiple_of(PAGE_SIZE);
+            let new = end.next_multiple_of(PAGE_SIZE);
+            let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+            let mut stale = None;
+            if new > old {
+                memory.areas.insert(old, new, flags).ok()?;
+            } else if new < old {
+                memory.areas.remove(new, old).ok()?;
+                let pages = Page::range(
+                    Page::containing_address(VirtAddr::new(new)),
+                    Page::containing_address(VirtAddr::new(old)),
+                );
+                let (unmapped, result) = self.space.lock().unmap(pages);
+                stale = Some(unmapped);
+                if result.is_err() {
+                    // What was unmapped still has to be flushed.
+                    return Some((None, stale));
+                }
+            }
+            heap.end = end;
+            memory.heap = Some(heap);
+            Some((Some(end), stale))
+        })?;
+        if let Some(stale) = stale {
+            stale.flush();
+        }
+        end
+    }
+}
+
+/// The process the calling thread belongs to, or `None` for a kernel thread.
+pub fn current() -> Option<Arc<Process>> {
+    thread::process()
+}
+
+/// The process with the given id, if it hasn't been reaped.
+pub fn find(id: ProcessId) -> Option<Arc<Process>> {
+    without_interrupts(|| PROCESSES.lock().get(&id).cloned())
+}
+
+/// Every process in the table, zombies included.
+pub fn processes() -> Vec<Arc<Process>> {
+    without_interrupts(|| PROCESSES.lock().values().cloned().collect())
+}
+
+/// Removes an exited process from the table. Does nothing if it's still running.
+pub fn reap(id: ProcessId) {
+    without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        if processes
+            .get(&id)
+            .is_some_and(|process| process.exit_code().is_some())
+        {
+            processes.remove(&id);
+        }
+    });
+}
+
+/// Ends the calling thread's whole process with `code`, or just the thread if it's a kernel
+/// thread.
+pub fn exit(code: i32) -> ! {
+    if let Some(process) = current() {
+        process.kill_with(code);
+    }
+    thread::exit(code)
+}
+
+/// Ends the calling thread if its process has been killed. Called on the way back to ring 3.
+pub fn exit_if_killed() {
+    let killed = current().and_then(|process| process.killed());
+    if let Some(code) = killed {
+        thread::exit(code);
+    }
+}
+
+fn bounds(pages: PageRange<Size4KiB>) -> (u64, u64) {
+    (
+        pages.start.start_address().as_u64(),
+        pages.end.start_address().as_u64(),
+    )
+}
@@ -0,0 +1,168 @@
+//! Virtual memory areas: the ranges of a process's user half it may use, and how.
+//!
+//! An area only says what a page may be backed with. Pages are backed when first touched, by
+//! `Process::handle_fault`, unless whoever created the area populated it up front.
+
+use rust_alloc::collections::BTreeMap;
+use rust_alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+
+use crate::mem::space::{USER_END, USER_START};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// A page aligned range `start..end` with the flags its pages are mapped with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub start: u64,
+    pub end: u64,
+    /// Only `WRITABLE` and `NO_EXECUTE` matter; pages are always present and user accessible.
+    pub flags: PageTableFlags,
+}
+
+impl Area {
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    /// The range is empty, not page aligned or reaches outside the user half.
+    Invalid,
+    /// The range overlaps an existing area, or there is no free range that large.
+    NoSpace,
+    /// No frames were left to populate the range.
+    OutOfMemory,
+}
+
+/// The areas of one address space, none overlapping, by start address.
+#[derive(Debug, Default)]
+pub struct Areas {
+    areas: BTreeMap<u64, Area>,
+}
+
+impl Areas {
+    pub const fn new() -> Self {
+        Areas {
+            areas: BTreeMap::new(),
+        }
+    }
+
+    /// The area `addr` lies in.
+    pub fn find(&self, addr: u64) -> Option<Area> {
+        let (_, area) = self.areas.range(..=addr).next_back()?;
+        area.contains(addr).then_some(*area)
+    }
+
+    /// Whether no area overlaps `start..end`.
+    pub fn is_free(&self, start: u64, end: u64) -> bool {
+        self.overlapping(start, end).next().is_none()
+    }
+
+    /// Adds an area over `start..end`, which must be free.
+    pub fn insert(&mut self, start: u64, end: u64, flags: PageTableFlags) -> Result<(), MapError> {
+        check(start, end)?;
+        if !self.is_free(start, end) {
+            return Err(MapError::NoSpace);
+        }
+        self.areas.insert(start, Area { start, end, flags });
+        Ok(())
+    }
+
+    /// Removes `start..end` from every area overlapping it, splitting those that stick out at
+    /// either end.
+    pub fn remove(&mut self, start: u64, end: u64) -> Result<(), MapError> {
+        check(start, end)?;
+        self.split(start);
+        self.split(end);
+        let inside: Vec<u64> = self
+            .areas
+            .range(start..end)
+            .map(|(&start, _)| start)
+            .collect();
+        for start in inside {
+            self.areas.remove(&start);
+        }
+        Ok(())
+    }
+
+    /// Changes the flags of `start..end`, which areas must cover without gaps.
+    pub fn protect(&mut self, start: u64, end: u64, flags: PageTableFlags) -> Result<(), MapError> {
+        check(start, end)?;
+        let mut covered = start;
+        for area in self.overlapping(start, end) {
+            if area.start > covered {
+                break;
+            }
+            covered = area.end;
+        }
+        if covered < end {
+            return Err(MapError::NoSpace);
+        }
+
+        self.split(start);
+        self.split(end);
+        for (_, area) in self.areas.range_mut(start..end) {
+            area.flags = flags;
+        }
+        Ok(())
+    }
+
+    /// The start of the highest free range of `len` bytes below `top`.
+    pub fn find_free(&self, len: u64, top: u64) -> Option<u64> {
+        let mut end = top.min(USER_END);
+        for (_, area) in self.areas.range(..end).rev() {
+            if area.end <= end && end - area.end >= len {
+                break;
+            }
+            end = end.min(area.start);
+        }
+        let start = end.checked_sub(len)?;
+        (start >= USER_START).then_some(start)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Area> {
+        self.areas.values()
+    }
+
+    fn overlapping(&self, start: u64, end: u64) -> impl Iterator<Item = &Area> {
+        // Areas don't overlap, so only the last one starting before `start` can reach into it.
+        let before = self.areas.range(..start).next_back().map(|(_, area)| area);
+        before
+            .into_iter()
+            .filter(move |area| area.end > start)
+            .chain(self.areas.range(start..end).map(|(_, area)| area))
+    }
+
+    /// Splits the area `addr` lies in, if any, so that one ends and another starts at `addr`.
+    fn split(&mut self, addr: u64) {
+        let Some(area) = self.find(addr) else {
+            return;
+        };
+        if area.start == addr {
+            return;
+        }
+        self.areas.get_mut(&area.start).unwrap().end = addr;
+        self.areas.insert(
+            addr,
+            Area {
+                start: addr,
+                ..area
+            },
+        );
+    }
+}
+
+fn check(start: u64, end: u64) -> Result<(), MapError> {
+    if start >= end
+        || start & (PAGE_SIZE - 1) != 0
+        || end & (PAGE_SIZE - 1) != 0
+        || start < USER_START
+        || end > USER_END
+    {
+        Err(MapError::Invalid)
+    } else {
+        Ok(())
+    }
+}
@@ -1,87 +1,50 @@
-use core::arch::asm;
+//! The kernel's side of the syscall ABI, which `lateral_abi` defines and this re-exports.
+//!
+//! Both `syscall` and `int 0x80` end up in [`dispatcher`], which runs the syscall for the calling
+//! thread. Pointer arguments are checked against the caller's address space (see [`user`]).
 
 pub mod service;
+pub mod user;
+
+pub use lateral_abi::{
+    decode, encode, SyscallError, SyscallNumber, SyscallResult, PCI_RECORD_SIZE, PROT_EXEC,
+    PROT_READ, PROT_WRITE,
+};
+
+/// Runs syscall `n` for the calling thread and encodes the result for `rax`. Doesn't return if
+/// the thread's process was killed meanwhile.
+pub fn dispatcher(n: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
+    let result = encode(dispatch(n, arg1, arg2, arg3));
+    crate::process::exit_if_killed();
+    result
+}
 
-pub const SLEEP: usize = 0;
-pub const UPTIME: usize = 1;
-pub const REALTIME: usize = 2;
+fn dispatch(n: usize, arg1: usize, arg2: usize, arg3: usize) -> SyscallResult {
+    const NANOS: f64 = 1_000_000_000.0;
 
-#[macro_export]
-macro_rules! syscall {
-    ($n:expr) => {
-        $crate::sys::syscall::syscall0($n as usize)
-    };
-    ($n:expr, $a1:expr) => {
-        $crate::sys::syscall::syscall1($n as usize, $a1 as usize)
-    };
-    ($n:expr, $a1:expr, $a2:expr) => {
-        $crate::sys::syscall::syscall2($n as usize, $a1 as usize, $a2 as usize)
-    };
-    ($n:expr, $a1:expr, $a2:expr, $a3:expr) => {
-        $crate::sys::syscall::syscall3($n as usize, $a1 as usize, $a2 as usize, $a3 as usize)
+    let Some(number) = SyscallNumber::from_usize(n) else {
+        return Err(SyscallError::ENOSYS);
     };
-}
 
-pub fn dispatcher(n: usize, arg1: usize, _arg2: usize, _arg3: usize) -> usize {
-    match n {
-        SLEEP => {
-            // sleep(f64)
-            service::sleep(f64::from_bits(arg1 as u64));
-            0
-        }
-        UPTIME => {
-            // uptime() -> f64
-            service::uptime().to_bits() as usize
-        }
-        REALTIME => {
-            // realtime() -> f64
-            service::realtime().to_bits() as usize
-        }
-        _ => {
-            unimplemented!();
+    match number {
+        SyscallNumber::Sleep => {
+            service::sleep(arg1 as f64 / NANOS);
+            Ok(0)
         }
+        SyscallNumber::Uptime => Ok((service::uptime() * NANOS) as usize),
+        SyscallNumber::Realtime => Ok((service::realtime() * NANOS) as usize),
+        SyscallNumber::SetPriority => service::set_priority(arg1, arg2).map(|()| 0),
+        SyscallNumber::Log => service::log(arg1, arg2).map(|()| 0),
+        SyscallNumber::Exit => service::exit(arg1 as i32),
+        SyscallNumber::Brk => service::brk(arg1),
+        SyscallNumber::Spawn => service::spawn(arg1, arg2),
+        SyscallNumber::Wait => service::wait(arg1),
+        SyscallNumber::Kill => service::kill(arg1).map(|()| 0),
+        SyscallNumber::GetPid => service::getpid(),
+        SyscallNumber::Mmap => service::mmap(arg1, arg2, arg3),
+        SyscallNumber::Munmap => service::munmap(arg1, arg2).map(|()| 0),
+        SyscallNumber::Mprotect => service::mprotect(arg1, arg2, arg3).map(|()| 0),
+        SyscallNumber::MemStats => service::memstats(arg1).map(|()| 0),
+        SyscallNumber::PciDevices => service::pci_devices(arg1, arg2),
     }
 }
-
-#[doc(hidden)]
-pub unsafe fn syscall0(n: usize) -> usize {
-    let res: usize;
-    asm!(
-        "int 0x80", in("rax") n,
-        lateout("rax") res
-    );
-    res
-}
-
-#[doc(hidden)]
-pub unsafe fn syscall1(n: usize, arg1: usize) -> usize {
-    let res: usize;
-    asm!(
-        "int 0x80", in("rax") n,
-        in("rdi") arg1,
-        lateout("rax") res
-    );
-    res
-}
-
-#[doc(hidden)]
-pub unsafe fn syscall2(n: usize, arg1: usize, arg2: usize) -> usize {
-    let res: usize;
-    asm!(
-        "int 0x80", in("rax") n,
-        in("rdi") arg1, in("rsi") arg2,
-        lateout("rax") res
-    );
-    res
-}
-
-#[doc(hidden)]
-pub unsafe fn syscall3(n: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
-    let res: usize;
-    asm!(
-        "int 0x80", in("rax") n,
-        in("rdi") arg1, in("rsi") arg2, in("rdx") arg3,
-        lateout("rax") res
-    );
-    res
-}
@@ -1,9 +1,21 @@
-use core::arch::asm;
+use rust_alloc::format;
+use rust_alloc::vec::Vec;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+use super::{user, SyscallError, PCI_RECORD_SIZE, PROT_EXEC, PROT_READ, PROT_WRITE};
+use crate::io::logging::kernel_info;
+use crate::loader::apps::{self, SpawnError};
+use crate::loader::elf::ElfError;
+use crate::mem::frame;
+use crate::mem::space::{USER_END, USER_START};
+use crate::pci;
+use crate::process::{self, MapError, ProcessId};
+use crate::thread::{Priority, ThreadId};
 
 pub fn sleep(seconds: f64) {
-    unsafe { asm!("sti") }; // Restore interrupts
     crate::time::rtc::sleep(seconds);
-    unsafe { asm!("cli") }; // Disable interrupts
 }
 
 pub fn uptime() -> f64 {
@@ -13,3 +25,195 @@ pub fn uptime() -> f64 {
 pub fn realtime() -> f64 {
     crate::time::realtime()
 }
+
+pub fn set_priority(thread: usize, priority: usize) -> Result<(), SyscallError> {
+    let priority = Priority::from_usize(priority).ok_or(SyscallError::EINVAL)?;
+    let thread = ThreadId::from_usize(thread);
+    let current = crate::thread::priority(thread).ok_or(SyscallError::ESRCH)?;
+    // Kernel threads may do as they like. A process can only lower its own threads, so it can't
+    // starve everything else or touch threads it doesn't own.
+    if let Some(process) = process::current() {
+        if !process.has_thread(thread) || priority == Priority::Realtime || priority < current {
+            return Err(SyscallError::EPERM);
+        }
+    }
+    if crate::thread::set_priority(thread, priority) {
+        Ok(())
+    } else {
+        Err(SyscallError::ESRCH)
+    }
+}
+
+pub fn log(message: usize, len: usize) -> Result<(), SyscallError> {
+    let message = user::read(message, len)?;
+    let message = core::str::from_utf8(&message).map_err(|_| SyscallError::EINVAL)?;
+    kernel_info(format!("{}: {}", crate::thread::name(), message).as_str());
+    Ok(())
+}
+
+pub fn exit(code: i32) -> ! {
+    process::exit(code)
+}
+
+pub fn brk(addr: usize) -> Result<usize, SyscallError> {
+    let process = process::current().ok_or(SyscallError::EINVAL)?;
+    process
+        .brk(addr as u64)
+        .map(|end| end as usize)
+        .ok_or(SyscallError::ENOMEM)
+}
+
+pub fn spawn(command: usize, len: usize) -> Result<usize, SyscallError> {
+    let command = user::read(command, len)?;
+    let command = core::str::from_utf8(&command).map_err(|_| SyscallError::EINVAL)?;
+    let mut words = command.split_terminator('\0');
+    let path = words.next().ok_or(SyscallError::EINVAL)?;
+    let args: Vec<&str> = words.collect();
+
+    match apps::spawn(path, &args) {
+        Ok(process) => Ok(process.id().as_usize()),
+        Err(SpawnError::NotFound) => Err(SyscallError::ENOENT),
+        Err(SpawnError::Elf(ElfError::ArgumentsTooLong)) => Err(SyscallError::E2BIG),
+        Err(SpawnError::Elf(ElfError::Map(_))) => Err(SyscallError::ENOMEM),
+        Err(SpawnError::Elf(_)) => Err(SyscallError::ENOEXEC),
+    }
+}
+
+pub fn wait(pid: usize) -> Result<usize, SyscallError> {
+    let me = process::current().ok_or(SyscallError::ECHILD)?;
+    let child = process::find(ProcessId::from_usize(pid)).ok_or(SyscallError::ESRCH)?;
+    if child.parent() != Some(me.id()) {
+        return Err(SyscallError::ECHILD);
+    }
+    drop(me);
+
+    // `None` means the caller was killed, which the dispatcher takes care of.
+    let code = child.wait().ok_or(SyscallError::ESRCH)?;
+    process::reap(child.id());
+    Ok(code as u32 as usize)
+}
+
+pub fn kill(pid: usize) -> Result<(), SyscallError> {
+    let process = process::find(ProcessId::from_usize(pid)).ok_or(SyscallError::ESRCH)?;
+    // Kernel threads may kill anything, a process only itself and its own children.
+    if let Some(caller) = process::current() {
+        if process.id() != caller.id() && process.parent() != Some(caller.id()) {
+            return Err(SyscallError::EPERM);
+        }
+    }
+    process.kill();
+    Ok(())
+}
+
+pub fn getpid() -> Result<usize, SyscallError> {
+    let process = process::current().ok_or(SyscallError::ESRCH)?;
+    Ok(process.id().as_usize())
+}
+
+pub fn mmap(addr: usize, len: usize, prot: usize) -> Result<usize, SyscallError> {
+    let process = process::current().ok_or(SyscallError::EINVAL)?;
+    let flags = protection(prot)?;
+    let addr = match addr {
+        0 => None,
+        addr => Some(VirtAddr::try_new(addr as u64).map_err(|_| SyscallError::EINVAL)?),
+    };
+    let addr = process
+        .map_lazy(addr, len as u64, flags)
+        .map_err(map_error)?;
+    Ok(addr.as_u64() as usize)
+}
+
+pub fn munmap(addr: usize, len: usize) -> Result<(), SyscallError> {
+    let process = process::current().ok_or(SyscallError::EINVAL)?;
+    process.unmap(pages(addr, len)?).map_err(map_error)
+}
+
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> Result<(), SyscallError> {
+    let process = process::current().ok_or(SyscallError::EINVAL)?;
+    let flags = protection(prot)?;
+    process.protect(pages(addr, len)?, flags).map_err(map_error)
+}
+
+pub fn memstats(stats: usize) -> Result<(), SyscallError> {
+    let frame::FrameStats { total, used, free } = frame::stats();
+    let bytes: Vec<u8> = [total, used, free]
+        .iter()
+        .flat_map(|&count| (count as u64).to_le_bytes())
+        .collect();
+    user::write(stats, &bytes)
+}
+
+pub fn pci_devices(records: usize, count: usize) -> Result<usize, SyscallError> {
+    let devices = pci::devices();
+    let bytes: Vec<u8> = devices
+        .iter()
+        .take(count)
+        .flat_map(|device| {
+            let mut record = [0u8; PCI_RECORD_SIZE];
+            record[..8].copy_from_slice(&[
+                device.address.bus,
+                device.address.device,
+                device.address.function,
+                device.header_type,
+                device.class,
+                device.subclass,
+                device.interface,
+                device.revision,
+            ]);
+            record[8..10].copy_from_slice(&device.vendor.to_le_bytes());
+            record[10..12].copy_from_slice(&device.device.to_le_bytes());
+            record[12] = device.interrupt_line().unwrap_or(0xFF);
+            record
+        })
+        .collect();
+    // Only asking how many there are needs no buffer.
+    if !bytes.is_empty() {
+        user::write(records, &bytes)?;
+    }
+    Ok(devices.len())
+}
+
+/// Page flags for `PROT_*` bits. Pages can't be made unreadable, nor writable and executable at
+/// once.
+fn protection(prot: usize) -> Result<PageTableFlags, SyscallError> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0
+        || prot & PROT_READ == 0
+        || prot & (PROT_WRITE | PROT_EXEC) == PROT_WRITE | PROT_EXEC
+    {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let mut flags = PageTableFlags::empty();
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    Ok(flags)
+}
+
+/// The pages of `addr..addr + len`, `len` rounded up. `addr` must be page aligned.
+fn pages(addr: usize, len: usize) -> Result<PageRange<Size4KiB>, SyscallError> {
+    const PAGE_SIZE: u64 = 4096;
+
+    let start = addr as u64;
+    let end = start
+        .checked_add(len as u64)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE))
+        .ok_or(SyscallError::EINVAL)?;
+    if start & (PAGE_SIZE - 1) != 0 || start < USER_START || end > USER_END {
+        return Err(SyscallError::EINVAL);
+    }
+    Ok(Page::range(
+        Page::containing_address(VirtAddr::new(start)),
+        Page::containing_address(VirtAddr::new(end)),
+    ))
+}
+
+fn map_error(e: MapError) -> SyscallError {
+    match e {
+        MapError::Invalid => SyscallError::EINVAL,
+        MapError::NoSpace | MapError::OutOfMemory => SyscallError::ENOMEM,
+    }
+}
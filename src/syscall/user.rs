@@ -0,0 +1,100 @@
+//! Copying syscall arguments in and out of the caller's memory.
+//!
+//! A pointer from a thread inside a process must lie in the user half and be mapped there
+//! `USER_ACCESSIBLE` (and `WRITABLE`, to be written to), or lie in an area that allows it to be;
+//! anything else is `EFAULT`. The copy goes
+//! through the physical memory mapping, so another thread unmapping the memory halfway can't
+//! fault the kernel. Kernel threads, which belong to no process, are trusted.
+
+use rust_alloc::vec;
+use rust_alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use super::SyscallError;
+use crate::mem::space::{USER_END, USER_START};
+use crate::process::{self, Process};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// The most a single syscall argument may copy.
+pub const MAX_COPY: usize = 0x1_0000;
+
+/// Copies `len` bytes from the caller's memory at `ptr`.
+pub fn read(ptr: usize, len: usize) -> Result<Vec<u8>, SyscallError> {
+    match process::current() {
+        Some(process) => {
+            check(&process, ptr, len, PageTableFlags::empty())?;
+            let mut buf = vec![0; len];
+            if !process.read(VirtAddr::new(ptr as u64), &mut buf) {
+                return Err(SyscallError::EFAULT);
+            }
+            Ok(buf)
+        }
+        None => {
+            kernel_check(ptr, len)?;
+            let mut buf = vec![0; len];
+            unsafe { core::ptr::copy_nonoverlapping(ptr as *const u8, buf.as_mut_ptr(), len) };
+            Ok(buf)
+        }
+    }
+}
+
+/// Copies `bytes` to the caller's memory at `ptr`.
+pub fn write(ptr: usize, bytes: &[u8]) -> Result<(), SyscallError> {
+    match process::current() {
+        Some(process) => {
+            check(&process, ptr, bytes.len(), PageTableFlags::WRITABLE)?;
+            if !process.write(VirtAddr::new(ptr as u64), bytes) {
+                return Err(SyscallError::EFAULT);
+            }
+        }
+        None => {
+            kernel_check(ptr, bytes.len())?;
+            unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, bytes.len()) };
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every page of `ptr..ptr + len` is mapped in `process`'s user half with `flags`.
+fn check(
+    process: &Process,
+    ptr: usize,
+    len: usize,
+    flags: PageTableFlags,
+) -> Result<(), SyscallError> {
+    if len > MAX_COPY {
+        return Err(SyscallError::EINVAL);
+    }
+    let start = ptr as u64;
+    let end = start.checked_add(len as u64).ok_or(SyscallError::EFAULT)?;
+    if start < USER_START || end > USER_END {
+        return Err(SyscallError::EFAULT);
+    }
+
+    let needed = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let write = flags.contains(PageTableFlags::WRITABLE);
+    let mut page = start & !(PAGE_SIZE - 1);
+    while page < end {
+        let addr = VirtAddr::new(page);
+        match process.flags(addr) {
+            Some(flags) if flags.contains(needed) => {}
+            // Not touched yet, as far as the process's areas allow it to be.
+            None if process.handle_fault(addr, write, false) => {}
+            _ => return Err(SyscallError::EFAULT),
+        }
+        page += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+fn kernel_check(ptr: usize, len: usize) -> Result<(), SyscallError> {
+    if len > MAX_COPY {
+        Err(SyscallError::EINVAL)
+    } else if ptr == 0 && len != 0 {
+        Err(SyscallError::EFAULT)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use core::task::{Context, Poll, Waker};
+
+use crossbeam_queue::ArrayQueue;
+use rust_alloc::collections::BTreeMap;
+use rust_alloc::sync::Arc;
+use rust_alloc::task::Wake;
+use x86_64::instructions::interrupts;
+
+use crate::thread::{self, ThreadId};
+
+use super::{Task, TaskId};
+
+const MAX_READY_TASKS: usize = 100;
+
+/// Runs tasks on the calling thread, blocking the thread whenever no task can make progress.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(MAX_READY_TASKS)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    pub fn run(&mut self) -> ! {
+        let thread = thread::current();
+        loop {
+            self.run_ready_tasks(thread);
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready_tasks(&mut self, thread: ThreadId) {
+        while let Some(task_id) = self.task_queue.pop() {
+            let task = match self.tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue, // task no longer exists
+            };
+
+            let waker = self
+                .waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::waker(task_id, thread, self.task_queue.clone()));
+            let mut context = Context::from_waker(waker);
+
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                self.tasks.remove(&task_id);
+                self.waker_cache.remove(&task_id);
+            }
+        }
+    }
+
+    /// Parks the executor's thread until a waker fires. The check and the block happen with
+    /// interrupts disabled, so a wakeup from an interrupt handler can't be missed.
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        if !self.task_queue.is_empty() {
+            interrupts::enable();
+        } else if thread::is_running() {
+            thread::block();
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    thread: ThreadId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    fn waker(task_id: TaskId, thread: ThreadId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            task_id,
+            thread,
+            task_queue,
+        }))
+    }
+
+    /// Must not block or allocate, it's called from interrupt handlers.
+    fn wake_task(&self) {
+        if self.task_queue.push(self.task_id).is_err() {
+            crate::io::logging::kernel_warning("Task queue full; dropping wakeup");
+        }
+        if thread::is_running() {
+            thread::wake(self.thread);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
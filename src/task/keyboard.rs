@@ -0,0 +1,16 @@
+use futures_util::stream::StreamExt;
+
+use crate::io::keybindings::handle_input;
+use crate::thread::ps2::{decode_scancode, ScancodeStream, KEYBOARD};
+
+/// Decodes scancodes as they arrive and hands them to the keybindings.
+pub async fn handle_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+
+    while let Some(scancode) = scancodes.next().await {
+        let decoded = decode_scancode(&mut *KEYBOARD.lock(), scancode);
+        if let Some(decoded_ok) = decoded {
+            handle_input(decoded_ok);
+        }
+    }
+}
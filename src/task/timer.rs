@@ -0,0 +1,81 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use rust_alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::time::rtc::{ticks, time_between_ticks};
+
+/// Pending timers as `(timer, deadline, waker)`.
+///
+/// Entries are only ever added and removed by the owning `Sleep`, so the PIT interrupt can wake
+/// them without dropping (and possibly deallocating) a waker.
+static TIMERS: Mutex<Vec<(usize, usize, Waker)>> = Mutex::new(Vec::new());
+static NEXT_TIMER: AtomicUsize = AtomicUsize::new(0);
+
+/// A future that completes once the PIT tick count reaches its deadline.
+pub struct Sleep {
+    timer: usize,
+    deadline: usize,
+}
+
+/// Completes after at least `seconds`.
+pub fn sleep(seconds: f64) -> Sleep {
+    sleep_ticks((seconds / time_between_ticks()) as usize)
+}
+
+/// Completes after at least `ticks` PIT ticks.
+pub fn sleep_ticks(ticks: usize) -> Sleep {
+    Sleep {
+        timer: NEXT_TIMER.fetch_add(1, Ordering::Relaxed),
+        deadline: self::ticks() + ticks,
+    }
+}
+
+impl Sleep {
+    fn unregister(&self) {
+        without_interrupts(|| {
+            TIMERS.lock().retain(|(timer, _, _)| *timer != self.timer);
+        });
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        self.unregister();
+        if ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        without_interrupts(|| {
+            TIMERS
+                .lock()
+                .push((self.timer, self.deadline, cx.waker().clone()));
+        });
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// Wakes every timer whose deadline has passed. Called by the PIT interrupt handler.
+///
+/// Must not block or allocate.
+pub fn wake_expired(now: usize) {
+    if let Some(timers) = TIMERS.try_lock() {
+        for (_, deadline, waker) in timers.iter() {
+            if *deadline <= now {
+                waker.wake_by_ref();
+            }
+        }
+    }
+}
@@ -1,19 +1,50 @@
 pub mod ps2;
 pub mod queue;
+pub mod sched;
+mod sleep;
+pub mod stack;
+pub mod sync;
 
-use core::arch::{asm, naked_asm};
+use core::arch::naked_asm;
+use core::fmt;
 use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use rust_alloc::boxed::Box;
+use rust_alloc::string::{String, ToString};
+use rust_alloc::sync::Arc;
 use rust_alloc::vec::Vec;
+use x86_64::instructions::interrupts::{self, without_interrupts};
 
-use crate::cpu::gdt::STACK_SIZE;
+use crate::cpu::{gdt, percpu};
 use crate::io::logging::*;
+use crate::mem::paging;
+use crate::process::Process;
+use crate::time::rtc::{ticks, time_between_ticks};
 use crate::THREAD_QUEUE;
 
-use self::queue::ThreadQueue;
+pub use self::sched::Priority;
+pub use lateral_abi::FAULT_EXIT_CODE;
 
-const MAX_THREADS: usize = 32;
-static mut RUNTIME: usize = 0;
+use self::sched::RunQueue;
+use self::sleep::SleepQueue;
+use self::stack::Stack;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+/// Set while the BSP's base thread waits in `Runtime::run` for everything else to exit.
+static RUN_WAITING: AtomicBool = AtomicBool::new(false);
+/// Threads currently being moved between runtimes, and how many moves have finished. Lets a
+/// lookup by id tell a thread that doesn't exist from one it missed while it was in flight.
+static MIGRATING: AtomicUsize = AtomicUsize::new(0);
+static MIGRATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The body of a thread, boxed so it can carry whatever state it closes over.
+pub type ThreadFn = Box<dyn FnOnce() + Send + 'static>;
+
+/// Default length of a time slice, in PIT ticks (roughly 1ms each).
+pub const DEFAULT_QUANTUM: usize = 10;
+
+static QUANTUM: AtomicUsize = AtomicUsize::new(DEFAULT_QUANTUM);
 
 #[derive(Debug, Default, Clone)]
 #[repr(C)]
@@ -27,33 +58,189 @@ struct ThreadContext {
     rbp: u64,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
-enum State {
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum State {
+    /// The slot is free and will be reused by the next spawn.
     Available,
     Running,
     Ready,
+    /// Waiting for something (a thread it joined, a sleep deadline) to wake it up.
+    Blocked,
+    /// Returned, but its exit code hasn't been collected by `join` yet.
+    Exited,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct ThreadId(usize);
+
+impl ThreadId {
+    /// The thread that entered `Runtime::run` on the BSP. Each AP's base thread gets a fresh id.
+    pub const BASE: ThreadId = ThreadId(0);
+
+    pub(crate) fn next() -> Self {
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Recovers an id passed through a syscall.
+    pub fn from_usize(id: usize) -> Self {
+        ThreadId(id)
+    }
+
+    pub fn as_usize(&self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
-#[derive(Clone)]
 struct Thread {
-    stack: [u8; STACK_SIZE],
+    id: ThreadId,
+    name: String,
+    /// `None` for a base thread, which keeps running on its CPU's boot stack, for a slot left
+    /// behind by a thread that moved to another CPU, and once the idle thread has freed the stack
+    /// of a reaped thread.
+    stack: Option<Stack>,
     ctx: ThreadContext,
     state: State,
+    priority: Priority,
+    exit_code: i32,
+    /// Nobody holds a `JoinHandle`, so the slot is freed as soon as the thread exits.
+    detached: bool,
+    /// The thread blocked in `join` on this one.
+    joiner: Option<ThreadId>,
+    /// PIT ticks that landed while this thread was running.
+    cpu_ticks: usize,
+    /// A `wake` arrived while the thread wasn't blocked yet; its next `block` returns at once.
+    wake_pending: bool,
+    /// Never moved to another CPU. Only base and idle threads are pinned.
+    pinned: bool,
+    /// The process whose address space the thread runs in, or `None` for a kernel thread. Kept
+    /// after the thread exits until its slot is reused, so its page tables are never released
+    /// while they may still be loaded.
+    process: Option<Arc<Process>>,
 }
 
 impl Thread {
-    fn new() -> Self {
+    fn new(id: ThreadId) -> Self {
+        Thread {
+            stack: Some(Stack::new(id).expect("out of frames for a thread stack.")),
+            ..Thread::vacant(id)
+        }
+    }
+
+    /// An `Available` slot without a stack; one is mapped if the slot is ever spawned into.
+    fn vacant(id: ThreadId) -> Self {
         Thread {
-            stack: [0_u8; STACK_SIZE],
+            id,
+            name: String::new(),
+            stack: None,
             ctx: ThreadContext::default(),
             state: State::Available,
+            priority: Priority::Normal,
+            exit_code: 0,
+            detached: false,
+            joiner: None,
+            cpu_ticks: 0,
+            wake_pending: false,
+            pinned: false,
+            process: None,
         }
     }
 }
 
+/// A snapshot of a thread, for listing what's running and how much CPU time it has used.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: String,
+    pub state: State,
+    pub priority: Priority,
+    /// The CPU whose runtime the thread belongs to.
+    pub cpu: usize,
+    /// PIT ticks spent running since the thread was spawned.
+    pub cpu_ticks: usize,
+}
+
+/// An owned permission to join on a thread. Dropping it detaches the thread.
+pub struct JoinHandle {
+    id: ThreadId,
+}
+
+impl JoinHandle {
+    pub(crate) fn new(id: ThreadId) -> Self {
+        JoinHandle { id }
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn state(&self) -> Option<State> {
+        state(self.id)
+    }
+
+    /// Blocks until the thread exits and returns its exit code.
+    pub fn join(self) -> i32 {
+        let id = self.id;
+        core::mem::forget(self);
+
+        without_interrupts(|| loop {
+            let me = current();
+            let exit_code = with_thread(id, |rt, index| {
+                let thread = &mut rt.threads[index];
+                if thread.state == State::Exited {
+                    thread.state = State::Available;
+                    Some(thread.exit_code)
+                } else {
+                    thread.joiner = Some(me);
+                    None
+                }
+            })
+            .expect("joined a thread that was already reaped.");
+
+            if let Some(exit_code) = exit_code {
+                return exit_code;
+            }
+            block();
+        })
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            if is_running() {
+                with_thread(self.id, |rt, index| rt.detach(index));
+            } else {
+                THREAD_QUEUE.lock().detach(self.id);
+            }
+        });
+    }
+}
+
+/// Schedules threads on one CPU. Every CPU gets its own, and idle ones steal ready threads from
+/// the others.
+///
+/// All mutable state is guarded by `lock`, which is only taken with interrupts disabled. It is
+/// held across a context switch and released by the thread switched to.
 pub struct Runtime {
     threads: Vec<Thread>,
     current: usize,
+    /// Indices of every `Ready` thread, by priority.
+    ready: RunQueue,
+    /// Only ever touched by this CPU with interrupts disabled, so unlike the rest it can grow
+    /// before `lock` is taken.
+    sleepers: SleepQueue,
+    /// Runs whenever nothing else is ready, so there is always a thread to switch to.
+    idle: ThreadId,
+    cpu: usize,
+    lock: AtomicBool,
+    /// Ticks left in the running thread's time slice.
+    slice: AtomicUsize,
 }
 
 impl Default for Runtime {
@@ -64,136 +251,830 @@ impl Default for Runtime {
 
 impl Runtime {
     pub fn new() -> Self {
+        let id = if percpu::id() == 0 {
+            ThreadId::BASE
+        } else {
+            ThreadId::next()
+        };
         let base_thread = Thread {
-            stack: [0_u8; STACK_SIZE],
-            ctx: ThreadContext::default(),
+            name: "kernel".to_string(),
             state: State::Running,
+            detached: true,
+            pinned: true,
+            ..Thread::vacant(id)
         };
 
-        let mut threads = [base_thread].to_vec();
-        let mut available_threads: Vec<Thread> = (1..MAX_THREADS).map(|_| Thread::new()).collect();
-        threads.append(&mut available_threads);
-
         Runtime {
-            threads,
+            threads: [base_thread].into(),
             current: 0,
+            ready: RunQueue::new(),
+            sleepers: SleepQueue::new(),
+            idle: ThreadId::BASE,
+            cpu: percpu::id(),
+            lock: AtomicBool::new(false),
+            slice: AtomicUsize::new(quantum()),
         }
     }
 
-    pub fn init(&self) {
-        unsafe {
-            let r_ptr: *const Runtime = self;
-            RUNTIME = r_ptr as usize;
-        }
+    /// Makes this the calling CPU's runtime, used by `yield_thread`, `spawn_thread` and friends,
+    /// starts its idle thread and spawns anything that was queued before any runtime existed.
+    pub fn init(&mut self) {
+        without_interrupts(|| {
+            let r_ptr: *mut Runtime = self;
+            percpu::current()
+                .runtime
+                .store(r_ptr as usize, Ordering::Release);
+
+            self.idle = ThreadId::next();
+            self.spawn_with_id(
+                self.idle,
+                "idle",
+                Priority::Idle,
+                None,
+                Box::new(idle),
+                true,
+            );
+            self.lock();
+            if let Some(index) = self.find(self.idle) {
+                self.threads[index].pinned = true;
+            }
+            self.unlock();
+
+            let queued = core::mem::take(&mut THREAD_QUEUE.lock().contents);
+            for queued in queued {
+                self.spawn_with_id(
+                    queued.id,
+                    &queued.name,
+                    queued.priority,
+                    queued.process,
+                    queued.entry,
+                    queued.detached,
+                );
+            }
+        });
     }
 
+    /// Hands the CPU over to the spawned threads. The base thread sleeps until every thread on
+    /// every CPU has exited, after which only the idle threads are left running.
     pub fn run(&mut self) -> ! {
-        while self.t_yield() {}
+        without_interrupts(|| {
+            self.lock();
+            self.threads[0].priority = Priority::Idle;
+            self.unlock();
+        });
+        RUN_WAITING.store(true, Ordering::Release);
+
+        while alive() {
+            // Woken by `exit` once nothing else is left.
+            block();
+        }
+        RUN_WAITING.store(false, Ordering::Release);
         kernel_event("All threads have exited.");
+
+        self.park();
+    }
+
+    /// Blocks the base thread for good, leaving the CPU to the spawned and idle threads.
+    pub fn park(&mut self) -> ! {
+        without_interrupts(|| {
+            self.lock();
+            self.threads[0].priority = Priority::Idle;
+            self.unlock();
+        });
+
         loop {
+            block();
+        }
+    }
+
+    pub fn state(&self, id: ThreadId) -> Option<State> {
+        self.locked(|rt| rt.find(id).map(|index| rt.threads[index].state))
+    }
+
+    pub fn name(&self, id: ThreadId) -> Option<String> {
+        self.locked(|rt| rt.find(id).map(|index| rt.threads[index].name.clone()))
+    }
+
+    pub fn priority(&self, id: ThreadId) -> Option<Priority> {
+        self.locked(|rt| rt.find(id).map(|index| rt.threads[index].priority))
+    }
+
+    /// Every live thread on this runtime, including the base and idle threads.
+    pub fn threads(&self) -> Vec<ThreadInfo> {
+        self.locked(|rt| {
+            rt.threads
+                .iter()
+                .filter(|t| t.state != State::Available)
+                .map(|t| ThreadInfo {
+                    id: t.id,
+                    name: t.name.clone(),
+                    state: t.state,
+                    priority: t.priority,
+                    cpu: rt.cpu,
+                    cpu_ticks: t.cpu_ticks,
+                })
+                .collect()
+        })
+    }
+
+    /// PIT ticks spent in the idle thread, i.e. with nothing else to run.
+    pub fn idle_ticks(&self) -> usize {
+        self.locked(|rt| {
+            rt.find(rt.idle)
+                .map_or(0, |index| rt.threads[index].cpu_ticks)
+        })
+    }
+
+    pub fn spawn<F>(&mut self, name: &str, f: F) -> JoinHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawn_with_priority(name, Priority::Normal, f)
+    }
+
+    pub fn spawn_with_priority<F>(&mut self, name: &str, priority: Priority, f: F) -> JoinHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let id = ThreadId::next();
+        self.spawn_with_id(id, name, priority, None, Box::new(f), false);
+        JoinHandle::new(id)
+    }
+
+    fn spawn_with_id(
+        &mut self,
+        id: ThreadId,
+        name: &str,
+        priority: Priority,
+        process: Option<Arc<Process>>,
+        f: ThreadFn,
+        detached: bool,
+    ) {
+        without_interrupts(|| {
+            self.lock();
+            // Prefer a slot that still has its stack mapped, in case the idle thread hasn't freed
+            // it yet.
+            let available = |t: &Thread| t.state == State::Available && !t.pinned;
+            let index = match self
+                .threads
+                .iter()
+                .position(|t| available(t) && t.stack.is_some())
+                .or_else(|| self.threads.iter().position(available))
+            {
+                Some(index) => index,
+                None => {
+                    self.threads.push(Thread::new(id));
+                    self.ready.reserve(self.threads.len());
+                    self.threads.len() - 1
+                }
+            };
+            let available = &mut self.threads[index];
+
+            let stack = available
+                .stack
+                .get_or_insert_with(|| Stack::new(id).expect("out of frames for a thread stack."));
+            stack.set_owner(id);
+            unsafe {
+                let s_ptr = stack.top().as_mut_ptr::<u8>();
+                let s_ptr = (s_ptr as usize & !15) as *mut u8;
+                ptr::write(s_ptr.offset(-8) as *mut usize, entry as usize);
+                available.ctx.rsp = s_ptr.offset(-8) as u64;
+            }
+            // Double boxed so `entry` only has to carry a thin pointer; `start` takes it back.
+            available.ctx.r12 = Box::into_raw(Box::new(f)) as u64;
+            available.id = id;
+            available.name = name.to_string();
+            available.exit_code = 0;
+            available.detached = detached;
+            available.joiner = None;
+            available.cpu_ticks = 0;
+            available.wake_pending = false;
+            available.priority = priority;
+            available.process = process;
+            self.make_ready(index);
+            self.unlock();
+        });
+    }
+
+    /// Changes a thread's priority, moving it to its new queue if it's waiting to run.
+    pub fn set_priority(&mut self, id: ThreadId, priority: Priority) -> bool {
+        without_interrupts(|| {
+            self.lock();
+            let found = self
+                .find(id)
+                .map(|index| self.set_priority_of(index, priority));
+            self.unlock();
+            found.is_some()
+        })
+    }
+
+    fn set_priority_of(&mut self, index: usize, priority: Priority) {
+        self.threads[index].priority = priority;
+        if self.threads[index].state == State::Ready {
+            self.ready.remove(index);
+            self.make_ready(index);
+        } else if index == self.current {
+            // Let the scheduler reconsider on the next tick in case it was lowered.
+            self.slice.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn find(&self, id: ThreadId) -> Option<usize> {
+        self.threads
+            .iter()
+            .position(|t| t.id == id && t.state != State::Available)
+    }
+
+    fn lock(&self) {
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
             core::hint::spin_loop();
         }
     }
 
-    fn t_return(&mut self) {
-        if self.current != 0 {
-            self.threads[self.current].state = State::Available;
-            self.t_yield();
+    fn try_lock(&self) -> bool {
+        self.lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock(&self) {
+        self.lock.store(false, Ordering::Release);
+    }
+
+    fn locked<R>(&self, f: impl FnOnce(&Self) -> R) -> R {
+        without_interrupts(|| {
+            self.lock();
+            let result = f(self);
+            self.unlock();
+            result
+        })
+    }
+
+    /// Readies a blocked thread, or makes its next `block` return straight away if it hasn't
+    /// blocked yet. Returns `false` if the thread has exited.
+    fn wake(&mut self, index: usize) -> bool {
+        match self.threads[index].state {
+            State::Blocked => {
+                self.make_ready(index);
+                true
+            }
+            State::Running | State::Ready => {
+                self.threads[index].wake_pending = true;
+                true
+            }
+            State::Available | State::Exited => false,
         }
     }
 
-    fn t_yield(&mut self) -> bool {
-        x86_64::instructions::interrupts::without_interrupts(|| {
-            let mut pos = self.current;
-            while self.threads[pos].state != State::Ready {
-                pos += 1;
-                if pos == self.threads.len() {
-                    pos = 0;
-                }
-                if pos == self.current {
-                    return false;
-                }
+    /// Readies every sleeper whose deadline has passed.
+    fn wake_sleepers(&mut self, now: usize) {
+        while let Some((index, id)) = self.sleepers.pop_expired(now) {
+            let thread = &self.threads[index];
+            if thread.id == id && thread.state == State::Blocked {
+                self.make_ready(index);
             }
+        }
+    }
+
+    fn detach(&mut self, index: usize) {
+        let thread = &mut self.threads[index];
+        thread.detached = true;
+        if thread.state == State::Exited {
+            thread.state = State::Available;
+        }
+    }
+
+    /// Queues a thread to run. If it outranks the running thread, the running thread is
+    /// preempted on the next tick instead of at the end of its time slice.
+    fn make_ready(&mut self, index: usize) {
+        let priority = self.threads[index].priority;
+        self.threads[index].state = State::Ready;
+        self.ready.push(priority, index);
+
+        if priority < self.threads[self.current].priority {
+            self.slice.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Switches to the highest priority ready thread. A thread that is still running only gives
+    /// way to threads of the same or a higher priority; returns `false` if it keeps the CPU.
+    ///
+    /// # Safety
+    /// Interrupts must be disabled and the runtime locked. The lock is released by whichever
+    /// thread runs next. By the time this returns the calling thread may have been moved to
+    /// another CPU, so `self` must not be used afterwards.
+    unsafe fn switch_next(&mut self) -> bool {
+        let running = self.threads[self.current].state == State::Running;
+        let current_priority = self.threads[self.current].priority;
+        self.slice.store(quantum(), Ordering::Relaxed);
+
+        let limit = if running {
+            current_priority
+        } else {
+            Priority::Idle
+        };
 
-            if self.threads[self.current].state != State::Available {
-                self.threads[self.current].state = State::Ready;
+        let pos = match self.ready.pop(limit) {
+            Some(pos) => pos,
+            None if running => {
+                self.ready.age(current_priority);
+                self.unlock();
+                return false;
             }
+            None => panic!("the idle thread wasn't ready."),
+        };
 
-            self.threads[pos].state = State::Running;
-            let old_pos = self.current;
-            self.current = pos;
+        self.ready.age(self.threads[pos].priority);
 
-            unsafe {
-                let old: *mut ThreadContext = &mut self.threads[old_pos].ctx;
-                let new: *const ThreadContext = &self.threads[pos].ctx;
-                asm!(
-                    "mov {old}, %rdi",
-                    "mov {new}, %rsi",
-                    old = in(reg) old,
-                    new = in(reg) new,
-                    // "r"(old), "r"(new)
-                    options(att_syntax)
-                );
-                switch();
+        if running {
+            self.make_ready(self.current);
+        }
+
+        self.threads[pos].state = State::Running;
+        let old_pos = self.current;
+        self.current = pos;
+
+        if old_pos != pos {
+            if let Some(stack) = &self.threads[pos].stack {
+                gdt::set_kernel_stack(stack.top());
             }
+            let table = match &self.threads[pos].process {
+                Some(process) => process.table(),
+                None => paging::kernel_table(),
+            };
+            paging::activate(table);
 
-            unsafe {
-                #[allow(static_mut_refs)]
-                for i in 0..THREAD_QUEUE.len() {
-                    self.spawn(THREAD_QUEUE.contents[i]);
+            let old: *mut ThreadContext = &mut self.threads[old_pos].ctx;
+            let new: *const ThreadContext = &self.threads[pos].ctx;
+            switch(old, new);
+        }
+
+        runtime().unlock();
+        true
+    }
+
+    /// Moves a ready thread from another CPU onto this one. Only called from the idle thread, as
+    /// adopting the thread may grow `threads`.
+    fn steal(&mut self) -> bool {
+        without_interrupts(|| {
+            self.lock();
+            let this: *const Runtime = self;
+
+            for victim in runtimes() {
+                // Never wait on another runtime while holding this one, or two CPUs stealing from
+                // each other would deadlock.
+                if ptr::eq(victim, this) || !victim.try_lock() {
+                    continue;
                 }
-                THREAD_QUEUE = ThreadQueue::new();
+
+                let index = victim
+                    .ready
+                    .steal(Priority::Normal, |index| !victim.threads[index].pinned);
+                let Some(index) = index else {
+                    victim.unlock();
+                    continue;
+                };
+
+                MIGRATING.fetch_add(1, Ordering::AcqRel);
+                let id = victim.threads[index].id;
+                let thread = core::mem::replace(&mut victim.threads[index], Thread::vacant(id));
+                victim.unlock();
+
+                let index = self.adopt(thread);
+                self.make_ready(index);
+                MIGRATIONS.fetch_add(1, Ordering::AcqRel);
+                MIGRATING.fetch_sub(1, Ordering::AcqRel);
+
+                self.unlock();
+                return true;
             }
 
-            !self.threads.is_empty()
+            self.unlock();
+            false
         })
     }
 
-    pub fn spawn(&mut self, f: fn()) {
-        let available = self
+    /// Frees the stacks of reaped threads. Only called from the idle thread, which runs on none of
+    /// them; each is dropped with the runtime unlocked, as that waits on the other CPUs.
+    fn free_stacks(&mut self) {
+        loop {
+            let stack = without_interrupts(|| {
+                self.lock();
+                let stack = self
+                    .threads
+                    .iter_mut()
+                    .filter(|t| t.state == State::Available && !t.pinned)
+                    .find_map(|t| t.stack.take());
+                self.unlock();
+                stack
+            });
+            let Some(stack) = stack else {
+                return;
+            };
+            drop(stack);
+        }
+    }
+
+    /// Takes in a thread from another runtime, preferably into a slot some thread left behind
+    /// when it moved away.
+    fn adopt(&mut self, thread: Thread) -> usize {
+        let vacant = self
             .threads
-            .iter_mut()
-            .find(|t| t.state == State::Available)
-            .expect("no available thread.");
+            .iter()
+            .position(|t| t.state == State::Available && t.stack.is_none() && !t.pinned);
 
-        let size = available.stack.len();
-        unsafe {
-            let s_ptr = available.stack.as_mut_ptr().add(size);
-            let s_ptr = (s_ptr as usize & !15) as *mut u8;
-            ptr::write(s_ptr.offset(-16) as *mut usize, guard as usize);
-            ptr::write(s_ptr.offset(-24) as *mut usize, skip as usize);
-            ptr::write(s_ptr.offset(-32) as *mut usize, f as usize);
-            available.ctx.rsp = s_ptr.offset(-32) as u64;
+        match vacant {
+            Some(index) => {
+                self.threads[index] = thread;
+                index
+            }
+            None => {
+                self.threads.push(thread);
+                self.ready.reserve(self.threads.len());
+                self.threads.len() - 1
+            }
         }
-        available.state = State::Ready;
     }
 }
 
-#[naked]
-fn skip() {
-    unsafe {
-        naked_asm!("");
+/// Every CPU's runtime.
+fn runtimes() -> impl Iterator<Item = &'static mut Runtime> {
+    percpu::online().filter_map(|cpu| {
+        let ptr = cpu.runtime.load(Ordering::Acquire);
+        (ptr != 0).then(|| unsafe { &mut *(ptr as *mut Runtime) })
+    })
+}
+
+/// Runs `f` on the runtime that owns thread `id`, with that runtime locked. Returns `None` if no
+/// runtime has it.
+///
+/// Must be called with interrupts disabled and without holding any runtime's lock.
+fn with_thread<R>(id: ThreadId, f: impl FnOnce(&mut Runtime, usize) -> R) -> Option<R> {
+    loop {
+        let migrations = MIGRATIONS.load(Ordering::Acquire);
+        for rt in runtimes() {
+            rt.lock();
+            if let Some(index) = rt.find(id) {
+                let result = f(rt, index);
+                rt.unlock();
+                return Some(result);
+            }
+            rt.unlock();
+        }
+
+        // The thread may have been in flight between two runtimes while we looked.
+        if MIGRATING.load(Ordering::Acquire) == 0
+            && MIGRATIONS.load(Ordering::Acquire) == migrations
+        {
+            return None;
+        }
     }
 }
 
-fn guard() {
+/// Whether any thread other than the base and idle threads is still around, on any CPU.
+fn alive() -> bool {
+    without_interrupts(|| {
+        runtimes().any(|rt| {
+            rt.locked(|rt| {
+                rt.threads
+                    .iter()
+                    .any(|t| !t.pinned && !matches!(t.state, State::Available | State::Exited))
+            })
+        })
+    })
+}
+
+/// Spawns a thread on the calling CPU's runtime, or queues it until `Runtime::init` if there is
+/// none.
+pub fn spawn<F>(name: &str, f: F) -> JoinHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_priority(name, Priority::Normal, f)
+}
+
+/// Spawns a thread with the given priority, the same way as [`spawn`].
+pub fn spawn_with_priority<F>(name: &str, priority: Priority, f: F) -> JoinHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_or_queue(ThreadId::next(), name, priority, None, Box::new(f))
+}
+
+/// Spawns a thread inside `process`, the same way as [`spawn`]. It runs on the process's page
+/// tables from its first instruction.
+pub fn spawn_in<F>(process: Arc<Process>, name: &str, priority: Priority, f: F) -> JoinHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    let id = ThreadId::next();
+    process.thread_started(id);
+    spawn_or_queue(id, name, priority, Some(process), Box::new(f))
+}
+
+fn spawn_or_queue(
+    id: ThreadId,
+    name: &str,
+    priority: Priority,
+    process: Option<Arc<Process>>,
+    f: ThreadFn,
+) -> JoinHandle {
+    match local() {
+        Some(rt) => rt.spawn_with_id(id, name, priority, process, f, false),
+        None => without_interrupts(|| THREAD_QUEUE.lock().push(id, name, priority, process, f)),
+    }
+    JoinHandle::new(id)
+}
+
+pub fn set_priority(id: ThreadId, priority: Priority) -> bool {
+    without_interrupts(|| {
+        with_thread(id, |rt, index| rt.set_priority_of(index, priority)).is_some()
+    })
+}
+
+pub fn priority(id: ThreadId) -> Option<Priority> {
+    without_interrupts(|| with_thread(id, |rt, index| rt.threads[index].priority))
+}
+
+/// A snapshot of every live thread on every CPU.
+pub fn threads() -> Vec<ThreadInfo> {
+    runtimes().flat_map(|rt| rt.threads()).collect()
+}
+
+/// PIT ticks the CPUs have spent halted in their idle threads, summed over every CPU.
+pub fn idle_ticks() -> usize {
+    runtimes().map(|rt| rt.idle_ticks()).sum()
+}
+
+/// The id of the calling thread.
+pub fn current() -> ThreadId {
+    without_interrupts(|| match local() {
+        Some(rt) => rt.threads[rt.current].id,
+        None => ThreadId::BASE,
+    })
+}
+
+/// The process the calling thread belongs to, or `None` for a kernel thread.
+pub fn process() -> Option<Arc<Process>> {
+    without_interrupts(|| {
+        let rt = local()?;
+        rt.locked(|rt| rt.threads[rt.current].process.clone())
+    })
+}
+
+/// The name of the calling thread.
+pub fn name() -> String {
+    without_interrupts(|| match local() {
+        Some(rt) => rt.locked(|rt| rt.threads[rt.current].name.clone()),
+        None => "kernel".to_string(),
+    })
+}
+
+pub fn state(id: ThreadId) -> Option<State> {
+    without_interrupts(|| {
+        if !is_running() {
+            return THREAD_QUEUE.lock().contains(id).then_some(State::Ready);
+        }
+        with_thread(id, |rt, index| rt.threads[index].state)
+    })
+}
+
+/// Blocks the calling thread for at least `seconds`, letting other threads run meanwhile.
+pub fn sleep(seconds: f64) {
+    sleep_ticks((seconds / time_between_ticks()) as usize);
+}
+
+/// Blocks the calling thread until `ticks` PIT ticks have passed.
+pub fn sleep_ticks(ticks: usize) {
+    let deadline = self::ticks() + ticks;
+    without_interrupts(|| {
+        // A `wake` meant for something else can cut the sleep short, so go back to sleep until
+        // the deadline has really passed.
+        while self::ticks() < deadline {
+            unsafe {
+                let rt = runtime();
+                rt.sleepers.reserve(1);
+                rt.lock();
+                let id = rt.threads[rt.current].id;
+                rt.sleepers.push(deadline, rt.current, id);
+                rt.threads[rt.current].state = State::Blocked;
+                rt.switch_next();
+            }
+        }
+    });
+}
+
+/// Blocks the calling thread until another thread `wake`s it.
+///
+/// Whoever is going to wake it must already know about it (e.g. through a `sync::WaitQueue`),
+/// otherwise it sleeps forever. May return spuriously, so callers should re-check whatever they
+/// were waiting for.
+pub fn block() {
+    without_interrupts(|| unsafe {
+        let rt = runtime();
+        rt.lock();
+        let thread = &mut rt.threads[rt.current];
+        if thread.wake_pending {
+            thread.wake_pending = false;
+            rt.unlock();
+            return;
+        }
+
+        thread.state = State::Blocked;
+        rt.switch_next();
+    });
+}
+
+/// Like [`block`], but also returns once the PIT tick count reaches `deadline`.
+pub fn block_until(deadline: usize) {
+    without_interrupts(|| unsafe {
+        let rt = runtime();
+        rt.sleepers.reserve(1);
+        rt.lock();
+        let thread = &mut rt.threads[rt.current];
+        if thread.wake_pending || ticks() >= deadline {
+            thread.wake_pending = false;
+            rt.unlock();
+            return;
+        }
+
+        let id = thread.id;
+        rt.sleepers.push(deadline, rt.current, id);
+        rt.threads[rt.current].state = State::Blocked;
+        rt.switch_next();
+    });
+}
+
+/// Wakes a thread on any CPU. Returns `false` if it has exited.
+pub fn wake(id: ThreadId) -> bool {
+    without_interrupts(|| with_thread(id, |rt, index| rt.wake(index)).unwrap_or(false))
+}
+
+/// Whether the calling CPU has a runtime, i.e. whether blocking calls can switch threads.
+pub fn is_running() -> bool {
+    local().is_some()
+}
+
+/// Ends the calling thread with the given exit code, which is handed to whoever joins it.
+pub fn exit(code: i32) -> ! {
+    interrupts::disable();
+    let rt = unsafe { runtime() };
+    rt.lock();
+
+    let thread = &mut rt.threads[rt.current];
+    if thread.pinned {
+        rt.unlock();
+        interrupts::enable();
+        unreachable!("the base thread can't exit.");
+    }
+
+    thread.exit_code = code;
+    thread.state = if thread.detached {
+        State::Available
+    } else {
+        State::Exited
+    };
+    let joiner = thread.joiner.take();
+    let (id, process) = (thread.id, thread.process.clone());
+    rt.unlock();
+
+    if let Some(process) = process {
+        process.thread_exited(id, code);
+    }
+    if let Some(joiner) = joiner {
+        wake(joiner);
+    }
+    if RUN_WAITING.load(Ordering::Acquire) && !alive() {
+        wake(ThreadId::BASE);
+    }
+
     unsafe {
-        let rt_ptr = RUNTIME as *mut Runtime;
-        (*rt_ptr).t_return();
+        let rt = runtime();
+        rt.lock();
+        rt.switch_next();
+    }
+    unreachable!("exited thread was scheduled again.");
+}
+
+/// Sets the length of a time slice in PIT ticks. Takes effect from the next switch.
+pub fn set_quantum(ticks: usize) {
+    QUANTUM.store(ticks.max(1), Ordering::Relaxed);
+}
+
+pub fn quantum() -> usize {
+    QUANTUM.load(Ordering::Relaxed)
+}
+
+/// Called by the timer interrupt handler on every tick: the PIT on the BSP, the local APIC timer
+/// on every other CPU.
+///
+/// Must not block or allocate.
+pub fn tick() {
+    let Some(rt) = local() else {
+        return;
     };
+
+    let remaining = rt.slice.load(Ordering::Relaxed);
+    if remaining > 0 {
+        rt.slice.store(remaining - 1, Ordering::Relaxed);
+    }
+
+    rt.lock();
+    rt.threads[rt.current].cpu_ticks += 1;
+    rt.wake_sleepers(ticks());
+    rt.unlock();
 }
 
-pub fn yield_thread() {
-    unsafe {
-        let rt_ptr = RUNTIME as *mut Runtime;
-        (*rt_ptr).t_yield();
+/// Called at the end of the timer interrupt, after the interrupt has been acknowledged.
+///
+/// The interrupted thread's registers have already been pushed by the interrupt wrapper,
+/// so switching away here and returning later through `iretq` resumes it exactly where it was.
+pub fn preempt() {
+    let Some(rt) = local() else {
+        return;
     };
+
+    if rt.slice.load(Ordering::Relaxed) != 0 {
+        return;
+    }
+
+    rt.lock();
+    unsafe { rt.switch_next() };
 }
 
-//*  Replace with `asm!` or else
+/// First code run by a new thread: `switch` returns here with the boxed `ThreadFn` in `r12` and
+/// the runtime still locked by the thread that switched away. Interrupts are only re-enabled
+/// once the lock is released.
+#[naked]
+unsafe extern "sysv64" fn entry() {
+    naked_asm!(
+        "call {release}",
+        "sti",
+        "mov %r12, %rdi",
+        "call {start}",
+        "call {guard}",
+        "ud2",
+        release = sym release,
+        start = sym start,
+        guard = sym guard,
+        options(att_syntax)
+    );
+}
+
+extern "sysv64" fn release() {
+    unsafe { runtime().unlock() };
+}
+
+/// Runs the thread's body. Both boxes are freed by the time this returns into `guard`.
+extern "sysv64" fn start(f: *mut ThreadFn) {
+    let f = unsafe { Box::from_raw(f) };
+    (*f)();
+}
+
+/// Body of the idle thread: picks up work from busier CPUs, otherwise halts until the next
+/// interrupt and gives way to whatever it woke.
+fn idle() {
+    loop {
+        let rt = unsafe { runtime() };
+        rt.free_stacks();
+        stack::refill_reserve();
+        if !rt.steal() {
+            x86_64::instructions::hlt();
+        }
+        yield_thread();
+    }
+}
+
+fn guard() {
+    exit(0);
+}
+
+pub fn yield_thread() {
+    without_interrupts(|| unsafe {
+        let rt = runtime();
+        rt.lock();
+        rt.switch_next();
+    });
+}
+
+/// The calling CPU's runtime, if `Runtime::init` has been called on it.
+fn local() -> Option<&'static mut Runtime> {
+    let ptr = percpu::current().runtime.load(Ordering::Acquire);
+    (ptr != 0).then(|| unsafe { &mut *(ptr as *mut Runtime) })
+}
+
+/// # Safety
+/// `Runtime::init` must have been called on the calling CPU, and the runtime must still be alive.
+unsafe fn runtime() -> &'static mut Runtime {
+    &mut *(percpu::current().runtime.load(Ordering::Acquire) as *mut Runtime)
+}
 
 #[naked]
-unsafe fn switch() {
+unsafe extern "sysv64" fn switch(_old: *mut ThreadContext, _new: *const ThreadContext) {
     naked_asm!(
         "mov     %rsp, 0x00(%rdi)",
         "mov     %r15, 0x08(%rdi)",
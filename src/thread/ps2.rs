@@ -1,7 +1,12 @@
-use crate::cpu::interrupt::set_irq_handler;
-use crate::io::keybindings::handle_input;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use crate::cpu::interrupt::add_irq_handler;
 use crate::io::logging::kernel_warning;
+use conquer_once::spin::OnceCell;
 use crossbeam_queue::ArrayQueue;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
 use pc_keyboard::{
     layouts, DecodedKey, HandleControl, KeyCode, Keyboard, KeyboardLayout, ScancodeSet,
     ScancodeSet1,
@@ -20,15 +25,11 @@ lazy_static! {
         ));
 }
 
-lazy_static! {
-    pub static ref SCANCODE_QUEUE: ArrayQueue<u8> = ArrayQueue::new(100);
-}
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
 
 pub fn init_ps2() {
-    set_irq_handler(1, add_scancode);
-    /*SCANCODE_QUEUE
-    .try_init_once(|| Vec::new())
-    .expect("ScancodeStream::new should only be called once");*/
+    add_irq_handler(1, add_scancode);
 }
 
 fn read_scancode() -> u8 {
@@ -42,13 +43,57 @@ fn read_scancode() -> u8 {
 fn add_scancode() {
     let scancode = read_scancode();
 
-    if SCANCODE_QUEUE.push(scancode).is_err() {
-        kernel_warning("Scancode queue full; dropping keyboard input");
+    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
+        if queue.push(scancode).is_err() {
+            kernel_warning("Scancode queue full; dropping keyboard input");
+        } else {
+            WAKER.wake();
+        }
+    } else {
+        kernel_warning("Scancode queue uninitialized; dropping keyboard input");
     }
+}
+
+/// The scancodes received by the keyboard interrupt, as an async stream.
+pub struct ScancodeStream {
+    _private: (),
+}
 
-    let decoded = decode_scancode(&mut *KEYBOARD.lock(), scancode);
-    if let Some(decoded_ok) = decoded {
-        handle_input(decoded_ok);
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        SCANCODE_QUEUE
+            .try_init_once(|| ArrayQueue::new(100))
+            .expect("ScancodeStream::new should only be called once");
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = SCANCODE_QUEUE
+            .try_get()
+            .expect("scancode queue not initialized");
+
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
     }
 }
 
@@ -1,7 +1,22 @@
+use rust_alloc::string::{String, ToString};
+use rust_alloc::sync::Arc;
 use rust_alloc::vec::Vec;
 
+use super::{Priority, ThreadFn, ThreadId};
+use crate::process::Process;
+
+/// A thread spawned before any `Runtime` was initialized.
+pub struct QueuedThread {
+    pub id: ThreadId,
+    pub name: String,
+    pub priority: Priority,
+    pub process: Option<Arc<Process>>,
+    pub entry: ThreadFn,
+    pub detached: bool,
+}
+
 pub struct ThreadQueue {
-    pub contents: Vec<fn()>,
+    pub contents: Vec<QueuedThread>,
 }
 
 impl Default for ThreadQueue {
@@ -17,12 +32,36 @@ impl ThreadQueue {
         }
     }
 
-    pub fn pop(&mut self) -> Option<fn()> {
+    pub fn pop(&mut self) -> Option<QueuedThread> {
         self.contents.pop()
     }
 
-    pub fn push(&mut self, thread: fn()) {
-        self.contents.push(thread);
+    pub fn push(
+        &mut self,
+        id: ThreadId,
+        name: &str,
+        priority: Priority,
+        process: Option<Arc<Process>>,
+        thread: ThreadFn,
+    ) {
+        self.contents.push(QueuedThread {
+            id,
+            name: name.to_string(),
+            priority,
+            process,
+            entry: thread,
+            detached: false,
+        });
+    }
+
+    pub fn contains(&self, id: ThreadId) -> bool {
+        self.contents.iter().any(|queued| queued.id == id)
+    }
+
+    pub fn detach(&mut self, id: ThreadId) {
+        if let Some(queued) = self.contents.iter_mut().find(|queued| queued.id == id) {
+            queued.detached = true;
+        }
     }
 
     pub fn is_empty(&self) -> bool {
@@ -0,0 +1,90 @@
+use rust_alloc::collections::VecDeque;
+
+pub use lateral_abi::Priority;
+
+fn level(priority: Priority) -> usize {
+    priority as usize
+}
+
+/// Whether threads waiting at `priority` are boosted when passed over (see [`AGING_THRESHOLD`]).
+fn ages(priority: Priority) -> bool {
+    priority == Priority::Normal
+}
+
+/// Number of scheduling decisions a `Normal` thread can be passed over for before it's boosted
+/// to `Interactive` until it next runs.
+pub const AGING_THRESHOLD: usize = 50;
+
+/// One FIFO of ready thread indices per priority level.
+pub(super) struct RunQueue {
+    levels: [VecDeque<(usize, usize)>; Priority::LEVELS],
+}
+
+impl RunQueue {
+    pub(super) fn new() -> Self {
+        RunQueue {
+            levels: Default::default(),
+        }
+    }
+
+    pub(super) fn push(&mut self, priority: Priority, index: usize) {
+        self.levels[level(priority)].push_back((index, 0));
+    }
+
+    pub(super) fn remove(&mut self, index: usize) {
+        for level in self.levels.iter_mut() {
+            level.retain(|&(queued, _)| queued != index);
+        }
+    }
+
+    /// Pops the highest priority ready thread, as long as it's at or above `limit`.
+    pub(super) fn pop(&mut self, limit: Priority) -> Option<usize> {
+        self.levels[..=level(limit)]
+            .iter_mut()
+            .find_map(|level| level.pop_front())
+            .map(|(index, _)| index)
+    }
+
+    /// Removes the highest priority thread at or above `limit` that `stealable` accepts, for
+    /// another CPU to run.
+    pub(super) fn steal<F: Fn(usize) -> bool>(
+        &mut self,
+        limit: Priority,
+        stealable: F,
+    ) -> Option<usize> {
+        self.levels[..=level(limit)].iter_mut().find_map(|level| {
+            let position = level.iter().position(|&(index, _)| stealable(index))?;
+            level.remove(position).map(|(index, _)| index)
+        })
+    }
+
+    /// Makes sure pushing `threads` entries never allocates, since threads are readied from
+    /// interrupt handlers.
+    pub(super) fn reserve(&mut self, threads: usize) {
+        for level in self.levels.iter_mut() {
+            level.reserve(threads.saturating_sub(level.len()));
+        }
+    }
+
+    /// Ages every thread waiting below `running`, boosting those past the threshold one level.
+    pub(super) fn age(&mut self, running: Priority) {
+        for priority in [Priority::Interactive, Priority::Normal, Priority::Idle] {
+            if !ages(priority) || priority <= running {
+                continue;
+            }
+
+            let boosted = Priority::from_usize(level(priority) - 1).unwrap();
+            let mut i = 0;
+            while i < self.levels[level(priority)].len() {
+                let (index, age) = self.levels[level(priority)][i];
+                if age + 1 >= AGING_THRESHOLD {
+                    self.levels[level(priority)].remove(i);
+                    self.levels[level(boosted)].push_back((index, 0));
+                } else {
+                    self.levels[level(priority)][i].1 = age + 1;
+                    i += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use core::cmp::Reverse;
+
+use rust_alloc::collections::BinaryHeap;
+
+use super::ThreadId;
+
+/// Threads blocked in `sleep`, ordered by the PIT tick they should wake up at.
+pub(super) struct SleepQueue {
+    sleepers: BinaryHeap<Reverse<(usize, usize, ThreadId)>>,
+}
+
+impl SleepQueue {
+    pub(super) fn new() -> Self {
+        SleepQueue {
+            sleepers: BinaryHeap::new(),
+        }
+    }
+
+    /// Makes sure the next `additional` pushes never allocate, so they can happen with the
+    /// runtime locked.
+    pub(super) fn reserve(&mut self, additional: usize) {
+        self.sleepers.reserve(additional);
+    }
+
+    pub(super) fn push(&mut self, deadline: usize, index: usize, id: ThreadId) {
+        self.sleepers.push(Reverse((deadline, index, id)));
+    }
+
+    /// Removes and returns the next sleeper whose deadline is at or before `now`.
+    ///
+    /// Doesn't allocate, so it's safe to call from the PIT interrupt.
+    pub(super) fn pop_expired(&mut self, now: usize) -> Option<(usize, ThreadId)> {
+        match self.sleepers.peek() {
+            Some(Reverse((deadline, _, _))) if *deadline <= now => self
+                .sleepers
+                .pop()
+                .map(|Reverse((_, index, id))| (index, id)),
+            _ => None,
+        }
+    }
+}
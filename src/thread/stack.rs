@@ -0,0 +1,246 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use rust_alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::cpu::gdt::STACK_SIZE;
+use crate::cpu::percpu::{self, MAX_CPUS};
+use crate::mem::frame::{BitmapFrameAllocator, FRAME_ALLOCATOR};
+use crate::mem::paging::{self, map_page, MAPPER};
+use crate::mem::tlb;
+
+use super::ThreadId;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Thread stacks are carved out of this region, one slot per stack.
+const STACK_REGION_START: u64 = 0x_5555_0000_0000;
+/// Each slot is a guard page followed by at most `SLOT_SIZE - PAGE_SIZE` bytes of stack.
+const SLOT_SIZE: u64 = 0x10_0000;
+
+/// Frames each CPU keeps back for growing stacks while the frame allocator is taken.
+const RESERVE_FRAMES: usize = 8;
+/// Marks an empty entry in a reserve.
+const NO_FRAME: u64 = u64::MAX;
+
+/// Number of slots handed out so far. A freed slot is handed out again before a new one.
+static SLOTS: AtomicUsize = AtomicUsize::new(0);
+/// The thread owning each slot, for overflow reports, or `None` if the slot is free.
+static OWNERS: Mutex<Vec<Option<ThreadId>>> = Mutex::new(Vec::new());
+/// Each CPU's reserve, only touched by that CPU with interrupts disabled.
+static RESERVES: [[AtomicU64; RESERVE_FRAMES]; MAX_CPUS] =
+    [const { [const { AtomicU64::new(NO_FRAME) }; RESERVE_FRAMES] }; MAX_CPUS];
+
+/// A thread stack mapped in its own slot with an unmapped guard page below it.
+///
+/// Only the top `STACK_SIZE` bytes are mapped up front; the rest of the slot is mapped on demand
+/// by the page fault handler as the stack grows down towards the guard page. The handler may have
+/// interrupted code holding any lock, so it never waits on one: it writes the page table entry
+/// itself, into the level 1 table mapping the top of the stack already created, and takes the
+/// frame from this CPU's reserve if the frame allocator is taken.
+///
+/// Dropping a stack unmaps it and frees its frames and slot. That waits on every other CPU to
+/// flush its TLB, so it must not happen with a lock held or on the stack itself.
+pub struct Stack {
+    slot: usize,
+}
+
+impl Stack {
+    pub fn new(owner: ThreadId) -> Result<Self, MapToError<Size4KiB>> {
+        let slot = without_interrupts(|| {
+            let mut owners = OWNERS.lock();
+            match owners.iter().position(Option::is_none) {
+                Some(slot) => {
+                    owners[slot] = Some(owner);
+                    slot
+                }
+                None => {
+                    owners.push(Some(owner));
+                    SLOTS.fetch_add(1, Ordering::Relaxed)
+                }
+            }
+        });
+
+        let stack = Stack { slot };
+        let bottom = stack.top() - STACK_SIZE as u64;
+        let pages = Page::range(
+            Page::containing_address(bottom),
+            Page::containing_address(stack.top()),
+        );
+
+        for page in pages {
+            map_page(page, PageTableFlags::PRESENT | PageTableFlags::WRITABLE)?;
+        }
+        refill_reserve();
+
+        Ok(stack)
+    }
+
+    /// Records the thread now running on this stack, for overflow reports.
+    pub fn set_owner(&self, owner: ThreadId) {
+        without_interrupts(|| OWNERS.lock()[self.slot] = Some(owner));
+    }
+
+    /// The (exclusive) upper end of the stack.
+    pub fn top(&self) -> VirtAddr {
+        slot_start(self.slot) + SLOT_SIZE
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        let pages = Page::range(
+            Page::containing_address(slot_start(self.slot) + PAGE_SIZE),
+            Page::containing_address(self.top()),
+        );
+        // Growing the heap maps pages, so the room is made before `MAPPER` is locked.
+        let mut frames = Vec::with_capacity((pages.end - pages.start) as usize);
+        without_interrupts(|| {
+            let mut mapper = MAPPER.lock();
+            let mapper = mapper.as_mut().expect("paging is not initialized");
+            for page in pages {
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    // Flushed everywhere at once below.
+                    flush.ignore();
+                    frames.push(frame);
+                }
+            }
+        });
+
+        tlb::shootdown_kernel(pages);
+        without_interrupts(|| {
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator
+                .as_mut()
+                .expect("frame allocator is not initialized");
+            for frame in frames {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        });
+        without_interrupts(|| OWNERS.lock()[self.slot] = None);
+    }
+}
+
+pub enum StackFault {
+    /// The faulting page was part of a thread stack and has now been mapped.
+    Grown,
+    /// The access hit the guard page of the given thread's stack.
+    Overflow(ThreadId),
+    /// The given thread's stack needed another page, but the frame allocator was taken and this
+    /// CPU's reserve was empty.
+    NoFrame(ThreadId),
+    /// The address is not inside any thread stack.
+    NotAStack,
+}
+
+/// Resolves a not-present page fault at `addr` if it falls inside a thread stack, without taking
+/// any lock the faulting code may hold. A page that is already mapped counts as grown.
+pub fn handle_page_fault(addr: VirtAddr) -> StackFault {
+    let addr = addr.as_u64();
+    if addr < STACK_REGION_START {
+        return StackFault::NotAStack;
+    }
+
+    let slot = ((addr - STACK_REGION_START) / SLOT_SIZE) as usize;
+    if slot >= SLOTS.load(Ordering::Relaxed) {
+        return StackFault::NotAStack;
+    }
+
+    // A live stack always has its top page mapped, a freed one has nothing mapped.
+    let top = Page::containing_address(slot_start(slot) + (SLOT_SIZE - 1));
+    if !entry(top).is_some_and(|entry| !entry.is_unused()) {
+        return StackFault::NotAStack;
+    }
+    if addr < (slot_start(slot) + PAGE_SIZE).as_u64() {
+        return StackFault::Overflow(owner(slot));
+    }
+
+    let page = Page::containing_address(VirtAddr::new(addr));
+    // In the same level 1 table as the top page, as no slot crosses a 2 MiB boundary.
+    let entry = entry(page).expect("a stack's level 1 table went missing");
+    if !entry.is_unused() {
+        return StackFault::Grown;
+    }
+    let frame = FRAME_ALLOCATOR
+        .try_lock()
+        .and_then(|mut frame_allocator| {
+            let frame_allocator = frame_allocator
+                .as_mut()
+                .expect("frame allocator is not initialized");
+            fill_reserve(frame_allocator);
+            frame_allocator.allocate_frame()
+        })
+        .or_else(take_reserved);
+    let Some(frame) = frame else {
+        return StackFault::NoFrame(owner(slot));
+    };
+    entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    x86_64::instructions::tlb::flush(page.start_address());
+    StackFault::Grown
+}
+
+/// Tops up this CPU's reserve from the frame allocator.
+pub fn refill_reserve() {
+    without_interrupts(|| {
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator
+            .as_mut()
+            .expect("frame allocator is not initialized");
+        fill_reserve(frame_allocator);
+    });
+}
+
+/// Must be called with interrupts disabled.
+fn fill_reserve(frame_allocator: &mut BitmapFrameAllocator) {
+    for reserved in &RESERVES[percpu::id()] {
+        if reserved.load(Ordering::Relaxed) == NO_FRAME {
+            let Some(frame) = frame_allocator.allocate_frame() else {
+                return;
+            };
+            reserved.store(frame.start_address().as_u64(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Must be called with interrupts disabled.
+fn take_reserved() -> Option<PhysFrame> {
+    RESERVES[percpu::id()].iter().find_map(|reserved| {
+        let frame = reserved.swap(NO_FRAME, Ordering::Relaxed);
+        (frame != NO_FRAME).then(|| PhysFrame::containing_address(PhysAddr::new(frame)))
+    })
+}
+
+/// The thread owning `slot`, for a report. Falls back on the current thread rather than wait for
+/// `OWNERS`.
+fn owner(slot: usize) -> ThreadId {
+    OWNERS
+        .try_lock()
+        .and_then(|owners| owners[slot])
+        .unwrap_or_else(super::current)
+}
+
+/// The kernel's level 1 entry for `page`, found without `MAPPER`. `None` if a table on the way
+/// is missing.
+fn entry(page: Page) -> Option<&'static mut PageTableEntry> {
+    let offset = paging::physical_memory_offset();
+    let mut table = paging::kernel_table();
+    let indexes = [page.p4_index(), page.p3_index(), page.p2_index()];
+    for index in indexes {
+        let entries = unsafe { &*(offset + table.start_address().as_u64()).as_ptr::<PageTable>() };
+        table = entries[index].frame().ok()?;
+    }
+    let entries =
+        unsafe { &mut *(offset + table.start_address().as_u64()).as_mut_ptr::<PageTable>() };
+    Some(&mut entries[page.p1_index()])
+}
+
+fn slot_start(slot: usize) -> VirtAddr {
+    VirtAddr::new(STACK_REGION_START + slot as u64 * SLOT_SIZE)
+}
@@ -0,0 +1,347 @@
+//! Locks that block through the scheduler instead of spinning.
+//!
+//! Unlike `spin` locks these may be held across a yield, but they can't be taken from interrupt
+//! handlers. Every wait enqueues the calling thread before it checks what it's waiting for one
+//! last time, and a notify on another CPU has to take the queue's lock to find it, so a wakeup
+//! can't slip in between the check and the `block`. One that lands between the enqueue and the
+//! `block` makes the `block` return at once.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use rust_alloc::collections::VecDeque;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::ThreadId;
+use crate::time::rtc::ticks;
+
+/// A FIFO of blocked threads.
+pub struct WaitQueue {
+    waiters: spin::Mutex<VecDeque<ThreadId>>,
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: spin::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Blocks the calling thread until it is notified.
+    ///
+    /// Whatever the thread is waiting for may already have happened on another CPU by the time
+    /// it's queued, so prefer [`wait_while`](Self::wait_while), which checks for it afterwards.
+    pub fn wait(&self) {
+        without_interrupts(|| {
+            let id = super::current();
+            self.waiters.lock().push_back(id);
+            super::block();
+            self.leave(id);
+        });
+    }
+
+    /// Takes `id` off the queue if it's still on it, as it is after a `block` that returned for
+    /// some other reason. Left there, it would use up a notify meant for a thread still waiting.
+    fn leave(&self, id: ThreadId) {
+        self.waiters.lock().retain(|&waiter| waiter != id);
+    }
+
+    /// Blocks the calling thread for as long as `condition` holds, re-checking it after every
+    /// notification.
+    ///
+    /// `condition` is checked with the queue locked and interrupts disabled, so it must not block
+    /// or take a lock a notifier might hold while notifying.
+    pub fn wait_while<F: FnMut() -> bool>(&self, mut condition: F) {
+        loop {
+            let waiting = without_interrupts(|| {
+                let mut waiters = self.waiters.lock();
+                if !condition() {
+                    return false;
+                }
+                let id = super::current();
+                waiters.push_back(id);
+                drop(waiters);
+                super::block();
+                self.leave(id);
+                true
+            });
+            if !waiting {
+                return;
+            }
+        }
+    }
+
+    /// Wakes the longest waiting thread. Returns `false` if there was none.
+    pub fn notify_one(&self) -> bool {
+        without_interrupts(|| loop {
+            let next = self.waiters.lock().pop_front();
+            match next {
+                Some(id) if super::wake(id) => return true,
+                Some(_) => continue,
+                None => return false,
+            }
+        })
+    }
+
+    /// Wakes every waiting thread and returns how many there were.
+    pub fn notify_all(&self) -> usize {
+        let mut woken = 0;
+        while self.notify_one() {
+            woken += 1;
+        }
+        woken
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+/// A mutual exclusion lock that blocks waiting threads instead of spinning.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.waiters
+            .wait_while(|| self.locked.swap(true, Ordering::Acquire));
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.locked.swap(true, Ordering::Acquire) {
+            None
+        } else {
+            Some(MutexGuard { mutex: self })
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.waiters.notify_one();
+    }
+}
+
+/// A condition variable to wait on alongside a [`Mutex`].
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Releases the lock and blocks until notified, then takes the lock again.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        without_interrupts(|| {
+            // Queued while the lock is still held, so a notify sent as soon as it's released
+            // finds this thread.
+            let id = super::current();
+            self.waiters.waiters.lock().push_back(id);
+            core::mem::drop(guard);
+            super::block();
+            self.waiters.leave(id);
+        });
+        mutex.lock()
+    }
+
+    /// Waits for as long as `condition` holds for the protected data.
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    pub fn notify_all(&self) -> usize {
+        self.waiters.notify_all()
+    }
+}
+
+/// A counting semaphore.
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Semaphore {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Takes a permit, blocking until one is available.
+    pub fn acquire(&self) {
+        self.waiters.wait_while(|| !self.try_acquire());
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.count.load(Ordering::Relaxed);
+        while count > 0 {
+            match self.count.compare_exchange_weak(
+                count,
+                count - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => count = actual,
+            }
+        }
+        false
+    }
+
+    /// Returns a permit and wakes a waiter, if any.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// A flag an interrupt handler raises to wake the thread waiting for it, e.g. for a device to
+/// say a request is done.
+///
+/// Unlike everything else here, [`set`](Self::set) may be called from an interrupt handler. Only
+/// one thread may wait at a time.
+pub struct Event {
+    set: AtomicBool,
+    waiter: spin::Mutex<Option<ThreadId>>,
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Event {
+            set: AtomicBool::new(false),
+            waiter: spin::Mutex::new(None),
+        }
+    }
+
+    /// Lowers the flag, so `wait` only returns once it's raised again.
+    pub fn reset(&self) {
+        self.set.store(false, Ordering::Relaxed);
+    }
+
+    /// Raises the flag and wakes the waiting thread, if any.
+    pub fn set(&self) {
+        self.set.store(true, Ordering::Release);
+        if let Some(waiter) = self.waiter.lock().take() {
+            super::wake(waiter);
+        }
+    }
+
+    /// Blocks until the flag is raised, then lowers it.
+    pub fn wait(&self) {
+        self.wait_until(None);
+    }
+
+    /// Like [`wait`](Self::wait), but gives up once `timeout` PIT ticks have passed. Returns
+    /// whether the flag was raised.
+    pub fn wait_timeout(&self, timeout: usize) -> bool {
+        self.wait_until(Some(ticks() + timeout))
+    }
+
+    fn wait_until(&self, deadline: Option<usize>) -> bool {
+        loop {
+            let set = without_interrupts(|| {
+                // Registered before checking, so a `set` on another CPU in between still wakes
+                // this thread.
+                *self.waiter.lock() = Some(super::current());
+                if self.set.swap(false, Ordering::Acquire) {
+                    self.waiter.lock().take();
+                    return true;
+                }
+                match deadline {
+                    Some(deadline) => super::block_until(deadline),
+                    None => super::block(),
+                }
+                false
+            });
+            if set {
+                return true;
+            }
+            if deadline.is_some_and(|deadline| ticks() >= deadline) {
+                without_interrupts(|| self.waiter.lock().take());
+                // It may have been raised since the thread last looked.
+                return self.set.swap(false, Ordering::Acquire);
+            }
+        }
+    }
+}
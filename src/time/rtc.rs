@@ -4,7 +4,7 @@ use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use x86_64::instructions::interrupts;
 use x86_64::instructions::port::Port;
 
-use crate::cpu::interrupt::set_irq_handler;
+use crate::cpu::interrupt::add_irq_handler;
 
 use super::cmos::CMOS;
 
@@ -19,9 +19,9 @@ static CLOCKS_PER_NANOSECOND: AtomicU64 = AtomicU64::new(0);
 pub fn init() {
     let divider = if PIT_DIVIDER < 65536 { PIT_DIVIDER } else { 0 };
     set_pit_frequency_divider(divider as u16);
-    set_irq_handler(0, pit_interrupt_handler);
+    add_irq_handler(0, pit_interrupt_handler);
 
-    set_irq_handler(8, rtc_interrupt_handler);
+    add_irq_handler(8, rtc_interrupt_handler);
     CMOS::new().enable_update_interrupt();
 
     let calibration_time = 250_000;
@@ -47,7 +47,14 @@ pub fn halt() {
     x86_64::instructions::hlt();
 }
 
+/// Sleeps for `seconds`. Once a thread runtime is up this parks the calling thread instead of
+/// keeping the CPU busy.
 pub fn sleep(seconds: f64) {
+    if crate::thread::is_running() {
+        crate::thread::sleep(seconds);
+        return;
+    }
+
     let start = super::uptime();
     while super::uptime() - start < seconds {
         halt();
@@ -63,7 +70,9 @@ pub fn nanowait(nanoseconds: u64) {
 }
 
 pub fn pit_interrupt_handler() {
-    PIT_TICKS.fetch_add(1, Ordering::Relaxed);
+    let now = PIT_TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    crate::task::timer::wake_expired(now);
+    crate::thread::tick();
 }
 
 pub fn rtc_interrupt_handler() {
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::drives_are_found,
+        &tests::sectors_round_trip,
+        &tests::bad_requests_are_rejected,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use lateral::block::{self, ata, BlockDevice, BlockError};
+
+    /// `tests/disk.img`, attached as the primary slave. The boot disk is the primary master.
+    const DISK: &str = "ata1";
+    const DISK_SECTORS: u64 = 512;
+
+    fn disk() -> Arc<dyn BlockDevice> {
+        block::find(DISK).expect("the test disk isn't attached")
+    }
+
+    pub fn drives_are_found() {
+        assert!(ata::init() >= 2);
+        assert!(block::find("ata0").is_some());
+        let disk = disk();
+        assert_eq!(disk.sector_size(), ata::SECTOR_SIZE);
+        assert_eq!(disk.sector_count(), DISK_SECTORS);
+        assert_eq!(disk.capacity(), DISK_SECTORS * ata::SECTOR_SIZE as u64);
+    }
+
+    pub fn sectors_round_trip() {
+        let disk = disk();
+        let size = disk.sector_size();
+
+        // The image is empty and attached as a snapshot, so every run starts from zeros.
+        let mut buf = vec![0xFFu8; 4 * size];
+        disk.read(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+
+        // More than one command's worth, ending on the last sector.
+        let start = DISK_SECTORS - 300;
+        let count = (DISK_SECTORS - start) as usize;
+        let pattern: Vec<u8> = (0..count * size)
+            .map(|i| (i * 7 + i / size) as u8)
+            .collect();
+        disk.write(start, &pattern).unwrap();
+        disk.flush().unwrap();
+
+        let mut back = vec![0u8; pattern.len()];
+        disk.read(start, &mut back).unwrap();
+        assert!(back == pattern);
+
+        // A single sector in the middle reads back on its own.
+        let mut one = vec![0u8; size];
+        disk.read(start + 5, &mut one).unwrap();
+        assert_eq!(one[..], pattern[5 * size..6 * size]);
+    }
+
+    pub fn bad_requests_are_rejected() {
+        let disk = disk();
+        let size = disk.sector_size();
+        let mut buf = vec![0u8; size];
+        assert_eq!(
+            disk.read(DISK_SECTORS, &mut buf),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            disk.write(DISK_SECTORS - 1, &vec![0u8; 2 * size]),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            disk.read(0, &mut buf[..size - 1]),
+            Err(BlockError::BadLength)
+        );
+        assert_eq!(disk.write(u64::MAX, &buf), Err(BlockError::OutOfRange));
+    }
+}
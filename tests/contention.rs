@@ -0,0 +1,151 @@
+#![no_std]
+#![no_main]
+
+use lateral::cpu::smp;
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    smp::init().expect("failed to start the application processors");
+
+    lateral::test::runner(&[
+        &tests::mutex_under_contention,
+        &tests::condvar_ping_pong,
+        &tests::semaphore_hand_off,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+/// The sync primitives hammered from threads spread over every CPU. A wakeup lost between two
+/// CPUs leaves a thread blocked for good, so each test fails on a deadline instead of joining.
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use lateral::cpu::percpu;
+    use lateral::cpu::smp;
+    use lateral::thread::sync::{Condvar, Mutex, Semaphore};
+    use lateral::thread::{self, JoinHandle};
+    use lateral::time::rtc::ticks;
+
+    const WORKERS: usize = 4;
+    const TIMEOUT_TICKS: usize = 20_000;
+
+    /// Which CPUs ran a worker, a bit each.
+    static CPUS_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+    fn seen() {
+        CPUS_SEEN.fetch_or(1 << percpu::id(), Ordering::Relaxed);
+    }
+
+    /// Joins every handle once `done` reaches `handles.len()`, failing if that takes too long.
+    fn finish(what: &str, done: &AtomicUsize, handles: [JoinHandle; WORKERS]) {
+        assert!(smp::online() >= 2, "no application processor came online");
+        let deadline = ticks() + TIMEOUT_TICKS;
+        while done.load(Ordering::Acquire) < handles.len() {
+            assert!(ticks() < deadline, "{}: a wakeup was lost", what);
+            thread::sleep_ticks(10);
+        }
+        for handle in handles {
+            assert_eq!(handle.join(), 0);
+        }
+        let cpus = CPUS_SEEN.swap(0, Ordering::Relaxed);
+        assert!(cpus.count_ones() >= 2, "{}: ran on a single CPU", what);
+    }
+
+    pub fn mutex_under_contention() {
+        const ROUNDS: usize = 20_000;
+        static COUNTER: Mutex<usize> = Mutex::new(0);
+        static DONE: AtomicUsize = AtomicUsize::new(0);
+
+        fn worker() {
+            for round in 0..ROUNDS {
+                seen();
+                let mut counter = COUNTER.lock();
+                *counter += 1;
+                // Now and then hold the lock across a switch, so the others block on it.
+                if round % 64 == 0 {
+                    thread::yield_thread();
+                }
+            }
+            DONE.fetch_add(1, Ordering::Release);
+        }
+
+        let handles = core::array::from_fn(|_| thread::spawn("locker", worker));
+        finish("mutex", &DONE, handles);
+        assert_eq!(*COUNTER.lock(), WORKERS * ROUNDS);
+    }
+
+    pub fn condvar_ping_pong() {
+        const ROUNDS: usize = 5_000;
+        /// Whose turn it is, by worker index.
+        static TURN: Mutex<usize> = Mutex::new(0);
+        static CHANGED: Condvar = Condvar::new();
+        static DONE: AtomicUsize = AtomicUsize::new(0);
+
+        fn worker(index: usize) {
+            for _ in 0..ROUNDS {
+                seen();
+                let mut turn = CHANGED.wait_while(TURN.lock(), |turn| *turn != index);
+                *turn = (index + 1) % WORKERS;
+                drop(turn);
+                CHANGED.notify_all();
+            }
+            DONE.fetch_add(1, Ordering::Release);
+        }
+
+        let handles = core::array::from_fn(|index| thread::spawn("ponger", move || worker(index)));
+        finish("condvar", &DONE, handles);
+        assert_eq!(*TURN.lock(), 0);
+    }
+
+    pub fn semaphore_hand_off() {
+        const ITEMS: usize = 10_000;
+        static FILLED: Semaphore = Semaphore::new(0);
+        static EMPTY: Semaphore = Semaphore::new(1);
+        static CONSUMED: AtomicUsize = AtomicUsize::new(0);
+        static DONE: AtomicUsize = AtomicUsize::new(0);
+
+        // Half the workers produce and half consume, through a single slot.
+        fn producer() {
+            for _ in 0..ITEMS {
+                seen();
+                EMPTY.acquire();
+                FILLED.release();
+            }
+            DONE.fetch_add(1, Ordering::Release);
+        }
+
+        fn consumer() {
+            for _ in 0..ITEMS {
+                seen();
+                FILLED.acquire();
+                CONSUMED.fetch_add(1, Ordering::Relaxed);
+                EMPTY.release();
+            }
+            DONE.fetch_add(1, Ordering::Release);
+        }
+
+        let handles = core::array::from_fn(|index| {
+            if index % 2 == 0 {
+                thread::spawn("producer", producer)
+            } else {
+                thread::spawn("consumer", consumer)
+            }
+        });
+        finish("semaphore", &DONE, handles);
+        assert_eq!(CONSUMED.load(Ordering::Relaxed), WORKERS / 2 * ITEMS);
+        assert_eq!(FILLED.available(), 0);
+    }
+}
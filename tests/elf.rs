@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::rejects_malformed_files,
+        &tests::loads_and_runs_a_program,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use lateral::loader::elf::{self, Elf, ElfError};
+    use lateral::thread;
+    use x86_64::VirtAddr;
+
+    /// Built from `tests/elf/argv.s`.
+    static ARGV: &[u8] = include_bytes!("elf/argv.elf");
+    /// Where `argv.s` leaves what it found on its stack.
+    const RESULT: u64 = 0x800_0000_2000;
+
+    pub fn rejects_malformed_files() {
+        assert!(matches!(Elf::parse(&ARGV[..32]), Err(ElfError::Truncated)));
+
+        let mut bad = [0; 64];
+        bad.copy_from_slice(&ARGV[..64]);
+        bad[0] = 0;
+        assert!(matches!(Elf::parse(&bad), Err(ElfError::NotElf)));
+    }
+
+    pub fn loads_and_runs_a_program() {
+        let elf = Elf::parse(ARGV).expect("the fixture is a valid executable");
+        assert_eq!(elf.segments().len(), 3);
+
+        let program = elf::load("argv", ARGV, &["argv", "xyz"], &["HOME=/"])
+            .expect("failed to load the fixture");
+        let process = program.process().clone();
+        // It ends by faulting on purpose.
+        assert_eq!(program.spawn().join(), thread::FAULT_EXIT_CODE);
+
+        let mut result = [0; 32];
+        assert!(process.read(VirtAddr::new(RESULT), &mut result));
+        let word = |i: usize| u64::from_le_bytes(result[i * 8..i * 8 + 8].try_into().unwrap());
+        assert_eq!(word(0), 2);
+        assert_eq!(word(1), b'x' as u64);
+        assert_eq!(word(2), b'H' as u64);
+        assert_eq!(word(3) % 16, 0);
+    }
+}
@@ -0,0 +1,30 @@
+# Test program for tests/elf.rs. Records what the loader put on its stack, then reads address 0
+# to fault, since there is no exit syscall to end it with. `result` ends up at 0x80000002000.
+#
+# Built with:
+#   as argv.s -o argv.o
+#   ld -static -nostdlib -s -z max-page-size=0x1000 -z noexecstack \
+#      -Ttext-segment=0x80000000000 --build-id=none argv.o -o argv.elf
+
+    .text
+    .globl _start
+_start:
+    # argc
+    movq (%rsp), %rcx
+    movq %rcx, result(%rip)
+    # argv[1][0]
+    movq 16(%rsp), %rax
+    movzbq (%rax), %rax
+    movq %rax, result+8(%rip)
+    # envp[0][0], just past argv's terminating null
+    movq 16(%rsp,%rcx,8), %rax
+    movzbq (%rax), %rax
+    movq %rax, result+16(%rip)
+    # The stack pointer, to check its alignment
+    movq %rsp, result+24(%rip)
+    movq 0, %rax
+
+    .data
+    .globl result
+result:
+    .quad 0, 0, 0, 0
@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::timers_wake_tasks_in_deadline_order,
+        &tests::scancodes_arrive_as_a_stream,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+/// Each test runs an executor on a thread of its own, as `Executor::run` never returns, and
+/// waits on a deadline for its tasks to be done.
+mod tests {
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use alloc::vec::Vec;
+    use futures_util::stream::StreamExt;
+    use lateral::task::executor::Executor;
+    use lateral::task::{timer, Task};
+    use lateral::thread;
+    use lateral::thread::ps2::{self, ScancodeStream};
+    use lateral::time::rtc::ticks;
+    use spin::Mutex;
+    use x86_64::instructions::interrupts::without_interrupts;
+    use x86_64::instructions::port::Port;
+
+    const TIMEOUT_TICKS: usize = 1_000;
+
+    /// Starts an executor on its own thread with the tasks `tasks` makes.
+    fn run(tasks: fn() -> Vec<Task>) {
+        thread::spawn("executor", move || {
+            let mut executor = Executor::new();
+            for task in tasks() {
+                executor.spawn(task);
+            }
+            executor.run();
+        });
+    }
+
+    /// Waits until `done` reaches `count`.
+    fn wait_for(done: &AtomicUsize, count: usize) {
+        let deadline = ticks() + TIMEOUT_TICKS;
+        while done.load(Ordering::Acquire) < count {
+            assert!(ticks() < deadline, "the tasks never finished");
+            thread::sleep_ticks(1);
+        }
+    }
+
+    pub fn timers_wake_tasks_in_deadline_order() {
+        /// The ticks each task sleeps for, by task. The first doesn't sleep at all.
+        const DELAYS: [usize; 4] = [0, 30, 10, 20];
+        static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        static DONE: AtomicUsize = AtomicUsize::new(0);
+
+        run(|| {
+            (0..DELAYS.len())
+                .map(|index| {
+                    Task::new(async move {
+                        if DELAYS[index] > 0 {
+                            timer::sleep_ticks(DELAYS[index]).await;
+                        }
+                        without_interrupts(|| ORDER.lock().push(index));
+                        DONE.fetch_add(1, Ordering::Release);
+                    })
+                })
+                .collect()
+        });
+
+        wait_for(&DONE, DELAYS.len());
+        assert_eq!(*ORDER.lock(), [0, 2, 3, 1]);
+    }
+
+    /// Has the keyboard controller hand `byte` to the CPU as if the keyboard had sent it, which
+    /// raises IRQ 1 like a key would.
+    fn inject(byte: u8) {
+        let mut status = Port::<u8>::new(0x64);
+        let mut data = Port::<u8>::new(0x60);
+        let wait_until_ready = |status: &mut Port<u8>| {
+            // Bit 1: the controller hasn't taken the last byte written yet.
+            while unsafe { status.read() } & 0x02 != 0 {
+                core::hint::spin_loop();
+            }
+        };
+        unsafe {
+            wait_until_ready(&mut status);
+            status.write(0xD2);
+            wait_until_ready(&mut status);
+            data.write(byte);
+        }
+    }
+
+    pub fn scancodes_arrive_as_a_stream() {
+        const SCANCODES: [u8; 3] = [0x1E, 0x9E, 0x30];
+        static RECEIVED: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+        static LISTENING: AtomicBool = AtomicBool::new(false);
+        static DONE: AtomicUsize = AtomicUsize::new(0);
+
+        ps2::init_ps2();
+        run(|| {
+            let mut scancodes = ScancodeStream::new();
+            LISTENING.store(true, Ordering::Release);
+            [Task::new(async move {
+                for _ in SCANCODES {
+                    let scancode = scancodes.next().await.expect("the stream ended");
+                    without_interrupts(|| RECEIVED.lock().push(scancode));
+                }
+                DONE.fetch_add(1, Ordering::Release);
+            })]
+            .into()
+        });
+
+        let deadline = ticks() + TIMEOUT_TICKS;
+        while !LISTENING.load(Ordering::Acquire) {
+            assert!(ticks() < deadline, "the executor never started");
+            thread::sleep_ticks(1);
+        }
+        for scancode in SCANCODES {
+            inject(scancode);
+            // Give the interrupt a chance to take it before the next one replaces it.
+            thread::sleep_ticks(2);
+        }
+
+        wait_for(&DONE, 1);
+        assert_eq!(*RECEIVED.lock(), SCANCODES);
+    }
+}
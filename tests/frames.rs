@@ -0,0 +1,179 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::frames_are_reused_once_freed,
+        &tests::unmapping_frees_frames_and_tables,
+        &tests::dropping_a_process_frees_its_memory,
+        &tests::reaped_threads_give_their_stacks_back,
+        &tests::stats_syscall_matches_the_kernel,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use alloc::collections::BTreeSet;
+    use alloc::vec::Vec;
+
+    use lateral::mem::frame::{self, FRAME_ALLOCATOR};
+    use lateral::process::{self, Process};
+    use lateral::syscall;
+    use lateral::syscall::SyscallNumber;
+    use lateral::thread;
+    use x86_64::instructions::interrupts::without_interrupts;
+    use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, PageTableFlags};
+    use x86_64::VirtAddr;
+
+    const PAGES: u64 = 16;
+
+    fn with_allocator<R>(f: impl FnOnce(&mut frame::BitmapFrameAllocator) -> R) -> R {
+        without_interrupts(|| f(FRAME_ALLOCATOR.lock().as_mut().unwrap()))
+    }
+
+    pub fn frames_are_reused_once_freed() {
+        let before = frame::stats();
+        assert!(before.total > 0);
+        assert_eq!(before.used + before.free, before.total);
+
+        // Growing the heap takes frames, so it mustn't happen with the allocator locked.
+        let mut frames = Vec::with_capacity(1000);
+        with_allocator(|allocator| {
+            for _ in 0..frames.capacity() {
+                frames.push(allocator.allocate_frame().expect("out of frames"));
+            }
+        });
+        let distinct: BTreeSet<_> = frames.iter().collect();
+        assert_eq!(distinct.len(), frames.len());
+        assert_eq!(frame::stats().used, before.used + frames.len());
+
+        // The lowest free frame is handed out first, so a freed one comes right back.
+        let reused = with_allocator(|allocator| unsafe {
+            allocator.deallocate_frame(frames[10]);
+            allocator.allocate_frame()
+        });
+        assert_eq!(reused, Some(frames[10]));
+
+        with_allocator(|allocator| {
+            for &frame in &frames {
+                unsafe { allocator.deallocate_frame(frame) };
+            }
+        });
+        assert_eq!(frame::stats(), before);
+    }
+
+    /// Maps `PAGES` pages into `process` and unmaps them again. Returns how many frames the
+    /// mapping took.
+    fn map_and_unmap(process: &Process) -> usize {
+        let first = Page::containing_address(VirtAddr::new(0x800_0000_0000));
+        let pages = Page::range(first, first + PAGES);
+        let before = frame::stats().used;
+        process
+            .map(pages, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)
+            .expect("failed to map memory");
+        let mapped = frame::stats().used;
+        process.unmap(pages).expect("failed to unmap memory");
+        mapped - before
+    }
+
+    pub fn unmapping_frees_frames_and_tables() {
+        let process = Process::new("unmap").expect("failed to create a process");
+        // Once first, so whatever else this needs (like more of the test's own stack) is there.
+        map_and_unmap(&process);
+
+        let before = frame::stats().used;
+        let mapped = map_and_unmap(&process);
+        // The pages themselves and a level 3, 2 and 1 table for them.
+        assert_eq!(mapped as u64, PAGES + 3);
+        assert_eq!(frame::stats().used, before);
+        process.kill();
+    }
+
+    pub fn dropping_a_process_frees_its_memory() {
+        let used = || {
+            let process = Process::new("dropped").expect("failed to create a process");
+            let first = Page::containing_address(VirtAddr::new(0x800_0000_0000));
+            process
+                .map(
+                    Page::range(first, first + PAGES),
+                    PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+                )
+                .expect("failed to map memory");
+            let used = frame::stats().used;
+            // A process without threads is removed from the table as soon as it's killed.
+            process.kill();
+            assert!(process::find(process.id()).is_none());
+            used
+        };
+        used();
+
+        let before = frame::stats().used;
+        let mapped = used();
+        // The pages, their three tables and the level 4 table.
+        assert_eq!((mapped - before) as u64, PAGES + 4);
+        assert_eq!(frame::stats().used, before);
+    }
+
+    pub fn reaped_threads_give_their_stacks_back() {
+        /// Frames in use while the thread was at its deepest.
+        static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+        /// Grows the stack by about `depth` pages.
+        fn deep(depth: usize) -> u8 {
+            let page = core::hint::black_box([depth as u8; 4096]);
+            if depth == 0 {
+                PEAK.store(frame::stats().used, Ordering::Relaxed);
+                page[0]
+            } else {
+                deep(depth - 1).wrapping_add(page[4095])
+            }
+        }
+        let run = || {
+            let handle = thread::spawn("deep", || {
+                deep(PAGES as usize);
+            });
+            assert_eq!(handle.join(), 0);
+            // The idle thread frees the stack once it gets to run.
+            thread::sleep_ticks(10);
+        };
+        run();
+
+        // Had the first thread's stack been kept, the second would run on it as it was grown.
+        let before = frame::stats().used;
+        run();
+        assert!(PEAK.load(Ordering::Relaxed) >= before + PAGES as usize);
+        assert_eq!(frame::stats().used, before);
+    }
+
+    pub fn stats_syscall_matches_the_kernel() {
+        let mut counts = [0u64; 3];
+        assert_eq!(
+            unsafe { syscall!(int 0x80; SyscallNumber::MemStats, counts.as_mut_ptr()) },
+            Ok(0)
+        );
+        let stats = frame::stats();
+        assert_eq!(counts[0] as usize, stats.total);
+        assert_eq!(counts[1] as usize, stats.used);
+        assert_eq!(counts[2] as usize, stats.free);
+    }
+}
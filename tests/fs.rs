@@ -0,0 +1,320 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::format_makes_an_empty_filesystem,
+        &tests::mount_reads_what_format_wrote,
+        &tests::changes_are_written_through,
+        &tests::check_finds_leaked_blocks,
+        &tests::paths_parse_and_print,
+        &tests::files_are_found_by_path,
+        &tests::wrong_kinds_are_errors,
+        &tests::directories_grow_as_entries_are_added,
+        &tests::rename_moves_entries,
+        &tests::remove_frees_blocks,
+        &tests::mount_rejects_other_disks,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use lateral::block::virtio;
+
+    use lateral::fs::{self, Entry, Extent, FsError, Kind, Path, Problem, Section, BLOCK_SIZE};
+
+    /// `tests/virtio.img`, 2 MiB.
+    const DISK: &str = "virtio0";
+    const DISK_BLOCKS: u64 = 512;
+    /// The superblock, one block of bitmap and a block for each section's root.
+    const FORMAT_BLOCKS: u64 = 6;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 13 + i / BLOCK_SIZE) as u8).collect()
+    }
+
+    pub fn format_makes_an_empty_filesystem() {
+        assert_eq!(virtio::init(), 1);
+        let fs = fs::format(DISK).unwrap();
+        let superblock = fs.superblock();
+        assert_eq!(superblock.block_count, DISK_BLOCKS);
+        assert_eq!(superblock.free_blocks, DISK_BLOCKS - FORMAT_BLOCKS);
+        for section in Section::ALL {
+            assert!(fs.read_entries(&fs.root(section)).unwrap().is_empty());
+        }
+        assert_eq!(fs::check(&fs), []);
+    }
+
+    pub fn mount_reads_what_format_wrote() {
+        let formatted = *fs::format(DISK).unwrap().superblock();
+        let fs = fs::mount(DISK).unwrap();
+        assert_eq!(*fs.superblock(), formatted);
+        assert_eq!(fs::check(&fs), []);
+    }
+
+    pub fn changes_are_written_through() {
+        let mut fs = fs::format(DISK).unwrap();
+        let data = pattern(2 * BLOCK_SIZE + 100);
+        let extent = fs.allocate(3).unwrap();
+        let extent = fs.write(extent, &data).unwrap();
+        let root = fs.root(Section::Misc);
+        let entry = Entry {
+            kind: Kind::File,
+            name: "notes".into(),
+            extent,
+        };
+        let root = fs
+            .write_entries(root, core::slice::from_ref(&entry))
+            .unwrap();
+        fs.set_root(Section::Misc, root).unwrap();
+
+        let mut fs = fs::mount(DISK).unwrap();
+        assert_eq!(fs.superblock().free_blocks, DISK_BLOCKS - FORMAT_BLOCKS - 3);
+        assert_eq!(fs.read_entries(&fs.root(Section::Misc)).unwrap(), [entry]);
+        assert_eq!(fs.read(&extent).unwrap(), data);
+        assert_eq!(fs::check(&fs), []);
+
+        let root = fs.write_entries(fs.root(Section::Misc), &[]).unwrap();
+        fs.set_root(Section::Misc, root).unwrap();
+        fs.free(extent).unwrap();
+        let fs = fs::mount(DISK).unwrap();
+        assert_eq!(fs.superblock().free_blocks, DISK_BLOCKS - FORMAT_BLOCKS);
+        assert_eq!(fs::check(&fs), []);
+    }
+
+    pub fn check_finds_leaked_blocks() {
+        let mut fs = fs::format(DISK).unwrap();
+        let extent = fs.allocate(2).unwrap();
+        assert_eq!(
+            fs::check(&fs),
+            [Problem::Leaked {
+                start: extent.start,
+                blocks: 2
+            }]
+        );
+        fs.free(extent).unwrap();
+        assert_eq!(fs::check(&fs), []);
+        assert_eq!(
+            fs.free(extent),
+            Err(FsError::Corrupt("an extent's blocks are already free"))
+        );
+        assert_eq!(
+            fs.read(&Extent {
+                start: DISK_BLOCKS - 1,
+                blocks: 2,
+                size: 0,
+            }),
+            Err(FsError::Corrupt("an extent is past the end of the disk"))
+        );
+    }
+
+    fn path(path: &str) -> Path {
+        Path::parse(path).unwrap()
+    }
+
+    pub fn paths_parse_and_print() {
+        let hello = path("apps: carter/hello-world");
+        assert_eq!(hello.section(), Section::Apps);
+        assert_eq!(hello.names(), ["carter", "hello-world"]);
+        assert_eq!(hello.to_string(), "apps: carter/hello-world");
+        assert_eq!(hello.parent(), Some(path("apps:carter")));
+        assert_eq!(path("logs:"), Path::root(Section::Logs));
+        assert_eq!(path("logs:").to_string(), "logs:");
+        for bad in [
+            "apps",
+            "other: carter",
+            "apps: carter/",
+            "apps: a//b",
+            "apps: a:b",
+        ] {
+            assert_eq!(Path::parse(bad), Err(FsError::BadPath), "{}", bad);
+        }
+    }
+
+    pub fn files_are_found_by_path() {
+        let mut fs = fs::format(DISK).unwrap();
+        fs.create(&path("apps: carter"), Kind::Directory).unwrap();
+        fs.create(&path("apps: carter/hello-world"), Kind::File)
+            .unwrap();
+        let data = pattern(BLOCK_SIZE + 1);
+        fs.write_file(&path("apps: carter/hello-world"), &data)
+            .unwrap();
+
+        let fs = fs::mount(DISK).unwrap();
+        assert_eq!(
+            fs.read_file(&path("apps: carter/hello-world")).unwrap(),
+            data
+        );
+        let entry = fs.lookup(&path("apps: carter/hello-world")).unwrap();
+        assert_eq!(
+            (entry.kind, entry.extent.size),
+            (Kind::File, data.len() as u64)
+        );
+        let names: Vec<String> = fs
+            .read_dir(&path("apps:"))
+            .unwrap()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["carter"]);
+        assert_eq!(
+            fs.lookup(&path("apps: carter/missing")),
+            Err(FsError::NotFound)
+        );
+        assert_eq!(
+            fs.lookup(&path("misc: carter/hello-world")),
+            Err(FsError::NotFound)
+        );
+        assert_eq!(fs::check(&fs), []);
+    }
+
+    pub fn wrong_kinds_are_errors() {
+        let mut fs = fs::format(DISK).unwrap();
+        fs.create(&path("misc: carter"), Kind::Directory).unwrap();
+        fs.create(&path("misc: carter/notes"), Kind::File).unwrap();
+        assert_eq!(
+            fs.create(&path("misc: carter"), Kind::File),
+            Err(FsError::AlreadyExists)
+        );
+        assert_eq!(
+            fs.create(&path("misc: carter/notes/more"), Kind::File),
+            Err(FsError::NotADirectory)
+        );
+        assert_eq!(
+            fs.read_dir(&path("misc: carter/notes")).err(),
+            Some(FsError::NotADirectory)
+        );
+        assert_eq!(
+            fs.read_file(&path("misc: carter")),
+            Err(FsError::IsADirectory)
+        );
+        assert_eq!(
+            fs.write_file(&path("misc:"), b"data"),
+            Err(FsError::IsADirectory)
+        );
+        assert_eq!(
+            fs.remove(&path("misc: carter")),
+            Err(FsError::DirectoryNotEmpty)
+        );
+        assert_eq!(fs.remove(&path("misc:")), Err(FsError::BadPath));
+    }
+
+    pub fn directories_grow_as_entries_are_added() {
+        let mut fs = fs::format(DISK).unwrap();
+        // More than a block of entries in both the root and a directory in it.
+        let count = 2 * BLOCK_SIZE / fs::ENTRY_SIZE;
+        fs.create(&path("logs: carter"), Kind::Directory).unwrap();
+        for index in 0..count {
+            fs.create(&path(&format!("logs: user{}", index)), Kind::Directory)
+                .unwrap();
+            fs.create(&path(&format!("logs: carter/{}.log", index)), Kind::File)
+                .unwrap();
+        }
+
+        let fs = fs::mount(DISK).unwrap();
+        assert_eq!(fs.read_dir(&path("logs:")).unwrap().len(), count + 1);
+        assert_eq!(fs.read_dir(&path("logs: carter")).unwrap().len(), count);
+        let walked = fs
+            .walk(&path("logs:"))
+            .unwrap()
+            .map(|found| found.unwrap().0.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(walked.len(), 2 * count + 1);
+        assert_eq!(walked[0], "logs: carter");
+        assert_eq!(walked[1], "logs: carter/0.log");
+        assert_eq!(fs::check(&fs), []);
+    }
+
+    pub fn rename_moves_entries() {
+        let mut fs = fs::format(DISK).unwrap();
+        fs.create(&path("apps: carter"), Kind::Directory).unwrap();
+        fs.create(&path("apps: system"), Kind::Directory).unwrap();
+        fs.create(&path("apps: carter/old"), Kind::File).unwrap();
+        let data = pattern(100);
+        fs.write_file(&path("apps: carter/old"), &data).unwrap();
+
+        fs.rename(&path("apps: carter/old"), &path("apps: carter/new"))
+            .unwrap();
+        fs.rename(&path("apps: carter/new"), &path("apps: system/new"))
+            .unwrap();
+        fs.rename(&path("apps: system/new"), &path("misc: moved"))
+            .unwrap();
+        fs.rename(&path("apps: system"), &path("apps: carter/system"))
+            .unwrap();
+        assert_eq!(fs.read_file(&path("misc: moved")).unwrap(), data);
+        assert_eq!(fs.lookup(&path("apps: carter/new")), Err(FsError::NotFound));
+        assert_eq!(
+            fs.lookup(&path("apps: carter/system")).unwrap().kind,
+            Kind::Directory
+        );
+
+        assert_eq!(
+            fs.rename(&path("apps: carter"), &path("apps: carter/system/carter")),
+            Err(FsError::BadPath)
+        );
+        fs.create(&path("misc: taken"), Kind::File).unwrap();
+        assert_eq!(
+            fs.rename(&path("misc: moved"), &path("misc: taken")),
+            Err(FsError::AlreadyExists)
+        );
+        assert_eq!(
+            fs.rename(&path("misc: gone"), &path("misc: here")),
+            Err(FsError::NotFound)
+        );
+        assert_eq!(fs::check(&fs), []);
+    }
+
+    pub fn remove_frees_blocks() {
+        let mut fs = fs::format(DISK).unwrap();
+        let free = fs.superblock().free_blocks;
+        fs.create(&path("configuration: carter"), Kind::Directory)
+            .unwrap();
+        fs.create(&path("configuration: carter/settings"), Kind::File)
+            .unwrap();
+        fs.write_file(
+            &path("configuration: carter/settings"),
+            &pattern(3 * BLOCK_SIZE),
+        )
+        .unwrap();
+        assert!(fs.superblock().free_blocks < free);
+
+        fs.remove(&path("configuration: carter/settings")).unwrap();
+        fs.remove(&path("configuration: carter")).unwrap();
+        assert_eq!(fs.superblock().free_blocks, free);
+        assert_eq!(
+            fs.remove(&path("configuration: carter")),
+            Err(FsError::NotFound)
+        );
+        assert_eq!(fs::check(&fs), []);
+    }
+
+    pub fn mount_rejects_other_disks() {
+        let fs = fs::format(DISK).unwrap();
+        let device = fs.disk().device().clone();
+        drop(fs);
+        device.write(0, &vec![0; device.sector_size()]).unwrap();
+        assert_eq!(fs::mount(DISK).err(), Some(FsError::BadMagic));
+    }
+}
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::many_small_allocations_grow_the_heap,
+        &tests::large_allocations_grow_it_too,
+        &tests::growth_stops_at_the_limit,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use alloc::alloc::{alloc, dealloc, Layout};
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use lateral::alloc::heap::{self, DEFAULT_HEAP_LIMIT, HEAP_SIZE};
+
+    const MIB: usize = 0x10_0000;
+
+    pub fn many_small_allocations_grow_the_heap() {
+        // Twice the initial heap, a block at a time.
+        let blocks: Vec<Box<[u64; 512]>> = (0..2 * HEAP_SIZE / 4096)
+            .map(|i| Box::new([i as u64; 512]))
+            .collect();
+        for (i, block) in blocks.iter().enumerate() {
+            assert!(block.iter().all(|&word| word == i as u64));
+        }
+        assert!(heap::size() >= 2 * HEAP_SIZE);
+    }
+
+    pub fn large_allocations_grow_it_too() {
+        let len = 64 * MIB;
+        let mut big = vec![0u8; len];
+        assert!(heap::size() >= len);
+        for (i, byte) in big.iter_mut().enumerate().step_by(4096) {
+            *byte = (i / 4096) as u8;
+        }
+        big[len - 1] = 0xAA;
+        assert!(big
+            .iter()
+            .enumerate()
+            .step_by(4096)
+            .all(|(i, &b)| b == (i / 4096) as u8));
+        assert_eq!(big[len - 1], 0xAA);
+
+        // Freed memory is reused before the heap grows again.
+        drop(big);
+        let size = heap::size();
+        let again = vec![1u8; len];
+        assert_eq!(heap::size(), size);
+        drop(again);
+    }
+
+    pub fn growth_stops_at_the_limit() {
+        heap::set_limit(heap::size() + 8 * MIB);
+        // Past every warning threshold by now.
+        let fits = vec![0u8; 6 * MIB];
+        unsafe {
+            let layout = Layout::from_size_align(8 * MIB, 8).unwrap();
+            assert!(alloc(layout).is_null());
+        }
+        drop(fits);
+        heap::set_limit(DEFAULT_HEAP_LIMIT);
+
+        unsafe {
+            let layout = Layout::from_size_align(16 * MIB, 4096).unwrap();
+            let ptr = alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 4096, 0);
+            dealloc(ptr, layout);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[&tests::runs_hello_world]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use lateral::loader::elf;
+
+    /// `apps/hello-world`, built against `liblateral` by the build script.
+    static HELLO_WORLD: &[u8] = include_bytes!(env!("LATERAL_APP_HELLO_WORLD"));
+
+    pub fn runs_hello_world() {
+        let program = elf::load(
+            "hello-world",
+            HELLO_WORLD,
+            &["hello-world", "lateral"],
+            &["HOME=/"],
+        )
+        .expect("failed to load hello-world");
+        let process = program.process().clone();
+        let heap = process.brk(0).expect("the loader sets up a heap");
+
+        assert_eq!(program.spawn().join(), 0);
+        // Greeting by name allocates, which grows the heap.
+        assert!(process.brk(0).expect("the heap is still there") > heap);
+    }
+}
@@ -0,0 +1,215 @@
+#![no_std]
+#![no_main]
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::areas_split_and_find_room,
+        &tests::pages_are_backed_on_first_touch,
+        &tests::writable_and_executable_are_exclusive,
+        &tests::bad_faults_kill_the_process,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use lateral::cpu::user::enter_user_mode;
+    use lateral::mem::space::{USER_END, USER_START};
+    use lateral::process::vma::{Area, Areas, MapError};
+    use lateral::process::{self, Process};
+    use lateral::syscall::{SyscallError, SyscallNumber, PROT_EXEC, PROT_READ, PROT_WRITE};
+    use lateral::{syscall, thread};
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    use x86_64::VirtAddr;
+
+    const PAGE: u64 = 4096;
+    const CODE: u64 = 0x800_0000_0000;
+    const DATA: u64 = CODE + PAGE;
+    const STACK: u64 = CODE + 2 * PAGE;
+
+    /// `mov [DATA], rax`, then `jmp $` if that didn't fault.
+    #[rustfmt::skip]
+    const WRITE_DATA: [u8; 12] = [
+        0x48, 0xa3,
+        DATA as u8, (DATA >> 8) as u8, (DATA >> 16) as u8, (DATA >> 24) as u8,
+        (DATA >> 32) as u8, (DATA >> 40) as u8, (DATA >> 48) as u8, (DATA >> 56) as u8,
+        0xeb, 0xfe,
+    ];
+
+    fn rw() -> PageTableFlags {
+        PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+    }
+
+    pub fn areas_split_and_find_room() {
+        let mut areas = Areas::new();
+        let start = USER_START;
+        areas.insert(start, start + 4 * PAGE, rw()).unwrap();
+        assert!(matches!(
+            areas.insert(start + 3 * PAGE, start + 5 * PAGE, rw()),
+            Err(MapError::NoSpace)
+        ));
+        assert!(matches!(
+            areas.insert(start + 1, start + PAGE, rw()),
+            Err(MapError::Invalid)
+        ));
+
+        // Punch a hole in the middle, then make the last page read-only.
+        areas.remove(start + PAGE, start + 2 * PAGE).unwrap();
+        areas
+            .protect(
+                start + 3 * PAGE,
+                start + 4 * PAGE,
+                PageTableFlags::NO_EXECUTE,
+            )
+            .unwrap();
+        {
+            let mut iter = areas.iter().copied();
+            let area = |start, end, flags| Area { start, end, flags };
+            assert_eq!(iter.next(), Some(area(start, start + PAGE, rw())));
+            assert_eq!(
+                iter.next(),
+                Some(area(start + 2 * PAGE, start + 3 * PAGE, rw()))
+            );
+            assert_eq!(
+                iter.next(),
+                Some(area(
+                    start + 3 * PAGE,
+                    start + 4 * PAGE,
+                    PageTableFlags::NO_EXECUTE
+                ))
+            );
+            assert_eq!(iter.next(), None);
+        }
+
+        // The hole can't be protected, but it's the only free page under the last area.
+        assert!(areas.protect(start, start + 2 * PAGE, rw()).is_err());
+        assert!(areas.find(start + PAGE).is_none());
+        assert_eq!(areas.find_free(PAGE, start + 3 * PAGE), Some(start + PAGE));
+        assert_eq!(areas.find_free(2 * PAGE, start + 3 * PAGE), None);
+        assert_eq!(areas.find_free(PAGE, USER_END), Some(USER_END - PAGE));
+    }
+
+    pub fn pages_are_backed_on_first_touch() {
+        let process = Process::new("lazy").expect("failed to create a process");
+        let task = process.spawn("lazy", || unsafe {
+            let len = 3 * PAGE;
+            let addr = syscall!(int 0x80; SyscallNumber::Mmap, 0, len, PROT_READ | PROT_WRITE)
+                .expect("failed to map memory");
+            let me = process::current().unwrap();
+            let addr = VirtAddr::new(addr as u64);
+            assert!(me.translate(addr).is_none());
+
+            // Faulted in by the write.
+            let ptr = (addr + PAGE).as_mut_ptr::<u64>();
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(0x1234);
+            assert!(me.translate(addr + PAGE).is_some());
+            assert!(me.translate(addr).is_none());
+
+            // Syscalls may point into memory nothing has touched.
+            let message = addr + 2 * PAGE;
+            assert!(me.translate(message).is_none());
+            assert_eq!(
+                syscall!(int 0x80; SyscallNumber::Log, message.as_u64(), 1),
+                Ok(0)
+            );
+            assert!(me.translate(message).is_some());
+
+            assert_eq!(
+                syscall!(int 0x80; SyscallNumber::Munmap, addr.as_u64(), len),
+                Ok(0)
+            );
+            assert!(me.translate(addr + PAGE).is_none());
+            assert!(me.areas().is_empty());
+        });
+        assert_eq!(task.join(), 0);
+    }
+
+    pub fn writable_and_executable_are_exclusive() {
+        let process = Process::new("w^x").expect("failed to create a process");
+        let task = process.spawn("w^x", || unsafe {
+            assert_eq!(
+                syscall!(
+                    int 0x80;
+                    SyscallNumber::Mmap,
+                    0,
+                    PAGE,
+                    PROT_READ | PROT_WRITE | PROT_EXEC
+                ),
+                Err(SyscallError::EINVAL)
+            );
+            let addr = syscall!(int 0x80; SyscallNumber::Mmap, CODE, PAGE, PROT_READ | PROT_WRITE)
+                .expect("failed to map memory");
+            assert_eq!(addr as u64, CODE);
+            assert_eq!(
+                syscall!(int 0x80; SyscallNumber::Mmap, CODE, PAGE, PROT_READ),
+                Err(SyscallError::ENOMEM)
+            );
+
+            (CODE as *mut u8).write_volatile(0xc3);
+            assert_eq!(
+                syscall!(int 0x80; SyscallNumber::Mprotect, CODE, PAGE, PROT_READ | PROT_EXEC),
+                Ok(0)
+            );
+            let me = process::current().unwrap();
+            let flags = me.flags(VirtAddr::new(CODE)).unwrap();
+            assert!(!flags.contains(PageTableFlags::WRITABLE));
+            assert!(!flags.contains(PageTableFlags::NO_EXECUTE));
+
+            assert_eq!(
+                syscall!(int 0x80; SyscallNumber::Mprotect, CODE, 2 * PAGE, PROT_READ),
+                Err(SyscallError::ENOMEM)
+            );
+            assert_eq!(
+                syscall!(int 0x80; SyscallNumber::Mprotect, CODE + 1, PAGE, PROT_READ),
+                Err(SyscallError::EINVAL)
+            );
+        });
+        assert_eq!(task.join(), 0);
+    }
+
+    pub fn bad_faults_kill_the_process() {
+        let process = Process::new("faulty").expect("failed to create a process");
+        let page = |addr| Page::containing_address(VirtAddr::new(addr));
+        process
+            .map(Page::range(page(CODE), page(DATA)), PageTableFlags::empty())
+            .unwrap();
+        // Read-only, so the program's write is one no area allows.
+        process
+            .map(
+                Page::range(page(DATA), page(STACK)),
+                PageTableFlags::NO_EXECUTE,
+            )
+            .unwrap();
+        process
+            .map(Page::range(page(STACK), page(STACK) + 1), rw())
+            .unwrap();
+        assert!(process.write(VirtAddr::new(CODE), &WRITE_DATA));
+
+        let bystander = process.spawn("bystander", || loop {
+            let _ = unsafe { syscall!(int 0x80; SyscallNumber::Uptime) };
+        });
+        let faulting = process.spawn("faulting", || unsafe {
+            enter_user_mode(VirtAddr::new(CODE), VirtAddr::new(STACK + PAGE))
+        });
+
+        assert_eq!(faulting.join(), thread::FAULT_EXIT_CODE);
+        assert_eq!(bystander.join(), thread::FAULT_EXIT_CODE);
+        assert_eq!(process.wait(), Some(thread::FAULT_EXIT_CODE));
+    }
+}
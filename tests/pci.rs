@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::functions_are_found,
+        &tests::bars_and_capabilities_are_decoded,
+        &tests::functions_are_claimed_once,
+        &tests::syscall_lists_every_function,
+        &tests::lspci_runs,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use alloc::vec;
+
+    use lateral::loader::apps;
+    use lateral::pci::capability::{Capability, ID_VENDOR};
+    use lateral::pci::{self, Address, Bar, Match, CLASS_BRIDGE};
+    use lateral::syscall;
+    use lateral::syscall::{SyscallNumber, PCI_RECORD_SIZE};
+
+    /// The virtio block device `tests/virtio.img` is attached as.
+    const VIRTIO_BLK: Match = Match::id(0x1AF4, 0x1001);
+
+    pub fn functions_are_found() {
+        let count = pci::init();
+        let devices = pci::devices();
+        assert_eq!(devices.len(), count);
+        assert!(devices
+            .windows(2)
+            .all(|pair| pair[0].address < pair[1].address));
+
+        let host = devices[0];
+        assert_eq!(
+            host.address,
+            Address {
+                bus: 0,
+                device: 0,
+                function: 0
+            }
+        );
+        assert_eq!((host.class, host.subclass), (CLASS_BRIDGE, 0x00));
+
+        // The IDE controller, which the ATA driver uses through its legacy ports.
+        assert!(!pci::matching(Match::class(0x01, 0x01)).is_empty());
+        assert_eq!(pci::matching(VIRTIO_BLK).len(), 1);
+    }
+
+    pub fn bars_and_capabilities_are_decoded() {
+        let virtio = pci::matching(VIRTIO_BLK)[0];
+        match virtio.bar(0) {
+            Some(Bar::Io { port, size }) => {
+                assert_ne!(port, 0);
+                // Sizes are powers of two, and the legacy registers take more than 0x14 bytes.
+                assert!(size.is_power_of_two() && size > 0x14);
+                assert_eq!(port as u32 & (size - 1), 0);
+            }
+            bar => panic!("BAR 0 should be I/O ports, not {:?}", bar),
+        }
+
+        // QEMU's virtio functions describe their modern interface in vendor capabilities, and
+        // can use MSI-X.
+        let capabilities = virtio.capabilities();
+        assert!(capabilities.iter().any(|c| c.id() == ID_VENDOR));
+        let msi_x = capabilities.iter().find_map(|c| match c {
+            Capability::MsiX(msi_x) => Some(*msi_x),
+            _ => None,
+        });
+        let msi_x = msi_x.expect("no MSI-X capability");
+        assert!(msi_x.vectors >= 1);
+        assert!(matches!(
+            virtio.bar(msi_x.table.0 as usize),
+            Some(Bar::Memory { .. })
+        ));
+    }
+
+    pub fn functions_are_claimed_once() {
+        let claimed = pci::claim(VIRTIO_BLK, "test");
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(pci::matching(VIRTIO_BLK)[0].driver, Some("test"));
+        assert!(pci::claim(VIRTIO_BLK, "other").is_empty());
+
+        pci::release(claimed[0].address);
+        assert_eq!(pci::matching(VIRTIO_BLK)[0].driver, None);
+        assert_eq!(pci::claim(VIRTIO_BLK, "other").len(), 1);
+        pci::release(claimed[0].address);
+    }
+
+    pub fn syscall_lists_every_function() {
+        let devices = pci::devices();
+        assert_eq!(
+            unsafe { syscall!(int 0x80; SyscallNumber::PciDevices, 0, 0) },
+            Ok(devices.len())
+        );
+
+        // Room for one fewer than there are.
+        let mut records = vec![0xAAu8; devices.len() * PCI_RECORD_SIZE];
+        assert_eq!(
+            unsafe {
+                syscall!(
+                    int 0x80;
+                    SyscallNumber::PciDevices,
+                    records.as_mut_ptr(),
+                    devices.len() - 1
+                )
+            },
+            Ok(devices.len())
+        );
+        for (device, record) in devices.iter().zip(records.chunks(PCI_RECORD_SIZE)) {
+            let address = &device.address;
+            assert_eq!(record[..3], [address.bus, address.device, address.function]);
+            assert_eq!(
+                record[4..7],
+                [device.class, device.subclass, device.interface]
+            );
+            assert_eq!(record[8..10], device.vendor.to_le_bytes());
+            assert_eq!(record[10..12], device.device.to_le_bytes());
+        }
+        assert!(records[(devices.len() - 1) * PCI_RECORD_SIZE..]
+            .iter()
+            .all(|&b| b == 0xAA));
+    }
+
+    pub fn lspci_runs() {
+        let lspci = apps::spawn("system/lspci", &[]).expect("failed to spawn system/lspci");
+        assert_eq!(lspci.wait(), Some(0));
+    }
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn("busy_first", tests::busy_first);
+    runtime.spawn("busy_second", tests::busy_second);
+
+    // Neither thread ever yields, so this only returns if the PIT preempts them.
+    lateral::test::run(&tests::busy_threads_make_progress);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use lateral::time::rtc::ticks;
+
+    const TARGET: usize = 100_000;
+    const TIMEOUT_TICKS: usize = 5_000;
+
+    static FIRST: AtomicUsize = AtomicUsize::new(0);
+    static SECOND: AtomicUsize = AtomicUsize::new(0);
+
+    pub fn busy_first() {
+        loop {
+            FIRST.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn busy_second() {
+        loop {
+            SECOND.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn busy_threads_make_progress() {
+        let deadline = ticks() + TIMEOUT_TICKS;
+        while FIRST.load(Ordering::Relaxed) < TARGET || SECOND.load(Ordering::Relaxed) < TARGET {
+            assert!(ticks() < deadline, "busy threads were not preempted");
+            core::hint::spin_loop();
+        }
+    }
+}
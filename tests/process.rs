@@ -0,0 +1,217 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    // Spawned before there's a runtime, so it waits in the queue until `init`.
+    tests::queue_thread();
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::queued_threads_keep_their_process_and_priority,
+        &tests::spawns_and_waits_for_children,
+        &tests::bad_requests_are_rejected,
+        &tests::failed_loads_leave_no_process,
+        &tests::kill_ends_every_thread,
+        &tests::orphans_are_reaped,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use lateral::cpu::user::enter_user_mode;
+    use lateral::loader::apps::{self, SpawnError};
+    use lateral::loader::elf::{self, ElfError};
+    use lateral::process::{self, Process, ProcessId, KILLED_EXIT_CODE};
+    use lateral::syscall;
+    use lateral::syscall::{SyscallError, SyscallNumber};
+    use lateral::thread::{self, Priority};
+    use lateral::time::rtc::ticks;
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    use x86_64::VirtAddr;
+
+    const CODE: u64 = 0x800_0000_0000;
+    const DATA: u64 = CODE + 0x1000;
+    const STACK: u64 = CODE + 0x2000;
+
+    /// `jmp $`
+    const SPIN: [u8; 2] = [0xeb, 0xfe];
+
+    /// A process with `CODE`, `DATA` and `STACK` mapped and `command` written to `DATA`.
+    fn parent(command: &[u8]) -> Arc<Process> {
+        let process = Process::new("parent").expect("failed to create a process");
+        let pages = Page::range(
+            Page::containing_address(VirtAddr::new(CODE)),
+            Page::containing_address(VirtAddr::new(STACK + 0x1000)),
+        );
+        process
+            .map(pages, PageTableFlags::WRITABLE)
+            .expect("failed to map the process's pages");
+        assert!(process.write(VirtAddr::new(CODE), &SPIN));
+        assert!(process.write(VirtAddr::new(DATA), command));
+        process
+    }
+
+    /// Set by the queued thread once it has checked where it runs.
+    static QUEUED_RAN: AtomicBool = AtomicBool::new(false);
+
+    pub fn queue_thread() {
+        let process = Process::new("queued").expect("failed to create a process");
+        let id = process.id();
+        let queued = thread::spawn_in(process, "queued", Priority::Interactive, move || {
+            assert_eq!(process::current().map(|me| me.id()), Some(id));
+            assert_eq!(
+                thread::priority(thread::current()),
+                Some(Priority::Interactive)
+            );
+            QUEUED_RAN.store(true, Ordering::SeqCst);
+        });
+        // Nothing can join it from here.
+        drop(queued);
+    }
+
+    pub fn queued_threads_keep_their_process_and_priority() {
+        let deadline = ticks() + 1_000;
+        while !QUEUED_RAN.load(Ordering::SeqCst) {
+            assert!(ticks() < deadline, "the queued thread never ran");
+            thread::sleep_ticks(1);
+        }
+    }
+
+    fn spawn(command: &[u8]) -> Result<usize, SyscallError> {
+        unsafe { syscall!(int 0x80; SyscallNumber::Spawn, DATA, command.len()) }
+    }
+
+    pub fn spawns_and_waits_for_children() {
+        const COMMAND: &[u8] = b"system/hello-world\0lateral\0";
+        let parent = parent(COMMAND);
+        let id = parent.id();
+
+        let task = parent.spawn("parent", move || {
+            let me = unsafe { syscall!(int 0x80; SyscallNumber::GetPid) };
+            assert_eq!(me, Ok(id.as_usize()));
+
+            let pid = spawn(COMMAND).expect("failed to spawn hello-world");
+            let child = process::find(ProcessId::from_usize(pid)).expect("the child is listed");
+            assert_eq!(child.parent(), Some(id));
+            assert_eq!(child.name(), "system/hello-world");
+
+            assert_eq!(
+                unsafe { syscall!(int 0x80; SyscallNumber::Wait, pid) },
+                Ok(0)
+            );
+            assert!(process::find(child.id()).is_none());
+            assert_eq!(
+                unsafe { syscall!(int 0x80; SyscallNumber::Wait, pid) },
+                Err(SyscallError::ESRCH)
+            );
+        });
+
+        assert_eq!(task.join(), 0);
+        assert_eq!(parent.wait(), Some(0));
+    }
+
+    pub fn bad_requests_are_rejected() {
+        // Started by the kernel, so nobody's child.
+        let help = apps::spawn("system/help", &[]).expect("failed to spawn system/help");
+        let stranger = help.id().as_usize();
+
+        const COMMAND: &[u8] = b"system/nothing\0";
+        let parent = parent(COMMAND);
+        let task = parent.spawn("parent", move || {
+            assert_eq!(spawn(COMMAND), Err(SyscallError::ENOENT));
+            assert_eq!(
+                unsafe { syscall!(int 0x80; SyscallNumber::Wait, stranger) },
+                Err(SyscallError::ECHILD)
+            );
+            assert_eq!(
+                unsafe { syscall!(int 0x80; SyscallNumber::Kill, usize::MAX) },
+                Err(SyscallError::ESRCH)
+            );
+            assert_eq!(
+                unsafe { syscall!(int 0x80; SyscallNumber::Kill, stranger) },
+                Err(SyscallError::EPERM)
+            );
+        });
+        assert_eq!(task.join(), 0);
+
+        assert_eq!(
+            unsafe { syscall!(int 0x80; SyscallNumber::GetPid) },
+            Err(SyscallError::ESRCH)
+        );
+        assert_eq!(help.wait(), Some(0));
+        // Nobody is left to wait for it.
+        assert!(process::find(help.id()).is_none());
+    }
+
+    pub fn failed_loads_leave_no_process() {
+        let before = process::processes().len();
+        let long = "x".repeat(elf::STACK_SIZE as usize);
+        assert!(matches!(
+            apps::spawn("system/help", &[&long]),
+            Err(SpawnError::Elf(ElfError::ArgumentsTooLong))
+        ));
+        assert_eq!(process::processes().len(), before);
+    }
+
+    pub fn kill_ends_every_thread() {
+        let process = parent(&[]);
+        let calling = process.spawn("calling", || loop {
+            let _ = unsafe { syscall!(int 0x80; SyscallNumber::Uptime) };
+        });
+        let spinning = process.spawn("spinning", || unsafe {
+            enter_user_mode(VirtAddr::new(CODE), VirtAddr::new(STACK + 0x1000))
+        });
+
+        thread::sleep_ticks(5);
+        assert_eq!(process.exit_code(), None);
+        unsafe { syscall!(int 0x80; SyscallNumber::Kill, process.id().as_usize()) }
+            .expect("failed to kill the process");
+
+        assert_eq!(calling.join(), KILLED_EXIT_CODE);
+        assert_eq!(spinning.join(), KILLED_EXIT_CODE);
+        assert_eq!(process.wait(), Some(KILLED_EXIT_CODE));
+        assert!(process::find(process.id()).is_none());
+    }
+
+    pub fn orphans_are_reaped() {
+        static CHILD: AtomicUsize = AtomicUsize::new(0);
+        const COMMAND: &[u8] = b"system/help\0";
+
+        let parent = parent(COMMAND);
+        let task = parent.spawn("parent", || {
+            let pid = spawn(COMMAND).expect("failed to spawn system/help");
+            CHILD.store(pid, Ordering::SeqCst);
+            unsafe { syscall!(int 0x80; SyscallNumber::Exit, 7) }.unwrap();
+            unreachable!();
+        });
+        assert_eq!(task.join(), 7);
+        assert_eq!(parent.wait(), Some(7));
+
+        // The child is either already gone or no longer anybody's.
+        let pid = ProcessId::from_usize(CHILD.load(Ordering::SeqCst));
+        if let Some(child) = process::find(pid) {
+            assert_eq!(child.parent(), None);
+            assert_eq!(child.wait(), Some(0));
+        }
+        assert!(process::find(pid).is_none());
+    }
+}
@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::cpu::smp;
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    smp::init().expect("failed to start the application processors");
+
+    lateral::test::runner(&[
+        &tests::unmapped_pages_fault_on_every_cpu,
+        &tests::protected_pages_fault_on_every_cpu,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+/// A process's threads touch a page from every CPU while another thread unmaps or protects it.
+/// Once that returns, no CPU may get through to the page on what its TLB still holds: every
+/// thread's next touch has to fault, which kills the process.
+mod tests {
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use alloc::sync::Arc;
+    use lateral::cpu::percpu;
+    use lateral::cpu::smp;
+    use lateral::process::Process;
+    use lateral::thread::{self, JoinHandle};
+    use lateral::time::rtc::ticks;
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    use x86_64::VirtAddr;
+
+    const WORKERS: usize = 4;
+    const TIMEOUT_TICKS: usize = 5_000;
+    const DATA: u64 = 0x800_0000_0000;
+
+    /// Which CPUs ran a worker, a bit each.
+    static CPUS_SEEN: AtomicUsize = AtomicUsize::new(0);
+    /// Set once the page has been unmapped or protected.
+    static CHANGED: AtomicBool = AtomicBool::new(false);
+    /// Accesses that went through after `CHANGED` was seen.
+    static STALE: AtomicUsize = AtomicUsize::new(0);
+
+    fn page() -> Page {
+        Page::containing_address(VirtAddr::new(DATA))
+    }
+
+    /// Runs `touch` on `DATA` in a loop, counting the times it gets through once the page has
+    /// changed.
+    fn worker(touch: fn(*mut u64)) {
+        loop {
+            CPUS_SEEN.fetch_or(1 << percpu::id(), Ordering::Relaxed);
+            let changed = CHANGED.load(Ordering::SeqCst);
+            touch(DATA as *mut u64);
+            if changed {
+                STALE.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Starts workers running `touch` in a fresh process with `DATA` mapped read-write, and
+    /// waits until they're running on more than one CPU.
+    fn start(touch: fn(*mut u64)) -> (Arc<Process>, [JoinHandle; WORKERS]) {
+        assert!(smp::online() >= 2, "no application processor came online");
+        CPUS_SEEN.store(0, Ordering::Relaxed);
+        CHANGED.store(false, Ordering::SeqCst);
+        STALE.store(0, Ordering::Relaxed);
+
+        let process = Process::new("toucher").expect("failed to create a process");
+        process
+            .map(
+                Page::range(page(), page() + 1),
+                PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            )
+            .expect("failed to map memory");
+        let handles = core::array::from_fn(|_| process.spawn("toucher", move || worker(touch)));
+
+        let deadline = ticks() + TIMEOUT_TICKS;
+        while CPUS_SEEN.load(Ordering::Relaxed).count_ones() < 2 {
+            assert!(ticks() < deadline, "the workers ran on a single CPU");
+            thread::sleep_ticks(1);
+        }
+        (process, handles)
+    }
+
+    /// Waits for every worker to fault, which they all must once the page has changed.
+    fn finish(process: Arc<Process>, handles: [JoinHandle; WORKERS]) {
+        CHANGED.store(true, Ordering::SeqCst);
+        let deadline = ticks() + TIMEOUT_TICKS;
+        while process.exit_code().is_none() {
+            assert!(ticks() < deadline, "a CPU kept using a stale mapping");
+            thread::sleep_ticks(10);
+        }
+        for handle in handles {
+            assert_eq!(handle.join(), thread::FAULT_EXIT_CODE);
+        }
+        assert_eq!(STALE.load(Ordering::Relaxed), 0);
+    }
+
+    pub fn unmapped_pages_fault_on_every_cpu() {
+        let (process, handles) = start(|data| unsafe {
+            data.read_volatile();
+        });
+        process
+            .unmap(Page::range(page(), page() + 1))
+            .expect("failed to unmap memory");
+        finish(process, handles);
+    }
+
+    pub fn protected_pages_fault_on_every_cpu() {
+        let (process, handles) = start(|data| unsafe { data.write_volatile(1) });
+        process
+            .protect(Page::range(page(), page() + 1), PageTableFlags::NO_EXECUTE)
+            .expect("failed to protect memory");
+        finish(process, handles);
+    }
+}
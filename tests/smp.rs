@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+use lateral::cpu::smp;
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    smp::init().expect("failed to start the application processors");
+
+    for _ in 0..tests::WORKERS {
+        runtime.spawn("worker", tests::worker);
+    }
+
+    lateral::test::run(&tests::workers_run_concurrently);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use lateral::cpu::percpu::{self, MAX_CPUS};
+    use lateral::cpu::smp;
+    use lateral::time::rtc::ticks;
+
+    pub const WORKERS: usize = 4;
+    const TIMEOUT_TICKS: usize = 5_000;
+
+    /// The last PIT tick a worker was seen running on each CPU.
+    static LAST_SEEN: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+    pub fn worker() {
+        loop {
+            LAST_SEEN[percpu::id()].store(ticks(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn workers_run_concurrently() {
+        assert!(smp::online() >= 2, "no application processor came online");
+
+        // Workers start out on the BSP; the other CPUs' idle threads have to steal them.
+        let deadline = ticks() + TIMEOUT_TICKS;
+        loop {
+            let now = ticks();
+            let busy = LAST_SEEN
+                .iter()
+                .filter(|seen| seen.load(Ordering::Relaxed) + 1 >= now && now > 1)
+                .count();
+            if busy >= 2 {
+                break;
+            }
+
+            assert!(now < deadline, "workers never ran on two CPUs at once");
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::producer_consumer_hand_off,
+        &tests::event_wait_times_out,
+        &tests::condvar_hand_off,
+        &tests::notify_all_wakes_every_waiter,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use alloc::collections::VecDeque;
+    use alloc::vec::Vec;
+
+    use lateral::thread::sync::{Condvar, Event, Mutex, Semaphore};
+    use lateral::thread::{self, JoinHandle, State};
+    use lateral::time::rtc::ticks;
+
+    const ITEMS: usize = 1_000;
+    const TIMEOUT_TICKS: usize = 1_000;
+    const CAPACITY: usize = 4;
+
+    static BUFFER: Mutex<VecDeque<usize>> = Mutex::new(VecDeque::new());
+    static FILLED: Semaphore = Semaphore::new(0);
+    static EMPTY: Semaphore = Semaphore::new(CAPACITY);
+
+    fn produce() {
+        for item in 0..ITEMS {
+            EMPTY.acquire();
+            BUFFER.lock().push_back(item);
+            FILLED.release();
+        }
+    }
+
+    fn consume() {
+        for expected in 0..ITEMS {
+            FILLED.acquire();
+            let item = BUFFER.lock().pop_front();
+            assert_eq!(item, Some(expected));
+            EMPTY.release();
+        }
+    }
+
+    pub fn producer_consumer_hand_off() {
+        let consumer = thread::spawn("consumer", consume);
+
+        // With nothing produced yet the consumer has to park instead of spinning.
+        thread::sleep_ticks(10);
+        assert_eq!(consumer.state(), Some(State::Blocked));
+
+        let producer = thread::spawn("producer", produce);
+        assert_eq!(producer.join(), 0);
+        assert_eq!(consumer.join(), 0);
+        assert!(BUFFER.lock().is_empty());
+    }
+
+    pub fn event_wait_times_out() {
+        static EVENT: Event = Event::new();
+
+        let start = ticks();
+        assert!(!EVENT.wait_timeout(10));
+        assert!(ticks() >= start + 10);
+
+        // Raised before the deadline, it ends the wait.
+        let setter = thread::spawn("setter", || EVENT.set());
+        assert!(EVENT.wait_timeout(1_000));
+        assert_eq!(setter.join(), 0);
+    }
+
+    /// Waits until every thread in `threads` is parked.
+    fn wait_until_blocked(threads: &[JoinHandle]) {
+        let deadline = ticks() + TIMEOUT_TICKS;
+        while threads
+            .iter()
+            .any(|handle| handle.state() != Some(State::Blocked))
+        {
+            assert!(ticks() < deadline, "the waiters never blocked");
+            thread::sleep_ticks(1);
+        }
+    }
+
+    pub fn condvar_hand_off() {
+        static READY: Mutex<bool> = Mutex::new(false);
+        static CHANGED: Condvar = Condvar::new();
+
+        let waiter = thread::spawn("waiter", || {
+            let ready = CHANGED.wait_while(READY.lock(), |ready| !*ready);
+            assert!(*ready);
+        });
+        wait_until_blocked(core::slice::from_ref(&waiter));
+
+        *READY.lock() = true;
+        assert!(CHANGED.notify_one());
+        assert_eq!(waiter.join(), 0);
+    }
+
+    pub fn notify_all_wakes_every_waiter() {
+        const WAITERS: usize = 4;
+        static OPEN: Mutex<bool> = Mutex::new(false);
+        static OPENED: Condvar = Condvar::new();
+        static PASSED: Mutex<usize> = Mutex::new(0);
+
+        let waiters: Vec<JoinHandle> = (0..WAITERS)
+            .map(|_| {
+                thread::spawn("waiter", || {
+                    drop(OPENED.wait_while(OPEN.lock(), |open| !*open));
+                    *PASSED.lock() += 1;
+                })
+            })
+            .collect();
+        wait_until_blocked(&waiters);
+
+        *OPEN.lock() = true;
+        assert_eq!(OPENED.notify_all(), WAITERS);
+        for waiter in waiters {
+            assert_eq!(waiter.join(), 0);
+        }
+        assert_eq!(*PASSED.lock(), WAITERS);
+    }
+}
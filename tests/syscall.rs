@@ -0,0 +1,205 @@
+#![no_std]
+#![no_main]
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::unknown_syscalls_fail_with_enosys,
+        &tests::bad_arguments_are_rejected,
+        &tests::user_pointers_are_checked,
+        &tests::processes_only_lower_their_own_threads,
+        &tests::syscall_and_int_0x80_agree,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use core::arch::x86_64::_rdtsc;
+
+    use lateral::cpu::user::enter_user_mode;
+    use lateral::process::Process;
+    use lateral::syscall::{SyscallError, SyscallNumber};
+    use lateral::thread::{self, Priority};
+    use lateral::{serial_println, syscall};
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    use x86_64::VirtAddr;
+
+    const CODE: u64 = 0x800_0000_0000;
+    const DATA: u64 = CODE + 0x1000;
+    const STACK: u64 = CODE + 0x2000;
+    const CALLS: u32 = 10_000;
+    const PROGRAM_LEN: usize = 54;
+
+    const INT_0X80: [u8; 2] = [0xcd, 0x80];
+    const SYSCALL: [u8; 2] = [0x0f, 0x05];
+
+    /// Makes `CALLS` `uptime` syscalls with `instruction`, keeping the count in `r12` and a
+    /// marker in `rdi` that both paths must preserve. Stores the last result and `rdi` at `DATA`,
+    /// then reads address 0 to end.
+    #[rustfmt::skip]
+    const fn program(instruction: [u8; 2]) -> [u8; PROGRAM_LEN] {
+        let c = CALLS.to_le_bytes();
+        let d = DATA.to_le_bytes();
+        let e = (DATA + 8).to_le_bytes();
+        [
+            0x41, 0xbc, c[0], c[1], c[2], c[3],               // mov r12d, CALLS
+            0xbf, 0xef, 0xbe, 0xad, 0xde,                     // mov edi, 0xdeadbeef
+            0xb8, 0x01, 0x00, 0x00, 0x00,                     // 1: mov eax, 1
+            instruction[0], instruction[1],                   // int 0x80 / syscall
+            0x41, 0xff, 0xcc,                                 // dec r12d
+            0x75, 0xf4,                                       // jnz 1b
+            0x48, 0xa3, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7], // movabs [DATA], rax
+            0x48, 0x89, 0xf8,                                 // mov rax, rdi
+            0x48, 0xa3, e[0], e[1], e[2], e[3], e[4], e[5], e[6], e[7], // movabs [DATA + 8], rax
+            0x48, 0x8b, 0x04, 0x25, 0x00, 0x00, 0x00, 0x00,   // mov rax, [0]
+        ]
+    }
+
+    pub fn unknown_syscalls_fail_with_enosys() {
+        assert_eq!(
+            unsafe { syscall!(int 0x80; 0xFFFF) },
+            Err(SyscallError::ENOSYS)
+        );
+        assert!(unsafe { syscall!(int 0x80; SyscallNumber::Uptime) }.unwrap() > 0);
+    }
+
+    pub fn bad_arguments_are_rejected() {
+        let this = thread::current().as_usize();
+        let realtime = Priority::Realtime as usize;
+        assert_eq!(
+            unsafe { syscall!(int 0x80; SyscallNumber::SetPriority, this, 99) },
+            Err(SyscallError::EINVAL)
+        );
+        assert_eq!(
+            unsafe { syscall!(int 0x80; SyscallNumber::SetPriority, usize::MAX, realtime) },
+            Err(SyscallError::ESRCH)
+        );
+
+        let invalid = [0xFFu8, 0xFE];
+        assert_eq!(
+            unsafe { syscall!(int 0x80; SyscallNumber::Log, invalid.as_ptr(), invalid.len()) },
+            Err(SyscallError::EINVAL)
+        );
+    }
+
+    /// A thread inside a process may only pass pointers into the process's user half.
+    pub fn user_pointers_are_checked() {
+        let process = Process::new("pointers").expect("failed to create a process");
+        let data = Page::containing_address(VirtAddr::new(DATA));
+        process
+            .map(Page::range(data, data + 1), PageTableFlags::empty())
+            .expect("failed to map the user page");
+        let message = b"hello";
+        assert!(process.write(VirtAddr::new(DATA), message));
+
+        let checker = process.spawn("pointers", || {
+            let kernel = b"kernel";
+            let log =
+                |ptr: u64, len: usize| unsafe { syscall!(int 0x80; SyscallNumber::Log, ptr, len) };
+
+            assert_eq!(log(DATA, 5), Ok(0));
+            // Runs off the end of the mapped page.
+            assert_eq!(log(DATA + 0xFFE, 5), Err(SyscallError::EFAULT));
+            assert_eq!(log(CODE, 5), Err(SyscallError::EFAULT));
+            assert_eq!(log(kernel.as_ptr() as u64, 6), Err(SyscallError::EFAULT));
+            assert_eq!(log(u64::MAX - 2, 5), Err(SyscallError::EFAULT));
+        });
+        assert_eq!(checker.join(), 0);
+    }
+
+    /// A thread inside a process can't raise priorities or touch threads of anything else.
+    pub fn processes_only_lower_their_own_threads() {
+        let other = Process::new("other").expect("failed to create a process");
+        let bystander = other.spawn("bystander", || thread::sleep_ticks(100));
+        let kernel = thread::current().as_usize();
+        let bystander_id = bystander.id().as_usize();
+
+        let process = Process::new("priorities").expect("failed to create a process");
+        let checker = process.spawn("priorities", move || {
+            let set = |thread: usize, priority: Priority| unsafe {
+                syscall!(int 0x80; SyscallNumber::SetPriority, thread, priority as usize)
+            };
+            let this = thread::current().as_usize();
+
+            assert_eq!(set(this, Priority::Realtime), Err(SyscallError::EPERM));
+            assert_eq!(set(this, Priority::Interactive), Err(SyscallError::EPERM));
+            assert_eq!(set(kernel, Priority::Idle), Err(SyscallError::EPERM));
+            assert_eq!(set(bystander_id, Priority::Idle), Err(SyscallError::EPERM));
+            assert_eq!(set(usize::MAX, Priority::Idle), Err(SyscallError::ESRCH));
+
+            assert_eq!(set(this, Priority::Normal), Ok(0));
+            assert_eq!(set(this, Priority::Idle), Ok(0));
+            assert_eq!(thread::priority(thread::current()), Some(Priority::Idle));
+            assert_eq!(set(this, Priority::Normal), Err(SyscallError::EPERM));
+        });
+        assert_eq!(checker.join(), 0);
+        assert_eq!(bystander.join(), 0);
+        assert_eq!(thread::priority(thread::current()), Some(Priority::Normal));
+    }
+
+    /// Runs `program` in a fresh process. Returns the TSC cycles it took, and what it stored.
+    fn run(program: &'static [u8]) -> (u64, [u64; 2]) {
+        let process = Process::new("bench").expect("failed to create a process");
+        let pages = Page::range(
+            Page::containing_address(VirtAddr::new(CODE)),
+            Page::containing_address(VirtAddr::new(STACK + 0x1000)),
+        );
+        process
+            .map(pages, PageTableFlags::WRITABLE)
+            .expect("failed to map the user pages");
+        assert!(process.write(VirtAddr::new(CODE), program));
+
+        let start = unsafe { _rdtsc() };
+        let task = process.spawn("bench", || unsafe {
+            enter_user_mode(VirtAddr::new(CODE), VirtAddr::new(STACK + 0x1000))
+        });
+        assert_eq!(task.join(), thread::FAULT_EXIT_CODE);
+        let cycles = unsafe { _rdtsc() } - start;
+
+        let mut data = [0; 16];
+        assert!(process.read(VirtAddr::new(DATA), &mut data));
+        let word = |i: usize| u64::from_le_bytes(data[i * 8..i * 8 + 8].try_into().unwrap());
+        (cycles, [word(0), word(1)])
+    }
+
+    pub fn syscall_and_int_0x80_agree() {
+        static SLOW: [u8; PROGRAM_LEN] = program(INT_0X80);
+        static FAST: [u8; PROGRAM_LEN] = program(SYSCALL);
+
+        let uptime = || unsafe { syscall!(int 0x80; SyscallNumber::Uptime) }.unwrap() as u64;
+
+        let before = uptime();
+        let (slow_cycles, slow) = run(&SLOW);
+        let (fast_cycles, fast) = run(&FAST);
+        let after = uptime();
+
+        // Both paths got the same clock, read in the order they ran, and handed `rdi` back.
+        assert!(before <= slow[0] && slow[0] <= fast[0] && fast[0] <= after);
+        assert_eq!(slow[1], 0xdead_beef);
+        assert_eq!(fast[1], slow[1]);
+
+        // Only reported: timings under emulation are too noisy to assert on.
+        serial_println!();
+        serial_println!(
+            "cycles per call over {} calls: int 0x80 {}, syscall {}",
+            CALLS,
+            slow_cycles / CALLS as u64,
+            fast_cycles / CALLS as u64
+        );
+    }
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::run(&tests::user_page_fault_kills_only_the_task);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use lateral::cpu::user::enter_user_mode;
+    use lateral::mem::paging;
+    use lateral::process::Process;
+    use lateral::thread;
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    use x86_64::VirtAddr;
+
+    const CODE: u64 = 0x800_0000_0000;
+    const STACK: u64 = CODE + 0x1000;
+    const UNMAPPED: u64 = CODE + 0x10_0000;
+
+    /// Makes an `uptime` syscall, which only works if `int 0x80` is open to ring 3, then reads
+    /// from an unmapped page.
+    #[rustfmt::skip]
+    const PROGRAM: [u8; 19] = [
+        0xb8, 0x01, 0x00, 0x00, 0x00,                   // mov eax, 1
+        0xcd, 0x80,                                     // int 0x80
+        0x48, 0xa1,                                     // movabs rax, [UNMAPPED]
+        UNMAPPED as u8, (UNMAPPED >> 8) as u8, (UNMAPPED >> 16) as u8, (UNMAPPED >> 24) as u8,
+        (UNMAPPED >> 32) as u8, (UNMAPPED >> 40) as u8, (UNMAPPED >> 48) as u8, (UNMAPPED >> 56) as u8,
+        0xeb, 0xfe,                                     // jmp $
+    ];
+
+    fn faulting_task() {
+        unsafe {
+            core::ptr::copy_nonoverlapping(PROGRAM.as_ptr(), CODE as *mut u8, PROGRAM.len());
+            enter_user_mode(VirtAddr::new(CODE), VirtAddr::new(STACK + 0x1000));
+        }
+    }
+
+    fn bystander() {
+        for _ in 0..20 {
+            thread::sleep_ticks(1);
+        }
+    }
+
+    pub fn user_page_fault_kills_only_the_task() {
+        let bystander = thread::spawn("bystander", bystander);
+        let process = Process::new("user").expect("failed to create a process");
+        let pages = Page::range(
+            Page::containing_address(VirtAddr::new(CODE)),
+            Page::containing_address(VirtAddr::new(STACK + 0x1000)),
+        );
+        process
+            .map(pages, PageTableFlags::WRITABLE)
+            .expect("failed to map the user pages");
+        assert!(process.translate(VirtAddr::new(UNMAPPED)).is_none());
+        // The kernel's own tables don't see the process's user half.
+        let offset = paging::physical_memory_offset();
+        assert!(paging::translate_addr(VirtAddr::new(CODE), offset).is_none());
+
+        let task = process.spawn("user", faulting_task);
+
+        assert_eq!(task.join(), thread::FAULT_EXIT_CODE);
+        assert_eq!(bystander.join(), 0);
+    }
+}
@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use lateral::thread::Runtime;
+
+// Entry point.
+bootloader::entry_point!(main);
+fn main(boot_info: &'static bootloader::BootInfo) -> ! {
+    lateral::init();
+    lateral::mem::init(boot_info);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    lateral::test::runner(&[
+        &tests::device_is_found,
+        &tests::sectors_round_trip,
+        &tests::large_transfers_are_batched,
+        &tests::bad_requests_are_rejected,
+    ]);
+    lateral::halt_loop();
+}
+
+// Panic handler.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    lateral::test::panic(info)
+}
+
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use lateral::block::{self, virtio, BlockDevice, BlockError};
+
+    /// `tests/virtio.img`, the only virtio drive attached.
+    const DISK: &str = "virtio0";
+    const DISK_SECTORS: u64 = 4096;
+
+    fn disk() -> Arc<dyn BlockDevice> {
+        block::find(DISK).expect("the test disk isn't attached")
+    }
+
+    fn pattern(len: usize, seed: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 512 + seed) as u8).collect()
+    }
+
+    pub fn device_is_found() {
+        assert_eq!(virtio::init(), 1);
+        let disk = disk();
+        assert_eq!(disk.sector_size(), virtio::SECTOR_SIZE);
+        assert_eq!(disk.sector_count(), DISK_SECTORS);
+    }
+
+    pub fn sectors_round_trip() {
+        let disk = disk();
+        let size = disk.sector_size();
+
+        // The image is empty and attached as a snapshot, so every run starts from zeros.
+        let mut buf = vec![0xFFu8; 4 * size];
+        disk.read(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+
+        // Ending on the last sector.
+        let start = DISK_SECTORS - 40;
+        let data = pattern(40 * size, 1);
+        disk.write(start, &data).unwrap();
+        disk.flush().unwrap();
+
+        let mut back = vec![0u8; data.len()];
+        disk.read(start, &mut back).unwrap();
+        assert!(back == data);
+
+        // A buffer that doesn't start on a page still ends up in the right place.
+        let mut unaligned = vec![0u8; 3 * size + 100];
+        disk.read(start + 1, &mut unaligned[100..]).unwrap();
+        assert_eq!(unaligned[100..], data[size..4 * size]);
+    }
+
+    pub fn large_transfers_are_batched() {
+        let disk = disk();
+        // More requests than fit in the queue at once, so it takes a few batches.
+        let data = pattern(3 * 1024 * 1024 / 2, 3);
+        disk.write(0, &data).unwrap();
+
+        let mut back = vec![0u8; data.len()];
+        disk.read(0, &mut back).unwrap();
+        assert!(back == data);
+    }
+
+    pub fn bad_requests_are_rejected() {
+        let disk = disk();
+        let size = disk.sector_size();
+        let mut buf = vec![0u8; size];
+        assert_eq!(
+            disk.read(DISK_SECTORS, &mut buf),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            disk.write(DISK_SECTORS - 1, &vec![0u8; 2 * size]),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            disk.read(0, &mut buf[..size - 1]),
+            Err(BlockError::BadLength)
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Makes and checks Lateral filesystem images on the host.
+//!
+//! ```text
+//! lfs mkfs <image> [size]   writes an empty filesystem, first resizing the image to `size`
+//!                           bytes (with a K, M or G suffix) if given
+//! lfs fsck <image>          checks the filesystem, listing every problem found
+//! lfs ls <image> [path]     lists everything in the filesystem, or under the directory at
+//!                           `path`, like `apps: carter`
+//! ```
+//!
+//! It runs on the host, and is one of the workspace's default members; `make mkfs`, `make fsck`
+//! and `make ls` run it on `target/disk.img`.
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::process::ExitCode;
+
+use lateralfs::check::check;
+use lateralfs::{Disk, Filesystem, FsError, Kind, Path, Section, BLOCK_SIZE};
+
+const USAGE: &str = "usage: lfs mkfs <image> [size] | lfs fsck <image> | lfs ls <image> [path]";
+
+/// A disk image file.
+struct Image(File);
+
+impl Disk for Image {
+    fn block_count(&self) -> u64 {
+        self.0
+            .metadata()
+            .map_or(0, |metadata| metadata.len() / BLOCK_SIZE as u64)
+    }
+
+    fn read(&self, start: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let offset = start.checked_mul(BLOCK_SIZE as u64).ok_or(FsError::Io)?;
+        self.0.read_exact_at(buf, offset).map_err(|_| FsError::Io)
+    }
+
+    fn write(&self, start: u64, buf: &[u8]) -> Result<(), FsError> {
+        let offset = start.checked_mul(BLOCK_SIZE as u64).ok_or(FsError::Io)?;
+        self.0.write_all_at(buf, offset).map_err(|_| FsError::Io)
+    }
+}
+
+/// Bytes in `size`, like `64M`.
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, unit) = match size.char_indices().last()? {
+        (index, 'K' | 'k') => (&size[..index], 1 << 10),
+        (index, 'M' | 'm') => (&size[..index], 1 << 20),
+        (index, 'G' | 'g') => (&size[..index], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn open(path: &str, create: bool) -> Result<Image, String> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(create)
+        .truncate(false)
+        .open(path)
+        .map(Image)
+        .map_err(|error| format!("{}: {}", path, error))
+}
+
+fn mkfs(path: &str, size: Option<&str>) -> Result<(), String> {
+    let image = open(path, size.is_some())?;
+    if let Some(size) = size {
+        let bytes = parse_size(size).ok_or_else(|| format!("bad size {:?}", size))?;
+        image
+            .0
+            .set_len(bytes)
+            .map_err(|error| format!("{}: {}", path, error))?;
+    }
+    let fs = Filesystem::format(image).map_err(|error| format!("{}: {}", path, error))?;
+    let superblock = fs.superblock();
+    println!(
+        "{}: {} blocks of {} bytes, {} free",
+        path, superblock.block_count, BLOCK_SIZE, superblock.free_blocks
+    );
+    Ok(())
+}
+
+fn fsck(path: &str) -> Result<bool, String> {
+    let fs =
+        Filesystem::mount(open(path, false)?).map_err(|error| format!("{}: {}", path, error))?;
+    let problems = check(&fs);
+    for problem in &problems {
+        println!("{}: {}", path, problem);
+    }
+    if problems.is_empty() {
+        let superblock = fs.superblock();
+        println!(
+            "{}: clean, {} of {} blocks free",
+            path, superblock.free_blocks, superblock.block_count
+        );
+    }
+    Ok(problems.is_empty())
+}
+
+/// Prints the directory at `path` and everything under it, a line each.
+fn list(fs: &Filesystem<Image>, path: &Path) -> Result<(), FsError> {
+    let walk = fs.walk(path)?;
+    println!("{}", path);
+    for found in walk {
+        let (path, entry) = found?;
+        match entry.kind {
+            Kind::File => println!("{} ({} bytes)", path, entry.extent.size),
+            Kind::Directory => println!("{}/", path),
+        }
+    }
+    Ok(())
+}
+
+fn ls(image: &str, path: Option<&str>) -> Result<(), String> {
+    let fs =
+        Filesystem::mount(open(image, false)?).map_err(|error| format!("{}: {}", image, error))?;
+    let paths = match path {
+        Some(path) => vec![Path::parse(path).map_err(|error| format!("{}: {}", path, error))?],
+        None => Section::ALL.into_iter().map(Path::root).collect(),
+    };
+    for path in paths {
+        list(&fs, &path).map_err(|error| format!("{}: {}", path, error))?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["mkfs", path] => mkfs(path, None).map(|()| true),
+        ["mkfs", path, size] => mkfs(path, Some(size)).map(|()| true),
+        ["fsck", path] => fsck(path),
+        ["ls", image] => ls(image, None).map(|()| true),
+        ["ls", image, path] => ls(image, Some(path)).map(|()| true),
+        _ => Err(USAGE.into()),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("lfs: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}